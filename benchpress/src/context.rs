@@ -0,0 +1,281 @@
+use benchpress_sys::declaration::{context_shape, Shape};
+use benchpress_sys::Options;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned};
+use syn::ext::IdentExt;
+use syn::{Data, DeriveInput, Fields, Ident, LitStr};
+
+/// the `#[template(...)]` attribute of a context
+struct TemplateAttr {
+    path: LitStr,
+    strict: bool,
+}
+
+/// parse the `#[template(path = "...", strict)]` attribute, if there is one
+fn template_attr(input: &DeriveInput) -> syn::Result<Option<TemplateAttr>> {
+    let attr = match input
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("template"))
+    {
+        Some(attr) => attr,
+        None => return Ok(None),
+    };
+
+    let mut path = None;
+    let mut strict = false;
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("path") {
+            path = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("strict") {
+            strict = true;
+            Ok(())
+        } else {
+            Err(meta.error("expected `path` or `strict`"))
+        }
+    })?;
+
+    match path {
+        Some(path) => Ok(Some(TemplateAttr { path, strict })),
+        None => Err(syn::Error::new_spanned(attr, "missing `path = \"...\"`")),
+    }
+}
+
+/// a Rust identifier for a property used in the template
+fn field_ident(name: &str, span: Span) -> syn::Result<Ident> {
+    if syn::parse_str::<Ident>(name).is_ok() {
+        return Ok(Ident::new(name, span));
+    }
+
+    // keywords can be used as raw identifiers, except for a few
+    match syn::parse_str::<Ident>(&format!("r#{}", name)) {
+        Ok(_) => Ok(Ident::new_raw(name, span)),
+        Err(_) => Err(syn::Error::new(
+            span,
+            format!("the template uses `{}`, which can't be a field name", name),
+        )),
+    }
+}
+
+/// statements checking the properties used on `value` exist,
+/// and that it can be iterated if it is
+fn checks(value: TokenStream2, shape: &Shape, span: Span) -> syn::Result<Vec<TokenStream2>> {
+    let mut output = Vec::new();
+
+    if let Some(element) = &shape.element {
+        let element_value = quote_spanned!(span=> ::benchpress_sys::context::element(#value));
+        output.push(quote_spanned!(span=> let _ = #element_value;));
+        output.extend(checks(element_value, element, span)?);
+    }
+
+    // `length` is a property of strings and arrays in JS
+    for (name, field) in shape.fields.iter().filter(|(name, _)| name != "length") {
+        let ident = field_ident(name, span)?;
+        let field_value = quote_spanned!(span=> &::benchpress_sys::context::fields(#value).#ident);
+        output.push(quote_spanned!(span=> let _ = #field_value;));
+        output.extend(checks(field_value, field, span)?);
+    }
+
+    Ok(output)
+}
+
+/// implement `ToJson` and `HasFields` for a struct,
+/// and `Template` if it has a `#[template(...)]` attribute
+pub fn derive(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => fields
+                .named
+                .iter()
+                .filter_map(|field| field.ident.clone())
+                .collect::<Vec<Ident>>(),
+            Fields::Unit => Vec::new(),
+            Fields::Unnamed(_) => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "`BenchpressContext` needs named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "`BenchpressContext` can only be derived for structs",
+            ))
+        }
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let keys = fields.iter().map(|field| field.unraw().to_string());
+
+    let mut output = quote! {
+        impl #impl_generics ::benchpress_sys::context::ToJson for #name #ty_generics #where_clause {
+            fn to_json(&self) -> ::benchpress_sys::json::JsonValue {
+                let mut object = ::benchpress_sys::json::object::Object::new();
+                #(
+                    object.insert(#keys, ::benchpress_sys::context::ToJson::to_json(&self.#fields));
+                )*
+
+                ::benchpress_sys::json::JsonValue::Object(object)
+            }
+        }
+
+        impl #impl_generics ::benchpress_sys::context::HasFields for #name #ty_generics #where_clause {
+            type Fields = Self;
+        }
+    };
+
+    let attr = match template_attr(&input)? {
+        Some(attr) => attr,
+        None => return Ok(output),
+    };
+
+    let options = Options {
+        strict: attr.strict,
+        name: Some(attr.path.value()),
+        ..Options::default()
+    };
+    let (paths, tree) = crate::load(&attr.path, &options)?;
+
+    // properties of the context are checked here for a clearer error,
+    // and deeper ones by accessing them in a function which is never called
+    let shape = context_shape(&tree);
+    let span = attr.path.span();
+    let mut statements = Vec::new();
+    for (field, field_shape) in &shape.fields {
+        if !fields.iter().any(|ident| ident.unraw() == field) {
+            return Err(syn::Error::new(
+                span,
+                format!(
+                    "`{}` is used by {} but isn't a field of `{}`",
+                    field,
+                    attr.path.value(),
+                    name
+                ),
+            ));
+        }
+
+        let ident = field_ident(field, span)?;
+        let value = quote_spanned!(span=> &context.#ident);
+        statements.extend(checks(value, field_shape, span)?);
+    }
+
+    let render = crate::render_fn(tree);
+    output.extend(quote! {
+        const _: () = {
+            #[allow(dead_code, unreachable_code, clippy::all)]
+            fn check #impl_generics (context: &#name #ty_generics) #where_clause {
+                #(#statements)*
+            }
+        };
+
+        impl #impl_generics ::benchpress_sys::context::Template for #name #ty_generics #where_clause {
+            fn render_with(
+                &self,
+                helpers: &::benchpress_sys::helpers::HelperRegistry,
+                out: &mut String,
+            ) {
+                // rebuild when the template or anything it imports changes
+                #(const _: &[u8] = include_bytes!(#paths);)*
+
+                let render: fn(
+                    &::benchpress_sys::json::JsonValue,
+                    &::benchpress_sys::helpers::HelperRegistry,
+                    &mut String,
+                ) = #render;
+                render(&::benchpress_sys::context::ToJson::to_json(self), helpers, out);
+            }
+        }
+    });
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn derive_str(input: TokenStream2) -> String {
+        derive(syn::parse2(input).unwrap())
+            .unwrap_or_else(|err| err.to_compile_error())
+            .to_string()
+    }
+
+    #[test]
+    fn without_template() {
+        let output = derive_str(quote! {
+            struct Post {
+                r#type: String,
+                body: String,
+            }
+        });
+
+        assert!(output.contains("object . insert (\"type\""));
+        assert!(output.contains("type Fields = Self"));
+        assert!(!output.contains("Template"));
+    }
+
+    #[test]
+    fn checks_paths() {
+        let output = derive_str(quote! {
+            #[template(path = "tests/templates/post.tpl")]
+            struct Page {
+                title: String,
+                posts: Vec<Post>,
+            }
+        });
+
+        assert!(output.contains("context :: element (& context . posts)"));
+        assert!(output.contains(
+            "& :: benchpress_sys :: context :: fields (:: benchpress_sys :: context :: element (& context . posts)) . body"
+        ));
+        assert!(output.contains("Template for Page"));
+    }
+
+    #[test]
+    fn missing_field() {
+        let output = derive_str(quote! {
+            #[template(path = "tests/templates/post.tpl")]
+            struct Page {
+                titel: String,
+                posts: Vec<Post>,
+            }
+        });
+
+        assert!(output
+            .contains("`title` is used by tests/templates/post.tpl but isn't a field of `Page`"));
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert!(derive_str(quote!(
+            enum Page {}
+        ))
+        .contains("can only be derived for structs"));
+        assert!(derive_str(quote!(
+            struct Page(String);
+        ))
+        .contains("needs named fields"));
+        assert!(derive_str(quote! {
+            #[template(strict)]
+            struct Page {}
+        })
+        .contains("missing `path"));
+        assert!(derive_str(quote! {
+            #[template(path = "a.tpl", fast)]
+            struct Page {}
+        })
+        .contains("expected `path` or `strict`"));
+    }
+
+    #[test]
+    fn field_idents() {
+        let span = Span::call_site();
+        assert_eq!(field_ident("title", span).unwrap().to_string(), "title");
+        assert_eq!(field_ident("type", span).unwrap().to_string(), "r#type");
+        assert!(field_ident("my-name", span).is_err());
+        assert!(field_ident("self", span).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+//! compile templates while compiling Rust,
+//! so broken templates fail `cargo build`
+//!
+//! the expanded code uses `benchpress_sys`, which must also be a dependency
+
+mod context;
+
+use benchpress_sys::imports::{FileResolver, TemplateResolver};
+use benchpress_sys::parser::Control;
+use benchpress_sys::{generator, parse_with_resolver, report, rust_generator, Options};
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::{Ident, LitStr, Token};
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// arguments to `template!`
+struct TemplateInput {
+    /// path to the template, relative to the crate root
+    path: LitStr,
+    /// expand to a Rust render function instead of JS
+    render: bool,
+    /// report unbalanced blocks as errors
+    strict: bool,
+}
+
+impl Parse for TemplateInput {
+    fn parse(input: ParseStream) -> syn::Result<TemplateInput> {
+        let path = input.parse()?;
+        let mut render = false;
+        let mut strict = false;
+
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+
+            let flag: Ident = input.parse()?;
+            match flag.to_string().as_str() {
+                "render" => render = true,
+                "strict" => strict = true,
+                _ => {
+                    return Err(syn::Error::new(
+                        flag.span(),
+                        "expected `render` or `strict`",
+                    ))
+                }
+            }
+        }
+
+        Ok(TemplateInput {
+            path,
+            render,
+            strict,
+        })
+    }
+}
+
+/// resolves templates from files like `FileResolver`,
+/// remembering the files read so they can be tracked for rebuilds
+struct TrackedResolver {
+    files: FileResolver,
+    read: RefCell<Vec<String>>,
+}
+
+impl TemplateResolver for TrackedResolver {
+    fn resolve(&self, path: &str) -> Result<String, String> {
+        let source = self.files.resolve(path)?;
+        let file = self.files.file(path)?.to_string_lossy().into_owned();
+
+        let mut read = self.read.borrow_mut();
+        if !read.contains(&file) {
+            read.push(file);
+        }
+
+        Ok(source)
+    }
+}
+
+/// read and parse a template relative to the crate root,
+/// importing templates relative to its directory,
+/// returning the absolute paths of every file read and its controls
+/// or an error with the rendered diagnostics
+fn load(path: &LitStr, options: &Options) -> syn::Result<(Vec<String>, Vec<Control>)> {
+    let name = path.value();
+    let root = env::var("CARGO_MANIFEST_DIR").unwrap_or_else(|_| ".".to_string());
+    let full = Path::new(&root).join(&name);
+
+    let template = fs::read_to_string(&full).map_err(|err| {
+        syn::Error::new(
+            path.span(),
+            format!("failed to read {}: {}", full.display(), err),
+        )
+    })?;
+
+    let resolver = TrackedResolver {
+        files: FileResolver::new(full.parent().unwrap_or(&full)),
+        read: RefCell::new(vec![full.to_string_lossy().into_owned()]),
+    };
+    let (tree, diagnostics) = parse_with_resolver(&template, options, &resolver);
+    if diagnostics.has_errors() {
+        let report = diagnostics
+            .errors()
+            .map(|diagnostic| report::render(diagnostic, &template, &name, false))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        return Err(syn::Error::new(path.span(), report));
+    }
+
+    Ok((resolver.read.into_inner(), tree))
+}
+
+/// a Rust function rendering a template
+fn render_fn(tree: Vec<Control>) -> TokenStream2 {
+    let module = rust_generator::generate(tree)
+        .parse::<TokenStream2>()
+        .expect("generated Rust code should be valid tokens");
+
+    quote!({ #module render })
+}
+
+/// compile the template, or report why it couldn't be
+fn expand(input: TemplateInput) -> syn::Result<TokenStream2> {
+    let options = Options {
+        strict: input.strict,
+        name: Some(input.path.value()),
+        ..Options::default()
+    };
+    let (paths, tree) = load(&input.path, &options)?;
+
+    let code = if input.render {
+        render_fn(tree)
+    } else {
+        let code = generator::generate_with(tree, &options);
+        quote!(#code)
+    };
+
+    // rebuild when the template or anything it imports changes
+    Ok(quote!({
+        #(const _: &[u8] = include_bytes!(#paths);)*
+        #code
+    }))
+}
+
+/// compile a template at build time
+///
+/// the path is relative to the crate root,
+/// and errors in the template are reported as compile errors
+///
+/// `{{{ import }}}` and `{{{ extends }}}` paths are relative to
+/// the directory of the template
+///
+/// ```ignore
+/// // the generated JS module, as a `&'static str`
+/// const POST: &str = benchpress::template!("templates/post.tpl");
+///
+/// // a Rust function rendering the template,
+/// // `fn(&JsonValue, &HelperRegistry, &mut String)`
+/// let render_post = benchpress::template!("templates/post.tpl", render);
+/// render_post(&ctx, &helpers, &mut out);
+/// ```
+///
+/// add `strict` to also reject unbalanced blocks
+#[proc_macro]
+pub fn template(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as TemplateInput);
+
+    expand(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// render a struct with a template, checking the template at build time
+///
+/// implements `ToJson` and `HasFields` from `benchpress_sys::context`,
+/// so the struct can be used within another context,
+/// and `Template` if there's a `#[template(path = "...")]` attribute
+///
+/// every property the template uses has to be a field,
+/// and fields used with `{{{ each }}}` have to be iterable,
+/// so typos fail the build instead of rendering as empty strings
+///
+/// properties are checked all the way down, so values with properties
+/// have to be structs deriving `BenchpressContext` too
+/// maps and `JsonValue`s can be iterated and rendered,
+/// but their properties can't be used directly, like `{settings.theme}`
+///
+/// ```ignore
+/// #[derive(BenchpressContext)]
+/// #[template(path = "templates/page.tpl")]
+/// struct Page {
+///     title: String,
+///     posts: Vec<Post>,
+/// }
+///
+/// #[derive(BenchpressContext)]
+/// struct Post {
+///     body: String,
+/// }
+///
+/// let html = page.render();
+/// ```
+#[proc_macro_derive(BenchpressContext, attributes(template))]
+pub fn derive_context(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+
+    context::derive(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// a template is checked against every struct it uses
+///
+/// ```
+/// use benchpress::BenchpressContext;
+///
+/// #[derive(BenchpressContext)]
+/// #[template(path = "tests/templates/post.tpl")]
+/// struct Page {
+///     title: String,
+///     posts: Vec<Post>,
+/// }
+///
+/// #[derive(BenchpressContext)]
+/// struct Post {
+///     body: String,
+/// }
+/// ```
+///
+/// so a typo in the field of a nested struct fails the build
+///
+/// ```compile_fail
+/// use benchpress::BenchpressContext;
+///
+/// #[derive(BenchpressContext)]
+/// #[template(path = "tests/templates/post.tpl")]
+/// struct Page {
+///     title: String,
+///     posts: Vec<Post>,
+/// }
+///
+/// #[derive(BenchpressContext)]
+/// struct Post {
+///     bdy: String,
+/// }
+/// ```
+///
+/// as does iterating a field which can't be iterated
+///
+/// ```compile_fail
+/// use benchpress::BenchpressContext;
+///
+/// #[derive(BenchpressContext)]
+/// #[template(path = "tests/templates/post.tpl")]
+/// struct Page {
+///     title: String,
+///     posts: String,
+/// }
+/// ```
+///
+/// or using the properties of a map
+///
+/// ```compile_fail
+/// use benchpress::BenchpressContext;
+/// use std::collections::HashMap;
+///
+/// #[derive(BenchpressContext)]
+/// #[template(path = "tests/templates/post.tpl")]
+/// struct Page {
+///     title: String,
+///     posts: Vec<HashMap<String, String>>,
+/// }
+/// ```
+#[cfg(doctest)]
+struct CompileFail;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_str(input: TokenStream2) -> String {
+        expand(syn::parse2(input).unwrap())
+            .unwrap_or_else(|err| err.to_compile_error())
+            .to_string()
+    }
+
+    #[test]
+    fn parse_flags() {
+        let input: TemplateInput = syn::parse2(quote!("a.tpl", render, strict,)).unwrap();
+        assert_eq!(input.path.value(), "a.tpl");
+        assert!(input.render && input.strict);
+
+        assert!(syn::parse2::<TemplateInput>(quote!("a.tpl", fast)).is_err());
+        assert!(syn::parse2::<TemplateInput>(quote!(a)).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let output = expand_str(quote!("tests/templates/broken.tpl"));
+        assert!(output.starts_with(":: core :: compile_error !"));
+        assert!(output.contains("error[B0001]"));
+        assert!(output.contains("tests/templates/broken.tpl:2:6"));
+
+        let output = expand_str(quote!("tests/templates/missing.tpl"));
+        assert!(output.contains("failed to read"));
+    }
+
+    #[test]
+    fn strict() {
+        let lenient = expand_str(quote!("tests/templates/unclosed.tpl"));
+        assert!(!lenient.contains("compile_error"));
+
+        let strict = expand_str(quote!("tests/templates/unclosed.tpl", strict));
+        assert!(strict.contains("error[B0007]"));
+        assert!(strict.contains("tests/templates/unclosed.tpl:1:1"));
+    }
+
+    #[test]
+    fn imports() {
+        let output = expand_str(quote!("tests/templates/article.tpl"));
+        assert!(!output.contains("compile_error"));
+        assert!(output.contains("tests/templates/article.tpl\")"));
+        // imported templates are tracked for rebuilds too
+        assert!(output.contains("tests/templates/byline.tpl\")"));
+    }
+}
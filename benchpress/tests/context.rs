@@ -0,0 +1,110 @@
+use benchpress::BenchpressContext;
+use benchpress_sys::context::{Template, ToJson};
+use benchpress_sys::helpers::HelperRegistry;
+use benchpress_sys::json::JsonValue;
+use benchpress_sys::render::render;
+use std::collections::BTreeMap;
+
+#[derive(BenchpressContext)]
+#[template(path = "tests/templates/post.tpl")]
+struct Page {
+    title: String,
+    posts: Vec<Post>,
+    // not used by the template
+    draft: bool,
+}
+
+#[derive(BenchpressContext)]
+struct Post {
+    body: Option<String>,
+}
+
+#[test]
+fn to_json() {
+    let page = Page {
+        title: "Posts".to_string(),
+        posts: vec![Post { body: None }],
+        draft: true,
+    };
+
+    assert_eq!(
+        page.to_json().dump(),
+        r#"{"title":"Posts","posts":[{"body":null}],"draft":true}"#
+    );
+}
+
+#[test]
+fn render_struct() {
+    let (tree, _) =
+        benchpress_sys::parse_with(include_str!("templates/post.tpl"), &Default::default());
+
+    for page in [
+        Page {
+            title: "a & b".to_string(),
+            posts: vec![
+                Post {
+                    body: Some("<p>".to_string()),
+                },
+                Post { body: None },
+            ],
+            draft: false,
+        },
+        Page {
+            title: String::new(),
+            posts: Vec::new(),
+            draft: false,
+        },
+    ] {
+        let expected = render(&tree, &page.to_json());
+        assert_eq!(page.render(), expected);
+
+        let mut out = String::new();
+        page.render_with(&HelperRegistry::new(), &mut out);
+        assert_eq!(out, expected);
+    }
+}
+
+#[derive(BenchpressContext)]
+#[template(path = "tests/templates/nested.tpl")]
+struct Nested<'a> {
+    user: Option<Box<User<'a>>>,
+    groups: BTreeMap<String, Group<'a>>,
+    extra: JsonValue,
+}
+
+#[derive(BenchpressContext)]
+struct Group<'a> {
+    members: Vec<&'a User<'a>>,
+}
+
+#[derive(BenchpressContext)]
+struct User<'a> {
+    name: &'a str,
+    r#type: u8,
+}
+
+#[test]
+fn nested() {
+    let alice = User {
+        name: "alice",
+        r#type: 1,
+    };
+    let mut groups = BTreeMap::new();
+    groups.insert(
+        "admins".to_string(),
+        Group {
+            members: vec![&alice],
+        },
+    );
+
+    let context = Nested {
+        user: Some(Box::new(User {
+            name: "bob",
+            r#type: 2,
+        })),
+        groups,
+        extra: JsonValue::from("x"),
+    };
+
+    assert_eq!(context.render(), "bob 2 | admins: alice(1) | x");
+}
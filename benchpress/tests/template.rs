@@ -0,0 +1,89 @@
+use benchpress::template;
+use benchpress_sys::helpers::HelperRegistry;
+use benchpress_sys::imports::FileResolver;
+use benchpress_sys::json::{self, JsonValue};
+use benchpress_sys::render::render;
+use benchpress_sys::Options;
+
+static POST: &str = include_str!("templates/post.tpl");
+
+#[test]
+fn javascript() {
+    let code: &str = template!("tests/templates/post.tpl");
+
+    assert_eq!(code, benchpress_sys::compile(POST));
+}
+
+#[test]
+fn render_fn() {
+    let render_post: fn(&JsonValue, &HelperRegistry, &mut String) =
+        template!("tests/templates/post.tpl", render);
+    let helpers = HelperRegistry::new();
+    let (tree, _) = benchpress_sys::parse_with(POST, &Default::default());
+
+    for data in &[
+        r#"{ "title": "Posts", "posts": [{ "body": "a < b" }, { "body": 2 }] }"#,
+        r#"{ "title": "None", "posts": [] }"#,
+        "{}",
+    ] {
+        let data = json::parse(data).unwrap();
+        let mut out = String::new();
+        render_post(&data, &helpers, &mut out);
+
+        assert_eq!(out, render(&tree, &data));
+    }
+}
+
+#[test]
+fn imports() {
+    let source = include_str!("templates/article.tpl");
+    let resolver = FileResolver::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/templates"));
+    let options = Options {
+        name: Some("tests/templates/article.tpl".to_string()),
+        ..Options::default()
+    };
+
+    let code: &str = template!("tests/templates/article.tpl");
+    assert_eq!(
+        code,
+        benchpress_sys::compile_with_resolver(source, &options, &resolver).code
+    );
+
+    let render_article: fn(&JsonValue, &HelperRegistry, &mut String) =
+        template!("tests/templates/article.tpl", render);
+    let mut out = String::new();
+    render_article(
+        &json::parse(r#"{ "author": "a", "body": "b" }"#).unwrap(),
+        &HelperRegistry::new(),
+        &mut out,
+    );
+    assert_eq!(
+        out,
+        "<article>\n<p class=\"byline\">by a</p>\n\nb\n</article>\n"
+    );
+}
+
+#[test]
+fn operators() {
+    // generated code refers to the crate by its absolute path
+    #[allow(dead_code)]
+    mod benchpress_sys {}
+
+    let render_count: fn(&JsonValue, &HelperRegistry, &mut String) =
+        template!("tests/templates/count.tpl", render);
+
+    for &(data, expected) in &[
+        (r#"{ "count": 3, "title": "post" }"#, "3 posts\n"),
+        (r#"{ "count": 1, "title": "post" }"#, "one\n"),
+        (r#"{ "count": 3, "title": "" }"#, "one\n"),
+    ] {
+        let mut out = String::new();
+        render_count(
+            &json::parse(data).unwrap(),
+            &HelperRegistry::new(),
+            &mut out,
+        );
+
+        assert_eq!(out, expected);
+    }
+}
@@ -0,0 +1,4 @@
+<article>
+{{{ import "byline" }}}
+{body}
+</article>
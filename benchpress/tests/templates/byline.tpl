@@ -0,0 +1 @@
+<p class="byline">by {author}</p>
@@ -0,0 +1 @@
+{{{ if count > "1" && title != "" }}}{count} {title}s{{{ else }}}one{{{ end }}}
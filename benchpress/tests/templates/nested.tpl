@@ -0,0 +1 @@
+{user.name} {user.type} |{{{ each groups }}} {@key}:{{{ each groups.members }}} {groups.members.name}({groups.members.type}){{{ end }}}{{{ end }}} | {extra}
\ No newline at end of file
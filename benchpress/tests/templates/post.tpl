@@ -0,0 +1,6 @@
+<h1>{title}</h1>
+{{{ each posts }}}
+<p class="{{{ if @first }}}first{{{ end }}}">{posts.body}</p>
+{{{ else }}}
+no posts
+{{{ end }}}
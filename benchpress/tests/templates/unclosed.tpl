@@ -0,0 +1 @@
+{{{ if a }}}b
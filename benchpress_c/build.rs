@@ -0,0 +1,23 @@
+use std::env;
+use std::path::Path;
+
+fn main() {
+    let root = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let config = cbindgen::Config::from_file(Path::new(&root).join("cbindgen.toml"))
+        .expect("cbindgen.toml should be valid");
+
+    let bindings =
+        cbindgen::generate_with_config(&root, config).expect("failed to generate the C header");
+    bindings.write_to_file(Path::new(&out_dir).join("benchpress.h"));
+
+    // the checked in header is only rewritten when asked,
+    // since the sources may be read-only
+    if env::var_os("BENCHPRESS_UPDATE_HEADER").is_some() {
+        bindings.write_to_file(Path::new(&root).join("include/benchpress.h"));
+    }
+
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=BENCHPRESS_UPDATE_HEADER");
+}
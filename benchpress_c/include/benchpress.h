@@ -0,0 +1,164 @@
+#ifndef BENCHPRESS_H
+#define BENCHPRESS_H
+
+/* generated by cbindgen from benchpress_c, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * the template compiled without errors
+ */
+#define BP_OK 0
+
+/**
+ * the template has errors, the code is still written
+ */
+#define BP_TEMPLATE_ERROR 1
+
+/**
+ * a required pointer was null, or an option was out of range
+ */
+#define BP_INVALID_ARGUMENT -1
+
+/**
+ * the template or name isn't valid UTF-8
+ */
+#define BP_INVALID_UTF8 -2
+
+/**
+ * compiling panicked, which is a bug in benchpress
+ */
+#define BP_PANIC -3
+
+/**
+ * pass malformed tags through as text, with a warning
+ */
+#define BP_MALFORMED_TEXT 0
+
+/**
+ * remove malformed tags from the output, with a warning
+ */
+#define BP_MALFORMED_DROP 1
+
+/**
+ * pass malformed tags through as text, with an error
+ */
+#define BP_MALFORMED_ERROR 2
+
+/**
+ * a factory supporting both CommonJS and AMD
+ */
+#define BP_FORMAT_UMD 0
+
+/**
+ * an ES module
+ */
+#define BP_FORMAT_ESM 1
+
+/**
+ * a CommonJS module
+ */
+#define BP_FORMAT_COMMONJS 2
+
+/**
+ * an AMD module
+ */
+#define BP_FORMAT_AMD 3
+
+/**
+ * a script registering the template in `window.templates`
+ */
+#define BP_FORMAT_IIFE 4
+
+/**
+ * an expression evaluating to the template function
+ */
+#define BP_FORMAT_FUNCTION 5
+
+/**
+ * `var`, function expressions and string concatenation
+ */
+#define BP_TARGET_ES5 0
+
+/**
+ * `const`, arrow functions, template literals and dotted property access
+ */
+#define BP_TARGET_ES2015 1
+
+/**
+ * options for `bp_compile`, zeroed for the defaults except for `size`
+ *
+ * fields are only ever added at the end, so callers built against
+ * an older header keep working
+ */
+typedef struct {
+  /**
+   * `sizeof(bp_options)` in the header the caller was built with
+   */
+  size_t size;
+  /**
+   * report unbalanced and mismatched blocks as errors
+   */
+  bool strict;
+  /**
+   * one of the `BP_MALFORMED_*` policies
+   */
+  uint32_t malformed;
+  /**
+   * one of the `BP_FORMAT_*` module formats
+   */
+  uint32_t format;
+  /**
+   * one of the `BP_TARGET_*` language levels
+   */
+  uint32_t target;
+  /**
+   * remove layout whitespace and shorten local names
+   */
+  bool minify;
+  /**
+   * NUL-terminated name of the template, or null
+   */
+  const char *name;
+} bp_options;
+
+/**
+ * compile a template of `len` bytes at `src`
+ *
+ * `src` may only be null if `len` is 0, and `options` may be null for the defaults,
+ * otherwise its `size` must be set, and `BP_INVALID_ARGUMENT` is returned if it's
+ * larger than the `bp_options` this library knows
+ *
+ * on success or `BP_TEMPLATE_ERROR`, `*out` is set to the NUL-terminated code
+ * and `*out_len` (if not null) to its length in bytes, without the NUL
+ *
+ * if `err` isn't null, `*err` is set to a JSON array of diagnostics,
+ * even if there are none
+ *
+ * strings written to `out` and `err` must be freed with `bp_free`
+ *
+ * # Safety
+ *
+ * the pointers must be null or valid for their use above
+ */
+int32_t bp_compile(const char *src,
+                   size_t len,
+                   const bp_options *options,
+                   char **out,
+                   size_t *out_len,
+                   char **err);
+
+/**
+ * free a string returned by `bp_compile`, doing nothing if it's null
+ *
+ * # Safety
+ *
+ * `ptr` must be null or returned by `bp_compile`, and not already freed
+ */
+void bp_free(char *ptr);
+
+#endif  /* BENCHPRESS_H */
@@ -0,0 +1,261 @@
+//! compile templates from C and other languages with a C FFI
+//!
+//! the header is checked in as `include/benchpress.h`,
+//! and regenerated by building with `BENCHPRESS_UPDATE_HEADER=1`
+
+use benchpress_sys::{compile_with, report, MalformedPolicy, Options, OutputFormat, Target};
+
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::str;
+
+/// the template compiled without errors
+pub const BP_OK: i32 = 0;
+/// the template has errors, the code is still written
+pub const BP_TEMPLATE_ERROR: i32 = 1;
+/// a required pointer was null, or an option was out of range
+pub const BP_INVALID_ARGUMENT: i32 = -1;
+/// the template or name isn't valid UTF-8
+pub const BP_INVALID_UTF8: i32 = -2;
+/// compiling panicked, which is a bug in benchpress
+pub const BP_PANIC: i32 = -3;
+
+/// pass malformed tags through as text, with a warning
+pub const BP_MALFORMED_TEXT: u32 = 0;
+/// remove malformed tags from the output, with a warning
+pub const BP_MALFORMED_DROP: u32 = 1;
+/// pass malformed tags through as text, with an error
+pub const BP_MALFORMED_ERROR: u32 = 2;
+
+/// a factory supporting both CommonJS and AMD
+pub const BP_FORMAT_UMD: u32 = 0;
+/// an ES module
+pub const BP_FORMAT_ESM: u32 = 1;
+/// a CommonJS module
+pub const BP_FORMAT_COMMONJS: u32 = 2;
+/// an AMD module
+pub const BP_FORMAT_AMD: u32 = 3;
+/// a script registering the template in `window.templates`
+pub const BP_FORMAT_IIFE: u32 = 4;
+/// an expression evaluating to the template function
+pub const BP_FORMAT_FUNCTION: u32 = 5;
+
+/// `var`, function expressions and string concatenation
+pub const BP_TARGET_ES5: u32 = 0;
+/// `const`, arrow functions, template literals and dotted property access
+pub const BP_TARGET_ES2015: u32 = 1;
+
+/// options for `bp_compile`, zeroed for the defaults except for `size`
+///
+/// fields are only ever added at the end, so callers built against
+/// an older header keep working
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub struct bp_options {
+    /// `sizeof(bp_options)` in the header the caller was built with
+    pub size: usize,
+    /// report unbalanced and mismatched blocks as errors
+    pub strict: bool,
+    /// one of the `BP_MALFORMED_*` policies
+    pub malformed: u32,
+    /// one of the `BP_FORMAT_*` module formats
+    pub format: u32,
+    /// one of the `BP_TARGET_*` language levels
+    pub target: u32,
+    /// remove layout whitespace and shorten local names
+    pub minify: bool,
+    /// NUL-terminated name of the template, or null
+    pub name: *const c_char,
+}
+
+/// the offset and size of each field of `bp_options`
+const OPTIONS_FIELDS: &[(usize, usize)] = &[
+    (mem::offset_of!(bp_options, size), mem::size_of::<usize>()),
+    (mem::offset_of!(bp_options, strict), mem::size_of::<bool>()),
+    (
+        mem::offset_of!(bp_options, malformed),
+        mem::size_of::<u32>(),
+    ),
+    (mem::offset_of!(bp_options, format), mem::size_of::<u32>()),
+    (mem::offset_of!(bp_options, target), mem::size_of::<u32>()),
+    (mem::offset_of!(bp_options, minify), mem::size_of::<bool>()),
+    (
+        mem::offset_of!(bp_options, name),
+        mem::size_of::<*const c_char>(),
+    ),
+];
+
+/// copy the options the caller knows about, leaving any newer fields zeroed
+/// return the error code if `size` is out of range or ends partway through a field,
+/// rather than after one or in the padding after it
+unsafe fn read_options(options: *const bp_options) -> Result<bp_options, i32> {
+    let size = (*options).size;
+    if size < mem::size_of::<usize>()
+        || size > mem::size_of::<bp_options>()
+        || OPTIONS_FIELDS
+            .iter()
+            .any(|&(offset, len)| offset < size && size < offset + len)
+    {
+        return Err(BP_INVALID_ARGUMENT);
+    }
+
+    // all zeroes are valid for every field, and mean the defaults
+    let mut copy: bp_options = mem::zeroed();
+    ptr::copy_nonoverlapping(
+        options as *const u8,
+        &mut copy as *mut bp_options as *mut u8,
+        size,
+    );
+
+    Ok(copy)
+}
+
+/// the compiler options for C options, or the error code if they're invalid
+unsafe fn options(options: &bp_options) -> Result<Options, i32> {
+    let malformed = match options.malformed {
+        BP_MALFORMED_TEXT => MalformedPolicy::Text,
+        BP_MALFORMED_DROP => MalformedPolicy::Drop,
+        BP_MALFORMED_ERROR => MalformedPolicy::Error,
+        _ => return Err(BP_INVALID_ARGUMENT),
+    };
+    let format = match options.format {
+        BP_FORMAT_UMD => OutputFormat::Umd,
+        BP_FORMAT_ESM => OutputFormat::Esm,
+        BP_FORMAT_COMMONJS => OutputFormat::CommonJs,
+        BP_FORMAT_AMD => OutputFormat::Amd,
+        BP_FORMAT_IIFE => OutputFormat::Iife,
+        BP_FORMAT_FUNCTION => OutputFormat::Function,
+        _ => return Err(BP_INVALID_ARGUMENT),
+    };
+    let target = match options.target {
+        BP_TARGET_ES5 => Target::Es5,
+        BP_TARGET_ES2015 => Target::Es2015,
+        _ => return Err(BP_INVALID_ARGUMENT),
+    };
+    let name = if options.name.is_null() {
+        None
+    } else {
+        let name = CStr::from_ptr(options.name)
+            .to_str()
+            .map_err(|_| BP_INVALID_UTF8)?;
+        Some(name.to_string())
+    };
+
+    Ok(Options {
+        strict: options.strict,
+        malformed,
+        format,
+        target,
+        minify: options.minify,
+        name,
+        ..Options::default()
+    })
+}
+
+/// copy a string into memory C can hold onto until `bp_free`
+///
+/// the length is stored before the returned pointer so it can be freed,
+/// and a NUL after the string, which may itself contain NULs
+fn allocate(string: &str) -> *mut c_char {
+    let header = mem::size_of::<usize>();
+    let mut bytes = Vec::with_capacity(header + string.len() + 1);
+    bytes.extend_from_slice(&(string.len() + 1).to_ne_bytes());
+    bytes.extend_from_slice(string.as_bytes());
+    bytes.push(0);
+
+    let bytes = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+    unsafe { bytes.add(header) as *mut c_char }
+}
+
+/// compile a template of `len` bytes at `src`
+///
+/// `src` may only be null if `len` is 0, and `options` may be null for the defaults,
+/// otherwise its `size` must be set, and `BP_INVALID_ARGUMENT` is returned if it's
+/// larger than the `bp_options` this library knows
+///
+/// on success or `BP_TEMPLATE_ERROR`, `*out` is set to the NUL-terminated code
+/// and `*out_len` (if not null) to its length in bytes, without the NUL
+///
+/// if `err` isn't null, `*err` is set to a JSON array of diagnostics,
+/// even if there are none
+///
+/// strings written to `out` and `err` must be freed with `bp_free`
+///
+/// # Safety
+///
+/// the pointers must be null or valid for their use above
+#[no_mangle]
+pub unsafe extern "C" fn bp_compile(
+    src: *const c_char,
+    len: usize,
+    options: *const bp_options,
+    out: *mut *mut c_char,
+    out_len: *mut usize,
+    err: *mut *mut c_char,
+) -> i32 {
+    if out.is_null() || (src.is_null() && len > 0) {
+        return BP_INVALID_ARGUMENT;
+    }
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let template = if len == 0 {
+            ""
+        } else {
+            match str::from_utf8(slice::from_raw_parts(src as *const u8, len)) {
+                Ok(template) => template,
+                Err(_) => return BP_INVALID_UTF8,
+            }
+        };
+        let options = if options.is_null() {
+            Options::default()
+        } else {
+            match read_options(options).and_then(|options| self::options(&options)) {
+                Ok(options) => options,
+                Err(code) => return code,
+            }
+        };
+
+        let output = compile_with(template, &options);
+
+        *out = allocate(&output.code);
+        if !out_len.is_null() {
+            *out_len = output.code.len();
+        }
+        if !err.is_null() {
+            *err = allocate(&report::json(&output.diagnostics, template));
+        }
+
+        if output.diagnostics.has_errors() {
+            BP_TEMPLATE_ERROR
+        } else {
+            BP_OK
+        }
+    }));
+
+    result.unwrap_or(BP_PANIC)
+}
+
+/// free a string returned by `bp_compile`, doing nothing if it's null
+///
+/// # Safety
+///
+/// `ptr` must be null or returned by `bp_compile`, and not already freed
+#[no_mangle]
+pub unsafe extern "C" fn bp_free(ptr: *mut c_char) {
+    if ptr.is_null() {
+        return;
+    }
+
+    let header = mem::size_of::<usize>();
+    let start = (ptr as *mut u8).sub(header);
+    let len = ptr::read_unaligned(start as *const usize);
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+        start,
+        header + len,
+    )));
+}
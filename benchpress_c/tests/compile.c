@@ -0,0 +1,36 @@
+#include <stdio.h>
+#include <string.h>
+
+#include "benchpress.h"
+
+int main(void) {
+  const char *src = "{{{ each posts }}}{posts.title}{{{ end }}}";
+  char *out = NULL;
+  size_t out_len = 0;
+  char *err = NULL;
+
+  int status = bp_compile(src, strlen(src), NULL, &out, &out_len, &err);
+  if (status != BP_OK) {
+    fprintf(stderr, "bp_compile returned %d\n", status);
+    return 1;
+  }
+  if (strcmp(err, "[]") != 0) {
+    fprintf(stderr, "unexpected diagnostics: %s\n", err);
+    return 1;
+  }
+
+  fwrite(out, 1, out_len, stdout);
+  bp_free(out);
+  bp_free(err);
+
+  bp_options options = {0};
+  options.size = sizeof(options);
+  options.target = 9;
+  if (bp_compile(src, strlen(src), &options, &out, NULL, NULL) !=
+      BP_INVALID_ARGUMENT) {
+    fprintf(stderr, "expected an invalid argument\n");
+    return 1;
+  }
+
+  return 0;
+}
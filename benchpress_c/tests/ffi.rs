@@ -0,0 +1,296 @@
+use benchpress_c::{
+    bp_options, BP_FORMAT_ESM, BP_INVALID_ARGUMENT, BP_INVALID_UTF8, BP_MALFORMED_ERROR, BP_OK,
+    BP_TEMPLATE_ERROR,
+};
+use benchpress_sys::{compile_with, json, Options, OutputFormat};
+
+use std::env;
+use std::ffi::CStr;
+use std::fs;
+use std::mem;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::process::Command;
+use std::ptr;
+
+// call the functions through the C ABI rather than as Rust functions
+extern "C" {
+    fn bp_compile(
+        src: *const c_char,
+        len: usize,
+        options: *const bp_options,
+        out: *mut *mut c_char,
+        out_len: *mut usize,
+        err: *mut *mut c_char,
+    ) -> i32;
+    fn bp_free(ptr: *mut c_char);
+}
+
+fn defaults() -> bp_options {
+    bp_options {
+        size: mem::size_of::<bp_options>(),
+        strict: false,
+        malformed: 0,
+        format: 0,
+        target: 0,
+        minify: false,
+        name: ptr::null(),
+    }
+}
+
+/// compile through the C ABI, returning the status, code and diagnostics
+fn compile(template: &[u8], options: Option<&bp_options>) -> (i32, Option<String>, Option<String>) {
+    let mut out = ptr::null_mut();
+    let mut out_len = 0;
+    let mut err = ptr::null_mut();
+
+    unsafe {
+        let status = bp_compile(
+            template.as_ptr() as *const c_char,
+            template.len(),
+            options.map_or(ptr::null(), |options| options as *const bp_options),
+            &mut out,
+            &mut out_len,
+            &mut err,
+        );
+
+        let take = |ptr: *mut c_char| {
+            if ptr.is_null() {
+                return None;
+            }
+            let string = CStr::from_ptr(ptr).to_str().unwrap().to_string();
+            bp_free(ptr);
+            Some(string)
+        };
+        let code = take(out);
+        if let Some(code) = &code {
+            assert_eq!(code.len(), out_len);
+        }
+
+        (status, code, take(err))
+    }
+}
+
+#[test]
+fn compiles() {
+    let template = "{{{ each posts }}}{posts.title}{{{ end }}}";
+    let (status, code, err) = compile(template.as_bytes(), None);
+
+    assert_eq!(status, BP_OK);
+    assert_eq!(code.unwrap(), benchpress_sys::compile(template));
+    assert_eq!(err.unwrap(), "[]");
+}
+
+#[test]
+fn options() {
+    let template = "<!-- BEGIN posts -->{posts.title}<!-- END -->";
+    let name = b"posts\0";
+    let options = bp_options {
+        format: BP_FORMAT_ESM,
+        minify: true,
+        name: name.as_ptr() as *const c_char,
+        ..defaults()
+    };
+    let (status, code, _) = compile(template.as_bytes(), Some(&options));
+
+    let expected = compile_with(
+        template,
+        &Options {
+            format: OutputFormat::Esm,
+            minify: true,
+            name: Some("posts".to_string()),
+            ..Options::default()
+        },
+    );
+    assert_eq!(status, BP_OK);
+    assert_eq!(code.unwrap(), expected.code);
+}
+
+#[test]
+fn older_options() {
+    let template = "{a}";
+    // a caller built before `format` was added doesn't know to set it
+    let options = bp_options {
+        size: mem::offset_of!(bp_options, format),
+        format: BP_FORMAT_ESM,
+        ..defaults()
+    };
+    let (status, code, _) = compile(template.as_bytes(), Some(&options));
+
+    assert_eq!(status, BP_OK);
+    assert_eq!(code.unwrap(), benchpress_sys::compile(template));
+
+    // its size includes the padding after the last field it knows
+    let name = b"a\0";
+    let options = bp_options {
+        size: mem::offset_of!(bp_options, name),
+        name: name.as_ptr() as *const c_char,
+        ..defaults()
+    };
+    let (status, code, _) = compile(template.as_bytes(), Some(&options));
+
+    assert_eq!(status, BP_OK);
+    assert_eq!(code.unwrap(), benchpress_sys::compile(template));
+}
+
+#[test]
+fn diagnostics() {
+    let options = bp_options {
+        malformed: BP_MALFORMED_ERROR,
+        ..defaults()
+    };
+    let (status, code, err) = compile(b"a {{{ if }}} b", Some(&options));
+
+    assert_eq!(status, BP_TEMPLATE_ERROR);
+    assert!(code.is_some());
+
+    let diagnostics = json::parse(&err.unwrap()).unwrap();
+    assert_eq!(diagnostics[0]["severity"], "error");
+    assert_eq!(diagnostics[0]["span"]["column"], 3);
+}
+
+#[test]
+fn invalid_arguments() {
+    let options = bp_options {
+        target: 7,
+        ..defaults()
+    };
+    assert_eq!(compile(b"a", Some(&options)).0, BP_INVALID_ARGUMENT);
+    assert_eq!(compile(b"\xff", None).0, BP_INVALID_UTF8);
+
+    // options from a newer header than this library knows, without a size,
+    // or ending partway through a field
+    let name = mem::offset_of!(bp_options, name);
+    let format = mem::offset_of!(bp_options, format);
+    for &size in &[
+        0,
+        mem::size_of::<bp_options>() + 8,
+        name + 1,
+        name + mem::size_of::<*const c_char>() - 1,
+        format + 2,
+    ] {
+        let options = bp_options { size, ..defaults() };
+        assert_eq!(compile(b"a", Some(&options)).0, BP_INVALID_ARGUMENT);
+    }
+
+    unsafe {
+        let mut out = ptr::null_mut();
+        assert_eq!(
+            bp_compile(
+                ptr::null(),
+                1,
+                ptr::null(),
+                &mut out,
+                ptr::null_mut(),
+                ptr::null_mut()
+            ),
+            BP_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            bp_compile(
+                b"a".as_ptr() as *const c_char,
+                1,
+                ptr::null(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut()
+            ),
+            BP_INVALID_ARGUMENT
+        );
+
+        // an empty template may be null, and only `out` is required
+        assert_eq!(
+            bp_compile(
+                ptr::null(),
+                0,
+                ptr::null(),
+                &mut out,
+                ptr::null_mut(),
+                ptr::null_mut()
+            ),
+            BP_OK
+        );
+        assert!(!out.is_null());
+        bp_free(out);
+        bp_free(ptr::null_mut());
+    }
+}
+
+#[test]
+fn interior_nul() {
+    let template = "a\0{b}";
+    let mut out = ptr::null_mut();
+    let mut out_len = 0;
+
+    unsafe {
+        let status = bp_compile(
+            template.as_ptr() as *const c_char,
+            template.len(),
+            ptr::null(),
+            &mut out,
+            &mut out_len,
+            ptr::null_mut(),
+        );
+        assert_eq!(status, BP_OK);
+
+        let code = std::slice::from_raw_parts(out as *const u8, out_len);
+        assert_eq!(code, benchpress_sys::compile(template).as_bytes());
+        assert_eq!(*out.add(out_len), 0);
+        bp_free(out);
+    }
+}
+
+#[test]
+fn header_up_to_date() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let generated = Path::new(env!("OUT_DIR")).join("benchpress.h");
+
+    assert!(
+        fs::read_to_string(root.join("include/benchpress.h")).unwrap()
+            == fs::read_to_string(generated).unwrap(),
+        "include/benchpress.h is out of date, rebuild with BENCHPRESS_UPDATE_HEADER=1"
+    );
+}
+
+/// compile and run `tests/compile.c` against the header and the shared library
+#[cfg(unix)]
+#[test]
+fn c_program() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    // the shared library is built into `deps` along with the test binary
+    let exe = env::current_exe().unwrap();
+    let lib_dir = exe.parent().unwrap();
+    let program = Path::new(env!("CARGO_TARGET_TMPDIR")).join("benchpress_c_compile");
+
+    let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg(root.join("tests/compile.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg("-L")
+        .arg(lib_dir)
+        .arg("-lbenchpress_c")
+        .arg("-o")
+        .arg(&program)
+        .status();
+    let status = match status {
+        Ok(status) => status,
+        // no C compiler to test with
+        Err(_) => return,
+    };
+    assert!(status.success(), "failed to compile tests/compile.c");
+
+    let output = Command::new(&program)
+        .env("LD_LIBRARY_PATH", lib_dir)
+        .env("DYLD_LIBRARY_PATH", lib_dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        benchpress_sys::compile("{{{ each posts }}}{posts.title}{{{ end }}}")
+    );
+}
@@ -0,0 +1,75 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use benchpress_sys::{
+    compile_with, parse_with, rust_generator, try_compile, MalformedPolicy, Options, Target,
+};
+use benchpress_sys_fuzz::{check_balanced, check_diagnostics};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(template) = std::str::from_utf8(data) {
+        let lenient = compile_with(template, &Options::default());
+        check_balanced(&lenient.code);
+        check_diagnostics(template, &lenient.diagnostics);
+
+        // `try_compile` fails exactly when there are errors
+        assert_eq!(
+            try_compile(template).is_err(),
+            lenient.diagnostics.has_errors()
+        );
+
+        let strict = compile_with(
+            template,
+            &Options {
+                strict: true,
+                malformed: MalformedPolicy::Drop,
+                ..Options::default()
+            },
+        );
+        check_balanced(&strict.code);
+        check_diagnostics(template, &strict.diagnostics);
+
+        // mapping and declarations don't change the generated code
+        let mapped = compile_with(
+            template,
+            &Options {
+                source_map: true,
+                declaration: true,
+                ..Options::default()
+            },
+        );
+        assert_eq!(mapped.code, lenient.code);
+        assert!(mapped.source_map.is_some());
+        assert!(mapped.declaration.is_some());
+
+        let modern = compile_with(
+            template,
+            &Options {
+                target: Target::Es2015,
+                ..Options::default()
+            },
+        );
+        check_balanced(&modern.code);
+
+        let minified = compile_with(
+            template,
+            &Options {
+                minify: true,
+                ..Options::default()
+            },
+        );
+        check_balanced(&minified.code);
+        let minified_mapped = compile_with(
+            template,
+            &Options {
+                minify: true,
+                source_map: true,
+                ..Options::default()
+            },
+        );
+        assert_eq!(minified_mapped.code, minified.code);
+
+        let (tree, _) = parse_with(template, &Options::default());
+        check_balanced(&rust_generator::generate(tree));
+    }
+});
@@ -0,0 +1,16 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use benchpress_sys::diagnostics::Diagnostics;
+use benchpress_sys::{lexer, MalformedPolicy};
+use benchpress_sys_fuzz::{check_diagnostics, check_tokens};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(template) = std::str::from_utf8(data) {
+        let mut diagnostics = Diagnostics::new();
+        let tokens = lexer::lex(template, MalformedPolicy::Text, &mut diagnostics);
+
+        check_tokens(template, &tokens);
+        check_diagnostics(template, &diagnostics);
+    }
+});
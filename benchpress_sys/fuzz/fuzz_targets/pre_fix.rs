@@ -0,0 +1,23 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use benchpress_sys::diagnostics::Diagnostics;
+use benchpress_sys::error::Span;
+use benchpress_sys::pre_fixer;
+use benchpress_sys_fuzz::{check_diagnostics, check_span};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(template) = std::str::from_utf8(data) {
+        let mut diagnostics = Diagnostics::new();
+        let (fixed, map) = pre_fixer::pre_fix(template, &mut diagnostics);
+
+        // every position in the output maps back into the template
+        for offset in 0..=fixed.len() {
+            if fixed.is_char_boundary(offset) {
+                assert!(map.offset(offset) <= template.len());
+            }
+        }
+        check_span(template, map.span(Span::new(0, fixed.len())));
+        check_diagnostics(template, &diagnostics);
+    }
+});
@@ -0,0 +1,151 @@
+#![no_main]
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+use benchpress_sys::diagnostics::{Diagnostics, Severity};
+use benchpress_sys::token::{Token, TokenPos};
+use benchpress_sys::{generator, parser, MalformedPolicy};
+use benchpress_sys_fuzz::{check_balanced, check_diagnostics};
+
+/// a token the lexer could produce
+#[derive(Arbitrary, Debug)]
+enum FuzzToken {
+    Text(String),
+    Comment(String),
+    Identifier(String),
+    StringLiteral(String),
+    LegacyHelper,
+    BlockOpen,
+    BlockClose,
+    If,
+    Else,
+    Iter,
+    End,
+    Bang,
+    LeftParen,
+    RightParen,
+    Comma,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+    RawOpen,
+    RawClose,
+    EscapedOpen,
+    EscapedClose,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    strict: bool,
+    drop: bool,
+    tokens: Vec<FuzzToken>,
+}
+
+fn is_simple_char(ch: char) -> bool {
+    ch.is_alphanumeric() || matches!(ch, '@' | '/' | '_' | ':' | '\\' | '-' | '.')
+}
+
+impl FuzzToken {
+    /// the token along with source text it could have been lexed from
+    fn into_token(self) -> (Token, String) {
+        match self {
+            FuzzToken::Text(value) => (Token::Text(value.clone()), value),
+            FuzzToken::Comment(value) => {
+                let value: String = value.chars().filter(|&ch| ch != '}').collect();
+                let source = format!("{{{{{{!{}}}}}}}", value);
+
+                (Token::Comment(value), source)
+            }
+            FuzzToken::Identifier(name) => {
+                let mut name: String = name.chars().filter(|&ch| is_simple_char(ch)).collect();
+                if name.is_empty() {
+                    name.push('a');
+                }
+
+                (Token::Identifier(name.clone()), name)
+            }
+            FuzzToken::StringLiteral(value) => {
+                let value: String = value
+                    .chars()
+                    .filter(|&ch| ch != '"' && ch != '\\')
+                    .collect();
+                let source = format!("\"{}\"", value);
+
+                (Token::StringLiteral(value), source)
+            }
+            FuzzToken::LegacyHelper => (Token::LegacyHelper, "function.".to_string()),
+            FuzzToken::BlockOpen => (Token::BlockOpen, "{{{".to_string()),
+            FuzzToken::BlockClose => (Token::BlockClose, "}}}".to_string()),
+            FuzzToken::If => (Token::If, "if".to_string()),
+            FuzzToken::Else => (Token::Else, "else".to_string()),
+            FuzzToken::Iter => (Token::Iter, "each".to_string()),
+            FuzzToken::End => (Token::End, "end".to_string()),
+            FuzzToken::Bang => (Token::Bang, "!".to_string()),
+            FuzzToken::LeftParen => (Token::LeftParen, "(".to_string()),
+            FuzzToken::RightParen => (Token::RightParen, ")".to_string()),
+            FuzzToken::Comma => (Token::Comma, ",".to_string()),
+            FuzzToken::Equal => (Token::Equal, "==".to_string()),
+            FuzzToken::NotEqual => (Token::NotEqual, "!=".to_string()),
+            FuzzToken::Less => (Token::Less, "<".to_string()),
+            FuzzToken::LessEqual => (Token::LessEqual, "<=".to_string()),
+            FuzzToken::Greater => (Token::Greater, ">".to_string()),
+            FuzzToken::GreaterEqual => (Token::GreaterEqual, ">=".to_string()),
+            FuzzToken::And => (Token::And, "&&".to_string()),
+            FuzzToken::Or => (Token::Or, "||".to_string()),
+            FuzzToken::RawOpen => (Token::RawOpen, "{{".to_string()),
+            FuzzToken::RawClose => (Token::RawClose, "}}".to_string()),
+            FuzzToken::EscapedOpen => (Token::EscapedOpen, "{".to_string()),
+            FuzzToken::EscapedClose => (Token::EscapedClose, "}".to_string()),
+        }
+    }
+}
+
+fuzz_target!(|input: Input| {
+    // lay the tokens out in a source string separated by spaces
+    let mut source = String::new();
+    let mut tokens = Vec::with_capacity(input.tokens.len());
+    for token in input.tokens {
+        let (tok, text) = token.into_token();
+        let start = source.len();
+        source.push_str(&text);
+
+        tokens.push(TokenPos {
+            start,
+            end: source.len(),
+            tok,
+        });
+        source.push(' ');
+    }
+
+    let malformed = if input.drop {
+        MalformedPolicy::Drop
+    } else {
+        MalformedPolicy::Text
+    };
+
+    let mut diagnostics = Diagnostics::new();
+    let instructions = parser::parse_instructions(&source, tokens, &mut diagnostics);
+    let instructions = if input.strict {
+        parser::check_blocks(&source, &instructions, Severity::Error, &mut diagnostics);
+        instructions
+    } else {
+        parser::fix_extra_instructions(&source, instructions, &mut diagnostics)
+    };
+    let instructions = parser::limit_depth(&source, instructions, &mut diagnostics);
+    let (tree, _) = parser::parse_tree(
+        &source,
+        &mut instructions.into_iter(),
+        &[],
+        1,
+        malformed,
+        &mut diagnostics,
+    );
+
+    check_balanced(&generator::generate(tree));
+    check_diagnostics(&source, &diagnostics);
+});
@@ -0,0 +1,154 @@
+//! invariants shared by the fuzz targets
+
+use benchpress_sys::diagnostics::Diagnostics;
+use benchpress_sys::error::Span;
+use benchpress_sys::report;
+use benchpress_sys::token::TokenPos;
+
+/// check that a span is in bounds and on char boundaries
+pub fn check_span(source: &str, span: Span) {
+    assert!(span.start <= span.end, "backwards span {:?}", span);
+    assert!(span.end <= source.len(), "span {:?} out of bounds", span);
+    assert!(
+        source.is_char_boundary(span.start) && source.is_char_boundary(span.end),
+        "span {:?} not on char boundaries",
+        span
+    );
+}
+
+/// check that token spans are in bounds, on char boundaries,
+/// in order and don't overlap
+pub fn check_tokens(source: &str, tokens: &[TokenPos]) {
+    let mut last = 0;
+
+    for token in tokens {
+        check_span(source, Span::new(token.start, token.end));
+        assert!(
+            token.start >= last,
+            "{:?} overlaps the previous token",
+            token
+        );
+
+        last = token.end;
+    }
+}
+
+/// check that diagnostics point within the template and can be rendered
+pub fn check_diagnostics(source: &str, diagnostics: &Diagnostics) {
+    for diagnostic in diagnostics {
+        check_span(source, diagnostic.span);
+        for label in &diagnostic.labels {
+            check_span(source, label.span);
+        }
+
+        report::render(diagnostic, source, "fuzz", false);
+    }
+}
+
+/// skip the rest of a string literal opened by `quote`
+fn skip_string<I: Iterator<Item = char>>(chars: &mut I, quote: char) {
+    loop {
+        match chars.next() {
+            Some('\\') => {
+                chars.next();
+            }
+            Some(ch) if ch == quote => return,
+            Some('\n') | Some('\r') | None => panic!("unterminated string literal"),
+            Some(_) => (),
+        }
+    }
+}
+
+/// skip the rest of a template literal chunk
+/// return true if it ended in a `${` substitution
+fn skip_template<I: Iterator<Item = char>>(chars: &mut std::iter::Peekable<I>) -> bool {
+    loop {
+        match chars.next() {
+            Some('\\') => {
+                chars.next();
+            }
+            Some('`') => return false,
+            Some('$') if chars.peek() == Some(&'{') => {
+                chars.next();
+                return true;
+            }
+            None => panic!("unterminated template literal"),
+            Some(_) => (),
+        }
+    }
+}
+
+/// skip a template literal chunk, expecting the end of any substitution
+fn open_template<I: Iterator<Item = char>>(
+    chars: &mut std::iter::Peekable<I>,
+    stack: &mut Vec<char>,
+) {
+    if skip_template(chars) {
+        stack.push('`');
+        stack.push('}');
+    }
+}
+
+/// check that generated JS has balanced brackets
+/// and terminated string and template literals
+pub fn check_balanced(code: &str) {
+    // closers expected, with '`' marking a template substitution
+    let mut stack: Vec<char> = Vec::new();
+    let mut chars = code.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' | '\'' => skip_string(&mut chars, ch),
+            '`' => open_template(&mut chars, &mut stack),
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|&ch| ch != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(ch) => last = ch,
+                        None => panic!("unterminated comment"),
+                    }
+                }
+            }
+            '(' => stack.push(')'),
+            '[' => stack.push(']'),
+            '{' => stack.push('}'),
+            ')' | ']' | '}' => {
+                assert_eq!(stack.pop(), Some(ch), "unbalanced `{}` in\n{}", ch, code);
+
+                // continue a template literal after a substitution
+                if ch == '}' && stack.last() == Some(&'`') {
+                    stack.pop();
+                    open_template(&mut chars, &mut stack);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    assert!(stack.is_empty(), "unclosed {:?} in\n{}", stack, code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balanced() {
+        check_balanced("a(b[c], { d: '}' }, \"\\\"(\", `${e({})}`) // (");
+    }
+
+    #[test]
+    #[should_panic]
+    fn unbalanced() {
+        check_balanced("a(b]");
+    }
+
+    #[test]
+    #[should_panic]
+    fn multi_line_string() {
+        check_balanced("a(\"b\nc\")");
+    }
+}
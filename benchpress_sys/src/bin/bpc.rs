@@ -1,10 +1,14 @@
 extern crate benchpress_sys;
 
-use benchpress_sys::{generator, lexer, parser, pre_fixer};
+use benchpress_sys::imports::{FileResolver, NoResolver, TemplateResolver};
+use benchpress_sys::{
+    declaration, generator, parser, report, source_map, ImportMode, MalformedPolicy, Options,
+    OutputFormat, Stages, Target,
+};
 use parser::Control;
 
 use std::fs::File;
-use std::io::{self, BufRead, Read, Write};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
 
 fn tree_tostring(tree: Vec<Control>) -> String {
     let mut output = String::new();
@@ -12,10 +16,24 @@ fn tree_tostring(tree: Vec<Control>) -> String {
     for elem in tree {
         output.push_str(
             match elem {
-                Control::If { subject, body, alt } => format!(
-                    "If {{ subject: {:?}, body: {}, alt: {} }},",
+                Control::If {
+                    subject,
+                    body,
+                    else_ifs,
+                    alt,
+                    ..
+                } => format!(
+                    "If {{ subject: {:?}, body: {}, else_ifs: [{}], alt: {} }},",
                     subject,
                     tree_tostring(body),
+                    else_ifs
+                        .into_iter()
+                        .map(|branch| format!(
+                            "{{ subject: {:?}, body: {} }},",
+                            branch.subject,
+                            tree_tostring(branch.body)
+                        ))
+                        .collect::<String>(),
                     tree_tostring(alt)
                 ),
                 Control::Iter {
@@ -24,6 +42,7 @@ fn tree_tostring(tree: Vec<Control>) -> String {
                     subject,
                     body,
                     alt,
+                    ..
                 } => format!(
                     "Iter {{ suffix: {}, raw: {}, subject: {:?}, body: {}, alt: {} }},",
                     suffix,
@@ -53,19 +72,58 @@ fn tree_tostring(tree: Vec<Control>) -> String {
     }
 }
 
-fn go(input: &str, debug: bool) {
-    let pre_fixed = pre_fixer::pre_fix(input);
-    let lexed = lexer::lex(&pre_fixed);
-    let first_parsed = parser::parse_instructions(&pre_fixed, lexed.clone());
-    let extras_fixed = parser::fix_extra_instructions(&pre_fixed, first_parsed.clone());
-    let (tree, _) = parser::parse_tree(
-        &pre_fixed,
-        &mut extras_fixed.clone().into_iter(),
-        &Vec::new(),
-        1,
-    );
+fn go(
+    input: &str,
+    name: &str,
+    options: &Options,
+    resolver: &dyn TemplateResolver,
+    map_path: Option<&str>,
+    declaration_path: Option<&str>,
+    debug: bool,
+) -> bool {
+    let (
+        Stages {
+            pre_fixed,
+            offset_map,
+            lexed,
+            first_parsed,
+            instructions,
+            tree,
+        },
+        diagnostics,
+    ) = benchpress_sys::parse_stages(input, options, resolver);
+
+    let color = io::stderr().is_terminal();
+    for diagnostic in &diagnostics {
+        eprintln!("{}", report::render(diagnostic, input, name, color));
+    }
+
+    if let Some(path) = declaration_path {
+        let declaration = declaration::declaration(&tree);
+        if let Err(err) =
+            File::create(path).and_then(|mut file| file.write_all(declaration.as_bytes()))
+        {
+            eprintln!("failed to write declaration to {}: {}", path, err);
+        }
+    }
 
-    let code = generator::generate(tree.clone());
+    let code = match map_path {
+        Some(path) => {
+            let (code, mut mappings) = generator::generate_with_mappings(tree.clone(), options);
+            for mapping in &mut mappings {
+                mapping.span = mapping.span.map(|span| offset_map.span(span));
+            }
+
+            let map = source_map::source_map(name, input, &mappings);
+            if let Err(err) = File::create(path).and_then(|mut file| file.write_all(map.as_bytes()))
+            {
+                eprintln!("failed to write source map to {}: {}", path, err);
+            }
+
+            code
+        }
+        None => generator::generate_with(tree.clone(), options),
+    };
 
     if debug {
         println!("/*");
@@ -73,13 +131,32 @@ fn go(input: &str, debug: bool) {
         println!("pre fixed   \n-------------\n{}\n\n", pre_fixed);
         println!("lexed       \n-------------\n{:?}\n\n", lexed);
         println!("first parsed\n-------------\n{:?}\n\n", first_parsed);
-        println!("extras fixed\n-------------\n{:?}\n\n", extras_fixed);
+        println!("extras fixed\n-------------\n{:?}\n\n", instructions);
         println!("parse tree  \n-------------\n{}\n\n", tree_tostring(tree));
 
         println!("code        \n-------------*/");
     }
 
     println!("{}", code);
+
+    !diagnostics.has_errors()
+}
+
+/// the value of `flag` parsed by `from_name`, or the default if it isn't given
+/// exits listing the valid `names` if the value isn't one of them
+fn named<T: Default>(flag: &str, from_name: fn(&str) -> Option<T>, names: &[&str]) -> T {
+    match std::env::args().skip_while(|x| x != flag).nth(1) {
+        Some(name) => from_name(&name).unwrap_or_else(|| {
+            eprintln!(
+                "unknown value `{}` for {}, expected one of: {}",
+                name,
+                flag,
+                names.join(", ")
+            );
+            std::process::exit(2);
+        }),
+        None => T::default(),
+    }
 }
 
 fn main() -> io::Result<()> {
@@ -88,12 +165,45 @@ fn main() -> io::Result<()> {
     let stdin = io::stdin();
 
     let debug = std::env::args().any(|x| x == "--debug");
+    let options = Options {
+        strict: std::env::args().any(|x| x == "--strict"),
+        minify: std::env::args().any(|x| x == "--minify"),
+        malformed: named(
+            "--malformed",
+            MalformedPolicy::from_name,
+            MalformedPolicy::NAMES,
+        ),
+        format: named("--format", OutputFormat::from_name, OutputFormat::NAMES),
+        target: named("--target", Target::from_name, Target::NAMES),
+        name: std::env::args().skip_while(|x| x != "--name").nth(1),
+        imports: named("--imports", ImportMode::from_name, ImportMode::NAMES),
+        ..Options::default()
+    };
+    let resolver: Box<dyn TemplateResolver> =
+        match std::env::args().skip_while(|x| x != "--partials").nth(1) {
+            Some(dir) => Box::new(FileResolver::new(dir)),
+            None => Box::new(NoResolver),
+        };
+    let map_path = std::env::args().skip_while(|x| x != "--source-map").nth(1);
+    let map_path = map_path.as_deref();
+    let declaration_path = std::env::args().skip_while(|x| x != "--declaration").nth(1);
+    let declaration_path = declaration_path.as_deref();
 
     if std::env::args().any(|x| x == "-") {
         let mut passed = String::new();
         stdin.lock().read_to_string(&mut passed)?;
 
-        go(&passed, debug);
+        if !go(
+            &passed,
+            "<stdin>",
+            &options,
+            resolver.as_ref(),
+            map_path,
+            declaration_path,
+            debug,
+        ) {
+            std::process::exit(1);
+        }
     } else if let Some(filepath) = {
         let mut args = std::env::args();
         if args.any(|x| x == "-i") {
@@ -102,11 +212,21 @@ fn main() -> io::Result<()> {
             None
         }
     } {
-        let mut file = File::open(filepath)?;
+        let mut file = File::open(&filepath)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
 
-        go(&contents, debug);
+        if !go(
+            &contents,
+            &filepath,
+            &options,
+            resolver.as_ref(),
+            map_path,
+            declaration_path,
+            debug,
+        ) {
+            std::process::exit(1);
+        }
     } else {
         loop {
             // Stdout needs to be flushed, due to missing newline
@@ -116,7 +236,15 @@ fn main() -> io::Result<()> {
             let mut line = String::new();
             stdin.lock().read_line(&mut line)?;
 
-            go(&line, debug);
+            go(
+                &line,
+                "<stdin>",
+                &options,
+                resolver.as_ref(),
+                None,
+                None,
+                debug,
+            );
         }
     }
 
@@ -0,0 +1,334 @@
+//! compile a directory of templates to Rust modules from a build script
+//!
+//! ```ignore
+//! // build.rs
+//! benchpress_sys::build::compile_dir("templates", std::env::var("OUT_DIR").unwrap()).unwrap();
+//!
+//! // src/lib.rs
+//! mod templates {
+//!     include!(concat!(env!("OUT_DIR"), "/templates.rs"));
+//! }
+//!
+//! templates::admin::users::render(&ctx, &helpers, &mut out);
+//! ```
+
+use imports::FileResolver;
+use options::Options;
+use report;
+use rust_generator;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// extension of the template files compiled
+pub static EXTENSION: &str = "tpl";
+/// name of the file including every module, within the output directory
+pub static INDEX: &str = "templates.rs";
+
+static KEYWORDS: &[&str] = &[
+    "_", "abstract", "as", "async", "await", "become", "box", "break", "const", "continue",
+    "crate", "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if",
+    "impl", "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// an error compiling a directory of templates
+#[derive(Debug)]
+pub enum BuildError {
+    /// reading a template or writing a module failed
+    Io(io::Error),
+    /// a template has errors, with the rendered diagnostics
+    Template { path: PathBuf, report: String },
+    /// two templates would be generated as the same module
+    DuplicateModule {
+        module: String,
+        first: PathBuf,
+        second: PathBuf,
+    },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::Io(err) => err.fmt(f),
+            BuildError::Template { path, report } => {
+                write!(f, "failed to compile {}\n{}", path.display(), report)
+            }
+            BuildError::DuplicateModule {
+                module,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} and {} are both generated as the module `{}`",
+                first.display(),
+                second.display(),
+                module
+            ),
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+impl From<io::Error> for BuildError {
+    fn from(err: io::Error) -> BuildError {
+        BuildError::Io(err)
+    }
+}
+
+/// a Rust identifier for a file or directory name
+pub fn module_name(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '_' })
+        .collect();
+
+    if ident.is_empty() || ident.starts_with(|ch: char| ch.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    if KEYWORDS.contains(&ident.as_str()) {
+        ident.push('_');
+    }
+
+    ident
+}
+
+/// a module in the index,
+/// with the generated file for a template and any nested modules
+#[derive(Default)]
+struct Module {
+    source: Option<PathBuf>,
+    file: Option<PathBuf>,
+    children: BTreeMap<String, Module>,
+}
+
+impl Module {
+    /// write Rust code declaring the nested modules
+    fn index(&self, depth: usize, out: &mut String) {
+        let indent = "    ".repeat(depth);
+
+        for (i, (name, module)) in self.children.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+
+            out.push_str(&format!("{}pub mod {} {{\n", indent, name));
+            if let Some(file) = &module.file {
+                out.push_str(&format!(
+                    "{}    include!({:?});\n",
+                    indent,
+                    file.to_string_lossy()
+                ));
+                if !module.children.is_empty() {
+                    out.push('\n');
+                }
+            }
+            module.index(depth + 1, out);
+            out.push_str(&format!("{}}}\n", indent));
+        }
+    }
+}
+
+/// templates within a directory, recursively,
+/// sorted so modules are always generated in the same order
+fn templates(dir: &Path, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            templates(&path, found)?;
+        } else if path.extension().is_some_and(|ext| ext == EXTENSION) {
+            found.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// compile every template in `src` to a Rust module within `out_dir`,
+/// along with an index declaring them named `templates.rs`
+///
+/// modules are nested the same way as the directories,
+/// so `src/admin/users.tpl` renders with `templates::admin::users::render`,
+/// and imports are inlined from `src`, like `{{{import "admin/users"}}}`
+///
+/// meant to be called from a build script,
+/// so it prints instructions to rerun when templates change,
+/// and prints warnings for cargo to show
+pub fn compile_dir<P, Q>(src: P, out_dir: Q) -> Result<(), BuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    compile_dir_with(src, out_dir, &Options::default())
+}
+
+/// compile every template in `src` to a Rust module within `out_dir`
+/// with the given options
+pub fn compile_dir_with<P, Q>(src: P, out_dir: Q, options: &Options) -> Result<(), BuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let src = src.as_ref();
+    let out_dir = out_dir.as_ref();
+    println!("cargo:rerun-if-changed={}", src.display());
+
+    let mut found = Vec::new();
+    templates(src, &mut found)?;
+    // templates can import each other by their path within `src`
+    let resolver = FileResolver::new(src).extension(EXTENSION);
+
+    // generated files are kept apart from the index,
+    // so a template can't overwrite it
+    fs::create_dir_all(out_dir)?;
+    let modules_dir = fs::canonicalize(out_dir)?.join("templates");
+
+    let mut root = Module::default();
+    for path in found {
+        let relative = path.strip_prefix(src).unwrap_or(&path);
+        let name = relative.display().to_string();
+        let template = fs::read_to_string(&path)?;
+
+        let (tree, diagnostics) = ::parse_with_resolver(&template, options, &resolver);
+        if diagnostics.has_errors() {
+            return Err(BuildError::Template {
+                report: diagnostics
+                    .iter()
+                    .map(|diagnostic| report::render(diagnostic, &template, &name, false))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+                path,
+            });
+        }
+        for diagnostic in &diagnostics {
+            for line in report::render(diagnostic, &template, &name, false).lines() {
+                println!("cargo:warning={}", line);
+            }
+        }
+
+        let mut module = &mut root;
+        let mut file = modules_dir.clone();
+        let mut names = Vec::new();
+        for component in relative.with_extension("").iter() {
+            let component = module_name(&component.to_string_lossy());
+            file.push(&component);
+            names.push(component.clone());
+            module = module.children.entry(component).or_default();
+        }
+        file.set_extension("rs");
+
+        if let Some(first) = module.source.take() {
+            return Err(BuildError::DuplicateModule {
+                module: names.join("::"),
+                first,
+                second: path,
+            });
+        }
+
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&file, rust_generator::generate(tree))?;
+        module.source = Some(path);
+        module.file = Some(file);
+    }
+
+    let mut index = "// generated by benchpress, do not edit\n\n".to_string();
+    root.index(0, &mut index);
+    fs::write(out_dir.join(INDEX), index)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn module_name_test() {
+        assert_eq!(module_name("users"), "users");
+        assert_eq!(module_name("user-list.v2"), "user_list_v2");
+        assert_eq!(module_name("404"), "_404");
+        assert_eq!(module_name("match"), "match_");
+        assert_eq!(module_name("_"), "__");
+    }
+
+    #[test]
+    fn compile_dir_test() {
+        let root = env::temp_dir().join(format!("benchpress-build-{}", std::process::id()));
+        let src = root.join("src");
+        let out = root.join("out");
+        fs::create_dir_all(src.join("admin")).unwrap();
+        fs::write(
+            src.join("index.tpl"),
+            "{title}{{{ import \"admin/users\" }}}",
+        )
+        .unwrap();
+        fs::write(
+            src.join("admin/users.tpl"),
+            "{{{ each users }}}{users.name}{{{ end }}}",
+        )
+        .unwrap();
+        fs::write(src.join("admin/notes.txt"), "not a template").unwrap();
+
+        compile_dir(&src, &out).unwrap();
+
+        let modules = fs::canonicalize(&out).unwrap().join("templates");
+        assert_eq!(
+            fs::read_to_string(out.join(INDEX)).unwrap(),
+            format!(
+                "// generated by benchpress, do not edit
+
+pub mod admin {{
+    pub mod users {{
+        include!({:?});
+    }}
+}}
+
+pub mod index {{
+    include!({:?});
+}}
+",
+                modules.join("admin/users.rs").to_string_lossy(),
+                modules.join("index.rs").to_string_lossy()
+            )
+        );
+        let index = fs::read_to_string(modules.join("index.rs")).unwrap();
+        assert!(index.contains("pub fn render("));
+        // imports are inlined
+        assert!(index.contains(".iter(1,"));
+        assert!(!modules.join("admin/notes.rs").exists());
+
+        fs::write(src.join("broken.tpl"), "{{{ each }}}").unwrap();
+        match compile_dir(&src, &out) {
+            Err(BuildError::Template { path, report }) => {
+                assert_eq!(path, src.join("broken.tpl"));
+                assert!(report.contains("broken.tpl"));
+            }
+            other => panic!("expected a template error, got {:?}", other),
+        }
+
+        fs::remove_file(src.join("broken.tpl")).unwrap();
+        fs::write(src.join("admin-users.tpl"), "").unwrap();
+        assert!(compile_dir(&src, &out).is_ok());
+
+        fs::write(src.join("admin_users.tpl"), "").unwrap();
+        match compile_dir(&src, &out) {
+            Err(BuildError::DuplicateModule { module, .. }) => assert_eq!(module, "admin_users"),
+            other => panic!("expected a duplicate module, got {:?}", other),
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
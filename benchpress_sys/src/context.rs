@@ -0,0 +1,243 @@
+use helpers::HelperRegistry;
+use json::{self, JsonValue};
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// a Rust value which can be rendered by a template,
+/// converted to JSON the way it would be passed to the JS runtime
+pub trait ToJson {
+    fn to_json(&self) -> JsonValue;
+}
+
+macro_rules! to_json_from {
+    ($($t:ty),*) => {
+        $(
+            impl ToJson for $t {
+                fn to_json(&self) -> JsonValue {
+                    JsonValue::from(*self)
+                }
+            }
+        )*
+    };
+}
+
+to_json_from!(bool, i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+
+impl ToJson for char {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::from(self.to_string())
+    }
+}
+
+impl ToJson for str {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::from(self)
+    }
+}
+
+impl ToJson for String {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::from(self.as_str())
+    }
+}
+
+impl ToJson for JsonValue {
+    fn to_json(&self) -> JsonValue {
+        self.clone()
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> JsonValue {
+        match self {
+            Some(value) => value.to_json(),
+            None => JsonValue::Null,
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> JsonValue {
+        self.as_slice().to_json()
+    }
+}
+
+impl<K: AsRef<str>, V: ToJson> ToJson for HashMap<K, V> {
+    fn to_json(&self) -> JsonValue {
+        let mut object = json::object::Object::with_capacity(self.len());
+        for (key, value) in self {
+            object.insert(key.as_ref(), value.to_json());
+        }
+
+        JsonValue::Object(object)
+    }
+}
+
+impl<K: AsRef<str>, V: ToJson> ToJson for BTreeMap<K, V> {
+    fn to_json(&self) -> JsonValue {
+        let mut object = json::object::Object::with_capacity(self.len());
+        for (key, value) in self {
+            object.insert(key.as_ref(), value.to_json());
+        }
+
+        JsonValue::Object(object)
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for &T {
+    fn to_json(&self) -> JsonValue {
+        (**self).to_json()
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for Box<T> {
+    fn to_json(&self) -> JsonValue {
+        (**self).to_json()
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for Rc<T> {
+    fn to_json(&self) -> JsonValue {
+        (**self).to_json()
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for Arc<T> {
+    fn to_json(&self) -> JsonValue {
+        (**self).to_json()
+    }
+}
+
+/// a value whose properties can be used in a template,
+/// like a struct deriving `BenchpressContext`
+///
+/// maps and `JsonValue`s don't implement it, since their keys aren't known
+/// until rendering, so the template can only iterate and render them
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` doesn't have fields for the template to use",
+    label = "fields of this are used by the template",
+    note = "derive `BenchpressContext` for structs used by templates",
+    note = "the properties of maps and `JsonValue`s can't be used, only iterated"
+)]
+pub trait HasFields {
+    /// the struct with the fields
+    type Fields: ?Sized;
+}
+
+impl<T: HasFields> HasFields for Option<T> {
+    type Fields = T::Fields;
+}
+
+impl<T: HasFields + ?Sized> HasFields for &T {
+    type Fields = T::Fields;
+}
+
+impl<T: HasFields + ?Sized> HasFields for Box<T> {
+    type Fields = T::Fields;
+}
+
+impl<T: HasFields + ?Sized> HasFields for Rc<T> {
+    type Fields = T::Fields;
+}
+
+impl<T: HasFields + ?Sized> HasFields for Arc<T> {
+    type Fields = T::Fields;
+}
+
+/// a value which can be iterated with `{{{ each }}}`
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be iterated by the template",
+    label = "this is iterated by the template"
+)]
+pub trait Iterable {
+    /// the type of each element
+    type Item: ?Sized;
+}
+
+impl<T> Iterable for [T] {
+    type Item = T;
+}
+
+impl<T> Iterable for Vec<T> {
+    type Item = T;
+}
+
+impl<K, V> Iterable for HashMap<K, V> {
+    type Item = V;
+}
+
+impl<K, V> Iterable for BTreeMap<K, V> {
+    type Item = V;
+}
+
+impl<T: Iterable> Iterable for Option<T> {
+    type Item = T::Item;
+}
+
+impl<T: Iterable + ?Sized> Iterable for &T {
+    type Item = T::Item;
+}
+
+impl<T: Iterable + ?Sized> Iterable for Box<T> {
+    type Item = T::Item;
+}
+
+impl<T: Iterable + ?Sized> Iterable for Rc<T> {
+    type Item = T::Item;
+}
+
+impl<T: Iterable + ?Sized> Iterable for Arc<T> {
+    type Item = T::Item;
+}
+
+/// the fields of a value, only used to check them at compile time
+#[doc(hidden)]
+pub fn fields<T: HasFields + ?Sized>(_: &T) -> &T::Fields {
+    unreachable!("only used to check fields")
+}
+
+/// an element of a value, only used to check its fields at compile time
+#[doc(hidden)]
+pub fn element<T: Iterable + ?Sized>(_: &T) -> &T::Item {
+    unreachable!("only used to check elements")
+}
+
+/// a context with a template,
+/// like a struct deriving `BenchpressContext` with `#[template(path = "...")]`
+pub trait Template: ToJson {
+    /// render the template with the given helpers into `out`
+    fn render_with(&self, helpers: &HelperRegistry, out: &mut String);
+
+    /// render the template without any helpers
+    fn render(&self) -> String {
+        let mut out = String::new();
+        self.render_with(&HelperRegistry::new(), &mut out);
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_json() {
+        let mut map = BTreeMap::new();
+        map.insert("b", vec![Some(1.5), None]);
+        map.insert("a", vec![]);
+
+        assert_eq!(map.to_json().dump(), r#"{"a":[],"b":[1.5,null]}"#);
+        assert_eq!(Box::new("x").to_json(), JsonValue::from("x"));
+        assert_eq!('y'.to_json(), JsonValue::from("y"));
+        assert_eq!(Rc::new(true).to_json(), JsonValue::from(true));
+        assert_eq!((-3i8).to_json(), JsonValue::from(-3));
+    }
+}
@@ -0,0 +1,340 @@
+use parser::{Control, Expression};
+use templates::{escape_path, indent, is_identifier};
+
+/// declaration of the values `{{{each}}}` iterates, arrays or plain objects
+const ITERATED: &str = "export type Iterated<T> = Array<T> | Record<string, T>;\n\n";
+
+/// the shape of a value a template expects,
+/// inferred from the paths used with it
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Shape {
+    /// properties used on the value, in order of first use
+    pub fields: Vec<(String, Shape)>,
+    /// the shape of its elements or values, if the value is iterated
+    pub element: Option<Box<Shape>>,
+}
+
+/// split an iteration suffix like `[1]` from the end of a path part,
+/// returning the part without it and whether there was one
+fn split_element(part: &str) -> (&str, bool) {
+    match part.rfind('[') {
+        Some(start)
+            if part.ends_with(']')
+                && start + 2 < part.len()
+                && part[start + 1..part.len() - 1]
+                    .bytes()
+                    .all(|b| b.is_ascii_digit()) =>
+        {
+            (&part[..start], true)
+        }
+        _ => (part, false),
+    }
+}
+
+impl Shape {
+    /// the shape of a property, added if it wasn't used yet
+    fn field(&mut self, name: &str) -> &mut Shape {
+        let index = match self.fields.iter().position(|(field, _)| field == name) {
+            Some(index) => index,
+            None => {
+                self.fields.push((name.to_string(), Shape::default()));
+                self.fields.len() - 1
+            }
+        };
+
+        &mut self.fields[index].1
+    }
+
+    /// the shape at a resolved path, added if it wasn't used yet
+    /// `loops` are the paths of the elements being iterated, innermost last
+    /// `None` for paths which aren't in the context, like `@index`
+    fn at(&mut self, path: &[String], loops: &[Vec<String>]) -> Option<&mut Shape> {
+        let path = within(path, loops)?;
+
+        let mut shape = self;
+        for part in path.iter().filter(|part| !part.ends_with("./")) {
+            let (name, element) = split_element(part);
+            if !name.is_empty() {
+                shape = shape.field(name);
+            }
+            if element {
+                shape = shape.element.get_or_insert_with(Default::default);
+            }
+        }
+
+        Some(shape)
+    }
+
+    /// add the paths used in an expression
+    fn expression(&mut self, input: &Expression, loops: &[Vec<String>]) {
+        match input {
+            Expression::PathExpression { path } => {
+                self.at(path, loops);
+            }
+            Expression::HelperExpression { args, .. } => {
+                for arg in args {
+                    self.expression(arg, loops);
+                }
+            }
+            Expression::NegativeExpression { expr } => self.expression(expr, loops),
+            Expression::ComparisonExpression { left, right, .. }
+            | Expression::LogicalExpression { left, right, .. } => {
+                self.expression(left, loops);
+                self.expression(right, loops);
+            }
+            Expression::StringLiteral { .. } => (),
+        }
+    }
+
+    /// add the paths used in a body, recursively
+    fn body(&mut self, entry: &[Control], loops: &mut Vec<Vec<String>>) {
+        for control in entry {
+            match control {
+                // imported templates aren't known until rendering
+                Control::Text { .. }
+                | Control::Import { .. }
+                | Control::Extends { .. }
+                | Control::Super { .. } => (),
+                Control::Block { body, .. } => self.body(body, loops),
+                Control::If {
+                    subject,
+                    body,
+                    else_ifs,
+                    alt,
+                    ..
+                } => {
+                    self.expression(subject, loops);
+                    self.body(body, loops);
+                    for branch in else_ifs {
+                        self.expression(&branch.subject, loops);
+                        self.body(&branch.body, loops);
+                    }
+                    self.body(alt, loops);
+                }
+                Control::Iter {
+                    suffix,
+                    subject,
+                    body,
+                    alt,
+                    ..
+                } => {
+                    self.expression(subject, loops);
+                    let element = match subject {
+                        Expression::PathExpression { path } => within(path, loops),
+                        _ => None,
+                    };
+
+                    match element {
+                        // `@value` within the body is the element
+                        Some(mut element) => {
+                            if let Some(shape) = self.at(&element, loops) {
+                                shape.element.get_or_insert_with(Default::default);
+                            }
+
+                            element.push(format!("[{}]", suffix));
+                            loops.push(element);
+                            self.body(body, loops);
+                            loops.pop();
+                        }
+                        None => self.body(body, loops),
+                    }
+                    self.body(alt, loops);
+                }
+                Control::Escaped { subject, .. } | Control::Raw { subject, .. } => {
+                    self.expression(subject, loops)
+                }
+            }
+        }
+    }
+
+    /// TypeScript object type of the fields
+    fn object_type<'a, I>(fields: I) -> String
+    where
+        I: Iterator<Item = &'a (String, Shape)>,
+    {
+        let members: Vec<String> = fields
+            .map(|(name, shape)| {
+                let name = if is_identifier(name) {
+                    name.to_string()
+                } else {
+                    format!("'{}'", escape_path(name))
+                };
+
+                format!("  {}: {};", name, indent(&shape.typescript(), 2))
+            })
+            .collect();
+
+        if members.is_empty() {
+            "{}".to_string()
+        } else {
+            format!("{{\n{}\n}}", members.join("\n"))
+        }
+    }
+
+    /// whether the value or any within it are iterated
+    fn iterated(&self) -> bool {
+        self.element.is_some() || self.fields.iter().any(|(_, shape)| shape.iterated())
+    }
+
+    /// TypeScript type of the shape
+    /// iterated values refer to the `Iterated` type of the declaration
+    pub fn typescript(&self) -> String {
+        match &self.element {
+            Some(element) => {
+                let iterated = format!("Iterated<{}>", element.typescript());
+                // arrays already have a length
+                let mut fields = self
+                    .fields
+                    .iter()
+                    .filter(|(name, _)| name != "length")
+                    .peekable();
+
+                if fields.peek().is_some() {
+                    format!("{} & {}", iterated, Shape::object_type(fields))
+                } else {
+                    iterated
+                }
+            }
+            None if self.fields.is_empty() => "unknown".to_string(),
+            None => Shape::object_type(self.fields.iter()),
+        }
+    }
+}
+
+/// the path within the context a resolved path refers to,
+/// with `@value` replaced by the innermost element of `loops`
+/// `None` for paths which aren't in the context, like `@index`
+fn within(path: &[String], loops: &[Vec<String>]) -> Option<Vec<String>> {
+    match path.first().map(String::as_str) {
+        Some("@root") => Some(path[1..].to_vec()),
+        Some("@value") => loops
+            .last()
+            .map(|element| [&element[..], &path[1..]].concat()),
+        Some(first) if first.starts_with('@') => None,
+        _ => Some(path.to_vec()),
+    }
+}
+
+/// infer the shape of the context a template expects
+pub fn context_shape(tree: &[Control]) -> Shape {
+    let mut shape = Shape::default();
+    shape.body(tree, &mut Vec::new());
+
+    shape
+}
+
+/// create a TypeScript declaration of the context a template expects
+pub fn declaration(tree: &[Control]) -> String {
+    let shape = context_shape(tree);
+
+    format!(
+        "{}export interface Context {}\n",
+        if shape.iterated() { ITERATED } else { "" },
+        Shape::object_type(shape.fields.iter())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn declare(template: &str) -> String {
+        let (tree, _) = ::parse_with(template, &::Options::default());
+
+        declaration(&tree)
+    }
+
+    #[test]
+    fn split_element_test() {
+        assert_eq!(split_element("posts[1]"), ("posts", true));
+        assert_eq!(split_element("[12]"), ("", true));
+        assert_eq!(split_element("posts[]"), ("posts[]", false));
+        assert_eq!(split_element("posts"), ("posts", false));
+    }
+
+    #[test]
+    fn nested() {
+        assert_eq!(
+            declare("{{{ each posts }}}{posts.title}{../user.username}{{{ end }}}{posts.length}"),
+            "export type Iterated<T> = Array<T> | Record<string, T>;
+
+export interface Context {
+  posts: Iterated<{
+    title: unknown;
+    user: {
+      username: unknown;
+    };
+  }>;
+}
+"
+        );
+    }
+
+    #[test]
+    fn legacy() {
+        assert_eq!(
+            declare(
+                "<!-- BEGIN rows --><!-- IF rows.cells.length -->{rows.name}<!-- ENDIF rows.cells.length --><!-- END rows -->"
+            ),
+            "export type Iterated<T> = Array<T> | Record<string, T>;
+
+export interface Context {
+  rows: Iterated<{
+    cells: {
+      length: unknown;
+    };
+    name: unknown;
+  }>;
+}
+"
+        );
+    }
+
+    #[test]
+    fn special_paths() {
+        assert_eq!(
+            declare(
+                "{{{ each items }}}{@index}{@value}{@root.title}{{{ end }}}\
+                 {{{ if !helper(a, \"b\", c.d) }}}{items.count}{{{ end }}}{my-name}"
+            ),
+            "export type Iterated<T> = Array<T> | Record<string, T>;
+
+export interface Context {
+  items: Iterated<unknown> & {
+    count: unknown;
+  };
+  title: unknown;
+  a: unknown;
+  c: {
+    d: unknown;
+  };
+  'my-name': unknown;
+}
+"
+        );
+
+        assert_eq!(declare("text"), "export interface Context {}\n");
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(
+            declare(
+                "{{{ each posts }}}{@value.title}\
+                 {{{ each @value.tags }}}{@value.name}{{{ end }}}\
+                 {{{ else }}}{@value.ignored}{{{ end }}}"
+            ),
+            "export type Iterated<T> = Array<T> | Record<string, T>;
+
+export interface Context {
+  posts: Iterated<{
+    title: unknown;
+    tags: Iterated<{
+      name: unknown;
+    }>;
+  }>;
+}
+"
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use error::{CompileError, ErrorKind, Span};
+
+use std::collections::HashSet;
+use std::fmt;
+use std::slice;
+use std::vec;
+
+/// how serious a diagnostic is
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash)]
+pub enum Severity {
+    /// the template is broken
+    Error,
+    /// the compiler made a judgement call
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+/// a secondary location related to a diagnostic
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// a problem found in a template
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Diagnostic {
+    pub code: ErrorKind,
+    pub severity: Severity,
+    pub span: Span,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: ErrorKind, span: Span, message: &str) -> Diagnostic {
+        Diagnostic {
+            code,
+            severity,
+            span,
+            message: message.to_string(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(code: ErrorKind, span: Span, message: &str) -> Diagnostic {
+        Diagnostic::new(Severity::Error, code, span, message)
+    }
+
+    pub fn warning(code: ErrorKind, span: Span, message: &str) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, code, span, message)
+    }
+
+    /// add a label pointing at a related location
+    pub fn with_label(mut self, span: Span, message: &str) -> Diagnostic {
+        self.labels.push(Label {
+            span,
+            message: message.to_string(),
+        });
+        self
+    }
+
+    /// add a note with extra information
+    pub fn with_note(mut self, note: &str) -> Diagnostic {
+        self.notes.push(note.to_string());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}]: {} (at bytes {}..{})",
+            self.severity, self.code, self.message, self.span.start, self.span.end
+        )?;
+
+        for note in &self.notes {
+            write!(f, "\n  = note: {}", note)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Diagnostic> for CompileError {
+    fn from(diagnostic: Diagnostic) -> CompileError {
+        CompileError {
+            kind: diagnostic.code,
+            span: diagnostic.span,
+            message: diagnostic.message,
+        }
+    }
+}
+
+/// collects diagnostics from every phase of compilation
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Diagnostics {
+    list: Vec<Diagnostic>,
+    /// the code, severity, span and message of every diagnostic in `list`
+    seen: HashSet<(ErrorKind, Severity, Span, String)>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics::default()
+    }
+
+    /// add a diagnostic, ignoring duplicates with the same code, severity, span and message
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        let key = (
+            diagnostic.code,
+            diagnostic.severity,
+            diagnostic.span,
+            diagnostic.message.clone(),
+        );
+        if self.seen.insert(key) {
+            self.list.push(diagnostic);
+        }
+    }
+
+    /// add every diagnostic from another collection
+    pub fn extend(&mut self, other: Diagnostics) {
+        for diagnostic in other {
+            self.push(diagnostic);
+        }
+    }
+
+    /// translate the spans of every diagnostic, including labels
+    pub fn map_spans<F>(self, f: F) -> Diagnostics
+    where
+        F: Fn(Span) -> Span,
+    {
+        let mut output = Diagnostics::new();
+        for mut diagnostic in self {
+            diagnostic.span = f(diagnostic.span);
+            for label in &mut diagnostic.labels {
+                label.span = f(label.span);
+            }
+            output.push(diagnostic);
+        }
+
+        output
+    }
+
+    pub fn error(&mut self, code: ErrorKind, span: Span, message: &str) {
+        self.push(Diagnostic::error(code, span, message));
+    }
+
+    pub fn warning(&mut self, code: ErrorKind, span: Span, message: &str) {
+        self.push(Diagnostic::warning(code, span, message));
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.iter().filter(|d| d.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.iter().filter(|d| d.severity == Severity::Warning)
+    }
+
+    /// the first error, if any
+    pub fn first_error(&self) -> Option<CompileError> {
+        self.errors().next().cloned().map(CompileError::from)
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, Diagnostic> {
+        self.list.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.list.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.list.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn severities() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.warning(ErrorKind::ExtraEnd, Span::new(0, 3), "extra");
+        assert!(!diagnostics.has_errors());
+
+        diagnostics.error(ErrorKind::InvalidExpression, Span::new(4, 5), "invalid");
+        assert!(diagnostics.has_errors());
+        assert_eq!(diagnostics.warnings().count(), 1);
+        assert_eq!(
+            diagnostics.first_error(),
+            Some(CompileError::new(
+                ErrorKind::InvalidExpression,
+                Span::new(4, 5),
+                "invalid"
+            ))
+        );
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.warning(ErrorKind::LegacySyntax, Span::new(0, 3), "legacy");
+        diagnostics.warning(ErrorKind::LegacySyntax, Span::new(0, 3), "legacy");
+        assert_eq!(diagnostics.len(), 1);
+
+        diagnostics.warning(ErrorKind::LegacySyntax, Span::new(0, 3), "other");
+        diagnostics.warning(ErrorKind::LegacySyntax, Span::new(0, 4), "legacy");
+        assert_eq!(diagnostics.len(), 3);
+
+        // an error isn't hidden by an earlier warning
+        diagnostics.error(ErrorKind::LegacySyntax, Span::new(0, 3), "legacy");
+        assert_eq!(diagnostics.len(), 4);
+        assert!(diagnostics.has_errors());
+    }
+
+    #[test]
+    fn display() {
+        let diagnostic = Diagnostic::warning(ErrorKind::ExtraEnd, Span::new(2, 9), "extra token")
+            .with_note("remove it");
+
+        assert_eq!(
+            diagnostic.to_string(),
+            "warning[B0003]: extra token (at bytes 2..9)\n  = note: remove it"
+        );
+    }
+}
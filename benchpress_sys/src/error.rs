@@ -0,0 +1,114 @@
+use std::error::Error;
+use std::fmt;
+
+/// a byte range within the template source
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+}
+
+/// the kind of problem found in a template
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash)]
+pub enum ErrorKind {
+    /// a block with a keyword (`{{{if}}}`, `{{{each}}}`, etc)
+    /// which couldn't be lexed
+    InvalidBlock,
+    /// an instruction missing its keyword or closer
+    UnterminatedInstruction,
+    /// an `{{{end}}}` which doesn't close anything
+    ExtraEnd,
+    /// an expression which couldn't be parsed
+    InvalidExpression,
+    /// a `{{{block}}}` without a known keyword
+    UnknownBlock,
+    /// legacy syntax rewritten before lexing
+    LegacySyntax,
+    /// an `{{{if}}}` or `{{{each}}}` which is never closed
+    UnclosedBlock,
+    /// an `{{{end}}}` whose subject doesn't match the block it closes
+    MismatchedEnd,
+    /// an `{{{else}}}` outside of a block or after another `{{{else}}}`
+    StrayElse,
+    /// blocks or expressions nested beyond the supported depth
+    NestingLimit,
+    /// a helper called while rendering which isn't registered
+    UnknownHelper,
+    /// an `{{{import}}}` or `{{{extends}}}` whose template couldn't be resolved
+    UnresolvedImport,
+    /// an `{{{import}}}` or `{{{extends}}}` of a template which is already being used
+    ImportCycle,
+    /// a `{{{block}}}` overriding a block its parent template doesn't have
+    UnknownOverride,
+    /// an `{{{extends}}}`, `{{{block}}}` or `{{{super}}}` which can't be used where it is
+    InvalidExtends,
+    /// an `{{{import}}}` within an `{{{each}}}` rendered at runtime,
+    /// which only sees the root context
+    ImportInLoop,
+}
+
+impl ErrorKind {
+    /// stable code identifying this kind of problem
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorKind::InvalidBlock => "B0001",
+            ErrorKind::UnterminatedInstruction => "B0002",
+            ErrorKind::ExtraEnd => "B0003",
+            ErrorKind::InvalidExpression => "B0004",
+            ErrorKind::UnknownBlock => "B0005",
+            ErrorKind::LegacySyntax => "B0006",
+            ErrorKind::UnclosedBlock => "B0007",
+            ErrorKind::MismatchedEnd => "B0008",
+            ErrorKind::StrayElse => "B0009",
+            ErrorKind::NestingLimit => "B0010",
+            ErrorKind::UnknownHelper => "B0011",
+            ErrorKind::UnresolvedImport => "B0012",
+            ErrorKind::ImportCycle => "B0013",
+            ErrorKind::UnknownOverride => "B0014",
+            ErrorKind::InvalidExtends => "B0015",
+            ErrorKind::ImportInLoop => "B0016",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// an error encountered while compiling a template
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct CompileError {
+    pub kind: ErrorKind,
+    pub span: Span,
+    pub message: String,
+}
+
+impl CompileError {
+    pub fn new(kind: ErrorKind, span: Span, message: &str) -> CompileError {
+        CompileError {
+            kind,
+            span,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (at bytes {}..{})",
+            self.message, self.span.start, self.span.end
+        )
+    }
+}
+
+impl Error for CompileError {}
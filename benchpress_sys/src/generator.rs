@@ -1,15 +1,101 @@
-use parser::{Control, Expression};
+use error::Span;
+use imports;
+use minify;
+use options::{Options, Target};
+use parser::{Branch, Control, Expression};
 use templates;
 
 use json;
 use std::collections::HashSet;
 
+// markers placed around generated code to track where it came from
+// they're private use characters, which are always escaped in literals
+pub static MARK_START: char = '\u{E000}';
+pub static MARK_END: char = '\u{E001}';
+
+/// template spans of generated code,
+/// recorded as markers within the code when enabled
+struct Marks {
+    spans: Option<Vec<Option<Span>>>,
+}
+
+impl Marks {
+    /// mark the following code as coming from `span`,
+    /// or from nowhere if `None`
+    fn mark(&mut self, span: Option<Span>) -> String {
+        match &mut self.spans {
+            Some(spans) => {
+                spans.push(span);
+                format!("{}{}{}", MARK_START, spans.len() - 1, MARK_END)
+            }
+            None => String::new(),
+        }
+    }
+}
+
+/// a position in the generated code
+/// and the template span it was generated from
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct Mapping {
+    /// 0-based line
+    pub line: usize,
+    /// 0-based column in UTF-16 code units
+    pub column: usize,
+    /// `None` for code which isn't from the template
+    pub span: Option<Span>,
+}
+
+/// code generated for a control within a body
+struct Piece {
+    /// marker before the code
+    start: String,
+    /// the value of text, which can be inlined into a template literal
+    text: Option<String>,
+    code: String,
+    /// marker after the code
+    end: String,
+}
+
+/// join the pieces of a body into a single expression
+///
+/// a template literal is only used when the body starts with text,
+/// since `+` adds numbers and booleans until it reaches a string
+fn join(pieces: Vec<Piece>, target: Target) -> String {
+    match target {
+        Target::Es2015 if pieces.len() > 1 && pieces[0].text.is_some() => {
+            templates::template_literal(
+                &pieces
+                    .into_iter()
+                    .map(|piece| {
+                        let inner = match piece.text {
+                            Some(text) => templates::escape_template(&text),
+                            None => templates::substitution(&piece.code),
+                        };
+                        format!("{}{}{}", piece.start, inner, piece.end)
+                    })
+                    .collect::<Vec<String>>(),
+            )
+        }
+        _ => templates::concat(
+            &pieces
+                .into_iter()
+                .map(|piece| format!("{}{}{}", piece.start, piece.code, piece.end))
+                .collect::<Vec<String>>(),
+        ),
+    }
+}
+
 /// generate code for a body
-/// recursively applied to If and Iter children
+/// recursively applied to If, Iter and Block children
+/// code is marked as coming from each control,
+/// then from `parent` once the control ends
 fn gen_body(
     entry: Vec<Control>,
     top: bool,
-    mut block_names: &mut HashSet<String>,
+    parent: Option<Span>,
+    block_names: &mut HashSet<String>,
+    marks: &mut Marks,
+    target: Target,
 ) -> (String, Vec<String>) {
     if entry.is_empty() {
         return ("\"\"".to_string(), Vec::new());
@@ -19,68 +105,370 @@ fn gen_body(
 
     let output = entry
         .into_iter()
-        .map(|elem| match elem {
-            // output a string literal in JS
-            Control::Text { value } => json::stringify(json::from(value)),
-            // output a ternary in JS
-            Control::If { subject, body, alt } => {
-                let (b, mut b_blocks) = gen_body(body, top, &mut block_names);
-                let (a, mut a_blocks) = gen_body(alt, top, &mut block_names);
-
-                blocks.append(&mut b_blocks);
-                blocks.append(&mut a_blocks);
-
-                // if an "if not" reverse the ternary
-                let (expr, neg) = if let Expression::NegativeExpression { expr } = subject {
-                    (*expr, true)
-                } else {
-                    (subject, false)
-                };
-
-                templates::if_else(neg, &templates::expression(expr), &b, &a)
-            }
-            // output a call to `iter` in JS
-            Control::Iter {
-                suffix,
-                subject_raw,
-                subject,
-                body,
-                alt,
-            } => {
-                let block = templates::iter(
+        .map(|elem| {
+            let span = elem.span();
+            let mut text = None;
+            let code = match elem {
+                // output a string literal in JS
+                Control::Text { value, .. } => {
+                    let code = templates::escape_markers(&json::stringify(json::from(&*value)));
+                    text = Some(value);
+                    code
+                }
+                // output a ternary in JS
+                Control::If {
+                    subject,
+                    body,
+                    else_ifs,
+                    alt,
+                    ..
+                } => {
+                    let (b, mut b_blocks) =
+                        gen_body(body, top, Some(span), block_names, marks, target);
+                    blocks.append(&mut b_blocks);
+
+                    let mut branches = Vec::with_capacity(else_ifs.len() + 1);
+                    for Branch { subject, body, .. } in else_ifs {
+                        let (code, mut code_blocks) =
+                            gen_body(body, top, Some(span), block_names, marks, target);
+                        blocks.append(&mut code_blocks);
+                        branches.push((templates::expression(target, subject), code));
+                    }
+
+                    let (a, mut a_blocks) =
+                        gen_body(alt, top, Some(span), block_names, marks, target);
+                    blocks.append(&mut a_blocks);
+
+                    match subject {
+                        // if a lone "if not" reverse the ternary
+                        Expression::NegativeExpression { expr } if branches.is_empty() => {
+                            templates::if_else(&[(templates::expression(target, *expr), a)], &b)
+                        }
+                        subject => {
+                            branches.insert(0, (templates::expression(target, subject), b));
+                            templates::if_else(&branches, &a)
+                        }
+                    }
+                }
+                // output a call to `iter` in JS
+                Control::Iter {
                     suffix,
-                    &templates::expression(subject),
-                    &gen_body(body, false, &mut HashSet::new()).0,
-                    &gen_body(alt, false, &mut HashSet::new()).0,
-                );
-
-                // if top level, pull out into a block method
-                if top && !block_names.contains(&subject_raw) {
-                    let out = templates::block_call(&subject_raw);
-                    blocks.push(templates::block(&subject_raw, &block));
-                    block_names.insert(subject_raw);
-
-                    out
-                } else {
-                    block
+                    subject_raw,
+                    subject,
+                    body,
+                    alt,
+                    ..
+                } => {
+                    let mut gen_child = |child| {
+                        gen_body(child, false, Some(span), &mut HashSet::new(), marks, target).0
+                    };
+                    let block = templates::iter(
+                        target,
+                        suffix,
+                        &templates::expression(target, subject),
+                        &gen_child(body),
+                        &gen_child(alt),
+                    );
+
+                    // if top level, pull out into a block method
+                    if top && !block_names.contains(&subject_raw) {
+                        let out = templates::block_call(&subject_raw);
+                        let block =
+                            format!("{}{}{}", marks.mark(Some(span)), block, marks.mark(None));
+                        blocks.push(templates::block(target, &subject_raw, &block));
+                        block_names.insert(subject_raw);
+
+                        out
+                    } else {
+                        block
+                    }
                 }
+                // generate an escape call and guard expression
+                Control::Escaped { subject, .. } => {
+                    format!(
+                        "{}({})",
+                        templates::ESCAPE,
+                        templates::expression(target, subject)
+                    )
+                }
+                // generate a guard expression
+                Control::Raw { subject, .. } => templates::expression(target, subject),
+                // call the import helper, for imports which weren't inlined
+                Control::Import { path, .. } => {
+                    templates::expression(target, imports::import_call(&path))
+                }
+                // output the body of a block in place
+                Control::Block { body, .. } => {
+                    let (b, mut b_blocks) =
+                        gen_body(body, top, Some(span), block_names, marks, target);
+                    blocks.append(&mut b_blocks);
+
+                    format!("({})", b)
+                }
+                // only used when extending templates
+                Control::Extends { .. } | Control::Super { .. } => "\"\"".to_string(),
+            };
+
+            Piece {
+                start: marks.mark(Some(span)),
+                text,
+                code,
+                end: marks.mark(parent),
             }
-            // generate an escape call and guard expression
-            Control::Escaped { subject } => {
-                format!("{}({})", templates::ESCAPE, templates::expression(subject))
-            }
-            // generate a guard expression
-            Control::Raw { subject } => templates::expression(subject),
         })
-        .filter(|x| !x.is_empty())
-        .collect::<Vec<String>>();
+        .collect::<Vec<Piece>>();
+
+    (join(output, target), blocks)
+}
+
+/// remove the markers from generated code,
+/// returning the mappings they represent
+fn strip_marks(code: &str, spans: &[Option<Span>]) -> (String, Vec<Mapping>) {
+    let mut output = String::with_capacity(code.len());
+    let mut mappings = Vec::new();
+    let mut line = 0;
+    let mut column = 0;
+
+    let mut chars = code.chars();
+    while let Some(ch) = chars.next() {
+        if ch == MARK_START {
+            let id: String = chars.by_ref().take_while(|&ch| ch != MARK_END).collect();
+            if let Some(&span) = id.parse::<usize>().ok().and_then(|id| spans.get(id)) {
+                // later markers at the same position take precedence
+                if let Some(last) = mappings.last_mut() {
+                    let last: &mut Mapping = last;
+                    if last.line == line && last.column == column {
+                        last.span = span;
+                        continue;
+                    }
+                }
+
+                mappings.push(Mapping { line, column, span });
+            }
+        } else {
+            output.push(ch);
+            if ch == '\n' {
+                line += 1;
+                column = 0;
+            } else {
+                column += ch.len_utf16();
+            }
+        }
+    }
 
-    (templates::concat(&output), blocks)
+    (output, mappings)
 }
 
 /// generate code from parser output
 pub fn generate(input: Vec<Control>) -> String {
-    let (body, blocks) = gen_body(input, true, &mut HashSet::new());
+    generate_with(input, &Options::default())
+}
+
+/// generate the whole module, minified if requested
+fn gen_module(input: Vec<Control>, options: &Options, marks: &mut Marks) -> String {
+    let (body, blocks) = gen_body(
+        input,
+        true,
+        None,
+        &mut HashSet::new(),
+        marks,
+        options.target,
+    );
+    let code = templates::wrapper(options, &body, &blocks);
+
+    if options.minify {
+        minify::minify(&code)
+    } else {
+        code
+    }
+}
+
+/// generate code from parser output with the given options
+pub fn generate_with(input: Vec<Control>, options: &Options) -> String {
+    gen_module(input, options, &mut Marks { spans: None })
+}
+
+/// generate code from parser output with the given options,
+/// along with mappings from the generated code to the template
+pub fn generate_with_mappings(input: Vec<Control>, options: &Options) -> (String, Vec<Mapping>) {
+    let mut marks = Marks {
+        spans: Some(Vec::new()),
+    };
+    let code = gen_module(input, options, &mut marks);
+
+    strip_marks(&code, &marks.spans.unwrap_or_default())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use options::OutputFormat;
+    use render;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    /// render a template by running code generated for `target` with node,
+    /// or `None` if node isn't installed
+    fn render_node(template: &str, data: &str, target: Target) -> Option<String> {
+        let options = Options {
+            target,
+            format: OutputFormat::Function,
+            ..Options::default()
+        };
+        let (tree, _) = ::parse_with(template, &options);
+
+        run_node(&generate_with(tree, &options), data)
+    }
+
+    /// render `data` by running code compiled with the `function` format with node,
+    /// or `None` if node isn't installed
+    pub(crate) fn run_node(code: &str, data: &str) -> Option<String> {
+        let script = format!(
+            "const guard = v => (v == null || (Array.isArray(v) && !v.length)) ? '' : v;
+const iter = (obj, each, alt) => {{
+  const keys = obj && typeof obj === 'object' ? Object.keys(obj) : [];
+  let output = '';
+  keys.forEach((key, i) => {{ output += each(key, i, keys.length, obj[key]); }});
+  return keys.length ? output : alt();
+}};
+const helper = (context, helpers, name, args) => name + '(' + args.join() + ')';
+const compiled = {};
+process.stdout.write(String(guard(compiled({{ __escape: x => x }}, {}, guard, iter, helper))));",
+            code, data
+        );
+
+        let mut node = Command::new("node")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+        node.stdin
+            .take()
+            .unwrap()
+            .write_all(script.as_bytes())
+            .unwrap();
+        let output = node.wait_with_output().unwrap();
+        assert!(output.status.success());
+
+        Some(String::from_utf8(output.stdout).unwrap())
+    }
+
+    #[test]
+    fn first_and_last() {
+        let data = r#"{ "l": ["a", "b", "c"] }"#;
+        let template = "{{{ each l }}}-{{!@first}}{{@first}}{{@last}}{{!@last}}{{{ end }}}";
+        let expected = "-falsetruefalsetrue-truefalsefalsetrue-truefalsetruefalse";
+
+        for &target in &[Target::Es5, Target::Es2015] {
+            match render_node(template, data, target) {
+                Some(output) => assert_eq!(output, expected, "{:?}", target),
+                None => return,
+            }
+        }
+    }
+
+    #[test]
+    fn targets_render_the_same() {
+        let data = r#"{ "n": 2, "a": 1, "f": true, "no": false, "zero": 0, "e": "", "s": "x", "l": [1, 2] }"#;
+        let templates = [
+            "{{n}}{{a}}",
+            "{{f}}{{f}}",
+            "{{n}}{{zero}}",
+            "{{n}}{{a}}{{s}}",
+            "{{missing}}{{n}}{{l}}",
+            "x{{n}}{{a}}",
+            "{{{ if f }}}{{n}}{{a}}{{{ else }}}{{s}}{{{ end }}}-{{n}}",
+            "{{{ if !f }}}{{s}}{{{ else }}}{{n}}{{f}}{{{ end }}}",
+            // paths stop at falsy values, not just `null` and `undefined`
+            "-{{zero.b}}|{{no.b}}|{{e.length}}|{{s.length}}|{{l.length}}|{{missing.b}}",
+            "{{{ if e.length }}}a{{{ else }}}b{{{ end }}}{{{ each zero.b }}}c{{{ end }}}",
+        ];
+
+        for template in &templates {
+            let (tree, _) = ::parse_with(template, &Options::default());
+            let expected = render::render(&tree, &json::parse(data).unwrap());
+
+            for &target in &[Target::Es5, Target::Es2015] {
+                match render_node(template, data, target) {
+                    Some(output) => assert_eq!(output, expected, "{} ({:?})", template, target),
+                    None => return,
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mappings() {
+        let tree = vec![
+            Control::Text {
+                value: "a\u{E000}".to_string(),
+                span: Span::new(0, 4),
+            },
+            Control::Raw {
+                subject: Expression::PathExpression {
+                    path: vec!["b".to_string()],
+                },
+                span: Span::new(4, 9),
+            },
+        ];
+        let (code, mappings) = generate_with_mappings(tree.clone(), &Options::default());
 
-    templates::wrapper(&body, &blocks)
+        // code is the same as without mappings
+        assert_eq!(code, generate(tree));
+
+        let lines: Vec<&str> = code.lines().collect();
+        let at = |mapping: &Mapping| &lines[mapping.line][mapping.column..];
+        assert_eq!(
+            mappings
+                .iter()
+                .map(|mapping| (at(mapping).split(' ').next().unwrap(), mapping.span))
+                .collect::<Vec<_>>(),
+            vec![
+                ("\"a\\ue000\"", Some(Span::new(0, 4))),
+                ("", None),
+                ("guard(context", Some(Span::new(4, 9))),
+                (";", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn es2015() {
+        let tree = vec![
+            Control::Text {
+                value: "a`".to_string(),
+                span: Span::new(0, 2),
+            },
+            Control::Raw {
+                subject: Expression::PathExpression {
+                    path: vec!["b".to_string()],
+                },
+                span: Span::new(2, 7),
+            },
+        ];
+        let options = Options {
+            target: Target::Es2015,
+            ..Options::default()
+        };
+        let (code, mappings) = generate_with_mappings(tree.clone(), &options);
+
+        assert_eq!(code, generate_with(tree, &options));
+        assert!(code.contains("return `a\\`${guard(context && context.b)}`;"));
+
+        let lines: Vec<&str> = code.lines().collect();
+        assert_eq!(
+            mappings
+                .iter()
+                .map(|mapping| (&lines[mapping.line][mapping.column..], mapping.span))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "a\\`${guard(context && context.b)}`;",
+                    Some(Span::new(0, 2))
+                ),
+                ("${guard(context && context.b)}`;", Some(Span::new(2, 7))),
+                ("`;", None),
+            ]
+        );
+    }
 }
@@ -0,0 +1,89 @@
+use json::JsonValue;
+use std::collections::HashMap;
+
+/// a function which can be called from a template,
+/// like `{helper(a, "b")}` or the legacy `{function.helper}`
+pub trait Helper: Send + Sync {
+    /// call the helper with the root context and evaluated arguments
+    /// legacy helpers are passed `@value`, or `@root` within `<!-- IF -->`,
+    /// as their first argument
+    /// falsy values render as empty strings
+    fn call(&self, context: &JsonValue, args: &[&JsonValue]) -> JsonValue;
+}
+
+impl<F> Helper for F
+where
+    F: Fn(&JsonValue, &[&JsonValue]) -> JsonValue + Send + Sync,
+{
+    fn call(&self, context: &JsonValue, args: &[&JsonValue]) -> JsonValue {
+        self(context, args)
+    }
+}
+
+/// helpers available while rendering, keyed by name
+#[derive(Default)]
+pub struct HelperRegistry {
+    helpers: HashMap<String, Box<dyn Helper>>,
+}
+
+impl HelperRegistry {
+    pub fn new() -> HelperRegistry {
+        HelperRegistry::default()
+    }
+
+    /// add a helper, replacing any with the same name
+    pub fn register<H: Helper + 'static>(&mut self, name: &str, helper: H) -> &mut HelperRegistry {
+        self.helpers.insert(name.to_string(), Box::new(helper));
+        self
+    }
+
+    /// add a helper function, replacing any with the same name
+    pub fn register_fn<F>(&mut self, name: &str, helper: F) -> &mut HelperRegistry
+    where
+        F: Fn(&JsonValue, &[&JsonValue]) -> JsonValue + Send + Sync + 'static,
+    {
+        self.register(name, helper)
+    }
+
+    /// get a helper by name
+    pub fn get(&self, name: &str) -> Option<&dyn Helper> {
+        self.helpers.get(name).map(|helper| helper.as_ref())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.helpers.contains_key(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Join;
+
+    impl Helper for Join {
+        fn call(&self, _: &JsonValue, args: &[&JsonValue]) -> JsonValue {
+            args.iter()
+                .map(|arg| arg.to_string())
+                .collect::<Vec<_>>()
+                .join("-")
+                .into()
+        }
+    }
+
+    #[test]
+    fn registry() {
+        let mut helpers = HelperRegistry::new();
+        helpers
+            .register("join", Join)
+            .register_fn("root", |context, _| context.clone());
+
+        let context = JsonValue::from("ctx");
+        let args = [&JsonValue::from(1), &JsonValue::from("a")];
+
+        assert!(helpers.contains("join"));
+        assert!(!helpers.contains("missing"));
+        assert_eq!(helpers.get("join").unwrap().call(&context, &args), "1-a");
+        assert_eq!(helpers.get("root").unwrap().call(&context, &args), "ctx");
+    }
+}
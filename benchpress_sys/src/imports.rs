@@ -0,0 +1,1127 @@
+use diagnostics::{Diagnostic, Diagnostics};
+use error::{ErrorKind, Span};
+use options::{ImportMode, Options};
+use parser::{Branch, Control, Expression};
+use report;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+
+/// the helper called to render a template imported at runtime
+pub static IMPORT_HELPER: &str = "__import";
+
+/// how deeply imported templates can import others
+pub const MAX_IMPORT_DEPTH: usize = 16;
+
+/// how many templates can be imported or extended in total while compiling one,
+/// since each can import others several times
+pub const MAX_IMPORTS: usize = 256;
+
+/// how many bytes of imported and extended templates can be inlined in total
+pub const MAX_IMPORTED_BYTES: usize = 1 << 20;
+
+/// finds the source of templates imported with `{{{import "path"}}}`
+pub trait TemplateResolver {
+    /// the source of the template imported as `path`,
+    /// or why it couldn't be found
+    fn resolve(&self, path: &str) -> Result<String, String>;
+}
+
+/// resolves nothing, for compiling templates without imports
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoResolver;
+
+impl TemplateResolver for NoResolver {
+    fn resolve(&self, _: &str) -> Result<String, String> {
+        Err("no template resolver was given".to_string())
+    }
+}
+
+impl TemplateResolver for HashMap<String, String> {
+    fn resolve(&self, path: &str) -> Result<String, String> {
+        self.get(path)
+            .cloned()
+            .ok_or_else(|| "no template with that path".to_string())
+    }
+}
+
+impl TemplateResolver for BTreeMap<String, String> {
+    fn resolve(&self, path: &str) -> Result<String, String> {
+        self.get(path)
+            .cloned()
+            .ok_or_else(|| "no template with that path".to_string())
+    }
+}
+
+/// resolves templates from files within a directory,
+/// so `partials/topic_list` reads `partials/topic_list.tpl`
+#[derive(Debug, Clone)]
+pub struct FileResolver {
+    root: PathBuf,
+    extension: String,
+}
+
+impl FileResolver {
+    /// resolve templates within `root` with the `tpl` extension
+    pub fn new<P: AsRef<Path>>(root: P) -> FileResolver {
+        FileResolver {
+            root: root.as_ref().to_path_buf(),
+            extension: "tpl".to_string(),
+        }
+    }
+
+    /// use a different extension for the template files
+    pub fn extension(mut self, extension: &str) -> FileResolver {
+        self.extension = extension.to_string();
+        self
+    }
+
+    /// the file the template imported as `path` is read from,
+    /// or why it can't be
+    pub fn file(&self, path: &str) -> Result<PathBuf, String> {
+        let relative = Path::new(path);
+        // imports can't reach outside of the directory
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err("paths must be relative and can't contain `..`".to_string());
+        }
+
+        let mut file = self.root.join(relative);
+        if file.extension().is_none() {
+            file.set_extension(&self.extension);
+        }
+
+        Ok(file)
+    }
+}
+
+impl TemplateResolver for FileResolver {
+    fn resolve(&self, path: &str) -> Result<String, String> {
+        let file = self.file(path)?;
+
+        fs::read_to_string(&file)
+            .map_err(|err| format!("failed to read {}: {}", file.display(), err))
+    }
+}
+
+/// the expression rendering a template imported at runtime
+pub fn import_call(path: &str) -> Expression {
+    Expression::HelperExpression {
+        helper_name: IMPORT_HELPER.to_string(),
+        args: vec![Expression::StringLiteral {
+            value: path.to_string(),
+        }],
+    }
+}
+
+/// how a template is used by another
+#[derive(Debug, Clone, Copy)]
+enum Usage {
+    Import,
+    Extend,
+}
+
+impl Usage {
+    fn verb(self) -> &'static str {
+        match self {
+            Usage::Import => "import",
+            Usage::Extend => "extend",
+        }
+    }
+}
+
+/// the bodies nested within a control
+fn children(control: &mut Control) -> Vec<&mut Vec<Control>> {
+    match control {
+        Control::If {
+            body,
+            else_ifs,
+            alt,
+            ..
+        } => {
+            let mut output = vec![body];
+            output.extend(else_ifs.iter_mut().map(|branch| &mut branch.body));
+            output.push(alt);
+            output
+        }
+        Control::Iter { body, alt, .. } => vec![body, alt],
+        Control::Block { body, .. } => vec![body],
+        _ => Vec::new(),
+    }
+}
+
+/// remove the controls matching `matches` from a body, recursively,
+/// reporting each as misplaced
+fn strip(
+    body: &mut Vec<Control>,
+    matches: fn(&Control) -> bool,
+    message: &str,
+    diagnostics: &mut Diagnostics,
+) {
+    body.retain(|control| {
+        if matches(control) {
+            diagnostics.error(ErrorKind::InvalidExtends, control.span(), message);
+            false
+        } else {
+            true
+        }
+    });
+
+    for control in body {
+        for child in children(control) {
+            strip(child, matches, message, diagnostics);
+        }
+    }
+}
+
+fn is_extends(control: &Control) -> bool {
+    matches!(control, Control::Extends { .. })
+}
+
+fn is_super(control: &Control) -> bool {
+    matches!(control, Control::Super { .. })
+}
+
+/// the names and spans of the blocks within a body, recursively
+fn blocks<'t>(body: &'t [Control], output: &mut Vec<(&'t str, Span)>) {
+    for control in body {
+        match control {
+            Control::Block {
+                name, body, span, ..
+            } => {
+                output.push((name, *span));
+                blocks(body, output);
+            }
+            Control::If {
+                body,
+                else_ifs,
+                alt,
+                ..
+            } => {
+                blocks(body, output);
+                for branch in else_ifs {
+                    blocks(&branch.body, output);
+                }
+                blocks(alt, output);
+            }
+            Control::Iter { body, alt, .. } => {
+                blocks(body, output);
+                blocks(alt, output);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// the first block named `name` within a body, recursively
+fn find_block<'t>(body: &'t mut [Control], name: &str) -> Option<&'t mut Control> {
+    for control in body {
+        if matches!(control, Control::Block { name: found, .. } if found == name) {
+            return Some(control);
+        }
+
+        for child in children(control) {
+            if let Some(block) = find_block(child, name) {
+                return Some(block);
+            }
+        }
+    }
+
+    None
+}
+
+/// replace each `{{{super}}}` within a body with `parent`, recursively
+fn replace_super(body: Vec<Control>, parent: &[Control]) -> Vec<Control> {
+    let mut output = Vec::with_capacity(body.len());
+
+    for mut control in body {
+        if is_super(&control) {
+            output.extend_from_slice(parent);
+            continue;
+        }
+
+        for child in children(&mut control) {
+            *child = replace_super(mem::take(child), parent);
+        }
+        output.push(control);
+    }
+
+    output
+}
+
+/// report the diagnostics from the template at `path` at `span`,
+/// noting where they were in its `source`
+fn relocate(
+    path: &str,
+    source: &str,
+    partial: &Diagnostics,
+    span: Span,
+    diagnostics: &mut Diagnostics,
+) {
+    for diagnostic in partial.iter() {
+        let position = report::position(source, diagnostic.span.start);
+        let mut moved = Diagnostic::new(
+            diagnostic.severity,
+            diagnostic.code,
+            span,
+            &diagnostic.message,
+        )
+        .with_note(&format!(
+            "in {}:{}:{}",
+            path, position.line, position.column
+        ));
+        for label in &diagnostic.labels {
+            let position = report::position(source, label.span.start);
+            moved = moved.with_note(&format!(
+                "{}:{}:{}: {}",
+                path, position.line, position.column, label.message
+            ));
+        }
+        for note in &diagnostic.notes {
+            moved = moved.with_note(note);
+        }
+
+        diagnostics.push(moved);
+    }
+}
+
+/// replaces imports and merges extended templates within a tree
+struct Importer<'a> {
+    options: &'a Options,
+    resolver: &'a dyn TemplateResolver,
+    /// templates currently being imported or extended, innermost last
+    stack: Vec<String>,
+    /// how many `{{{each}}}` bodies the controls are within
+    loops: usize,
+    /// how many templates have been imported or extended so far
+    imports: usize,
+    /// the total size of those templates
+    imported_bytes: usize,
+    /// whether either of those ran out, so no more are
+    exhausted: bool,
+}
+
+impl<'a> Importer<'a> {
+    /// replace the imports within a body, recursively
+    fn body(&mut self, entry: Vec<Control>, diagnostics: &mut Diagnostics) -> Vec<Control> {
+        let mut output = Vec::with_capacity(entry.len());
+
+        for control in entry {
+            match control {
+                Control::If {
+                    subject,
+                    body,
+                    else_ifs,
+                    alt,
+                    span,
+                } => output.push(Control::If {
+                    subject,
+                    body: self.body(body, diagnostics),
+                    else_ifs: else_ifs
+                        .into_iter()
+                        .map(|branch| Branch {
+                            body: self.body(branch.body, diagnostics),
+                            ..branch
+                        })
+                        .collect(),
+                    alt: self.body(alt, diagnostics),
+                    span,
+                }),
+                Control::Iter {
+                    suffix,
+                    subject_raw,
+                    subject,
+                    body,
+                    alt,
+                    span,
+                } => {
+                    self.loops += 1;
+                    let body = self.body(body, diagnostics);
+                    self.loops -= 1;
+
+                    output.push(Control::Iter {
+                        suffix,
+                        subject_raw,
+                        subject,
+                        body,
+                        alt: self.body(alt, diagnostics),
+                        span,
+                    })
+                }
+                Control::Block {
+                    name,
+                    body,
+                    base,
+                    suffix,
+                    span,
+                } => output.push(Control::Block {
+                    name,
+                    body: self.body(body, diagnostics),
+                    base,
+                    suffix,
+                    span,
+                }),
+                Control::Import {
+                    path,
+                    base,
+                    suffix,
+                    span,
+                } => match self.options.imports {
+                    ImportMode::Runtime => {
+                        if self.loops > 0 {
+                            diagnostics.push(
+                                Diagnostic::warning(
+                                    ErrorKind::ImportInLoop,
+                                    span,
+                                    &format!(
+                                        "`{}` is imported at runtime with the root context, \
+                                         not the current element",
+                                        path
+                                    ),
+                                )
+                                .with_note("inline the import to render it within the loop"),
+                            );
+                        }
+
+                        output.push(Control::Raw {
+                            subject: import_call(&path),
+                            span,
+                        })
+                    }
+                    ImportMode::Inline => {
+                        output.append(&mut self.inline(&path, &base, suffix, span, diagnostics))
+                    }
+                },
+                control => output.push(control),
+            }
+        }
+
+        output
+    }
+
+    /// the source of a template used at `span`,
+    /// unless it's already being used, is nested too deeply,
+    /// would use up the budget for imports or can't be resolved
+    fn load(
+        &mut self,
+        path: &str,
+        usage: Usage,
+        span: Span,
+        diagnostics: &mut Diagnostics,
+    ) -> Option<String> {
+        // the template being compiled is open too, if it's named
+        let open = self.options.name.iter().chain(&self.stack);
+        if open.clone().any(|open| open == path) {
+            let mut chain: Vec<&str> = open.map(|open| open.as_str()).collect();
+            chain.push(path);
+            diagnostics.push(
+                Diagnostic::error(
+                    ErrorKind::ImportCycle,
+                    span,
+                    &format!("`{}` {}s itself", path, usage.verb()),
+                )
+                .with_note(&format!(
+                    "{}ed through {}",
+                    usage.verb(),
+                    chain.join(" -> ")
+                )),
+            );
+            return None;
+        }
+
+        if self.stack.len() >= MAX_IMPORT_DEPTH {
+            diagnostics.push(Diagnostic::error(
+                ErrorKind::NestingLimit,
+                span,
+                &format!(
+                    "templates {}ed more than {} deep are ignored",
+                    usage.verb(),
+                    MAX_IMPORT_DEPTH
+                ),
+            ));
+            return None;
+        }
+
+        // nothing more is inlined once the budget runs out,
+        // which is only reported where it does
+        if self.exhausted {
+            return None;
+        }
+
+        match self.resolver.resolve(path) {
+            Ok(source) => {
+                if self.imports >= MAX_IMPORTS
+                    || self.imported_bytes + source.len() > MAX_IMPORTED_BYTES
+                {
+                    self.exhausted = true;
+                    diagnostics.push(
+                        Diagnostic::error(
+                            ErrorKind::NestingLimit,
+                            span,
+                            &format!(
+                                "`{}` isn't {}ed, since too many templates already are",
+                                path,
+                                usage.verb()
+                            ),
+                        )
+                        .with_note(&format!(
+                            "at most {} templates or {} bytes can be imported or extended \
+                             in total, later ones are ignored",
+                            MAX_IMPORTS, MAX_IMPORTED_BYTES
+                        )),
+                    );
+                    return None;
+                }
+
+                self.imports += 1;
+                self.imported_bytes += source.len();
+                Some(source)
+            }
+            Err(reason) => {
+                diagnostics.error(
+                    ErrorKind::UnresolvedImport,
+                    span,
+                    &format!("failed to {} `{}`: {}", usage.verb(), path, reason),
+                );
+                None
+            }
+        }
+    }
+
+    /// the controls of an imported template,
+    /// with diagnostics from it reported at `span`
+    fn inline(
+        &mut self,
+        path: &str,
+        base: &[String],
+        suffix: u16,
+        span: Span,
+        diagnostics: &mut Diagnostics,
+    ) -> Vec<Control> {
+        let source = match self.load(path, Usage::Import, span, diagnostics) {
+            Some(source) => source,
+            None => return Vec::new(),
+        };
+
+        // the partial is parsed within the scope it's imported in,
+        // as if it was written there
+        let mut partial = Diagnostics::new();
+        let (tree, offset_map) = ::parse_mapped(&source, self.options, base, suffix, &mut partial);
+
+        self.stack.push(path.to_string());
+        let mut nested = Diagnostics::new();
+        let mut tree = self.body(tree, &mut nested);
+        self.stack.pop();
+        strip(
+            &mut tree,
+            is_extends,
+            "imported templates can't extend others",
+            &mut nested,
+        );
+        partial.extend(nested.map_spans(|span| offset_map.span(span)));
+        relocate(path, &source, &partial, span, diagnostics);
+
+        // everything inlined comes from the import
+        for control in &mut tree {
+            control.map_spans(&|_| span);
+        }
+
+        tree
+    }
+
+    /// replace the imports within a template,
+    /// then override the blocks of the template it extends, if any
+    fn template(&mut self, tree: Vec<Control>, diagnostics: &mut Diagnostics) -> Vec<Control> {
+        let tree = self.body(tree, diagnostics);
+
+        let mut defined: Vec<(&str, Span)> = Vec::new();
+        blocks(&tree, &mut defined);
+        for (index, (name, span)) in defined.iter().enumerate() {
+            if let Some((_, first)) = defined[..index].iter().find(|(other, _)| other == name) {
+                diagnostics.push(
+                    Diagnostic::error(
+                        ErrorKind::InvalidExtends,
+                        *span,
+                        &format!("block `{}` is defined more than once", name),
+                    )
+                    .with_label(*first, "first defined here"),
+                );
+            }
+        }
+
+        // only a single extends at the top level is used
+        let mut extends: Option<(String, Span)> = None;
+        let mut rest = Vec::with_capacity(tree.len());
+        for mut control in tree {
+            match control {
+                Control::Extends { path, span } => match &extends {
+                    Some((first, first_span)) => diagnostics.push(
+                        Diagnostic::error(
+                            ErrorKind::InvalidExtends,
+                            span,
+                            "a template can only extend one other",
+                        )
+                        .with_label(*first_span, &format!("already extends `{}` here", first)),
+                    ),
+                    None => extends = Some((path, span)),
+                },
+                _ => {
+                    for child in children(&mut control) {
+                        strip(
+                            child,
+                            is_extends,
+                            "`extends` must be at the top level of a template",
+                            diagnostics,
+                        );
+                    }
+                    rest.push(control);
+                }
+            }
+        }
+
+        let (path, span) = match extends {
+            Some(extends) => extends,
+            None => {
+                strip(
+                    &mut rest,
+                    is_super,
+                    "`super` can only be used within a block overriding another",
+                    diagnostics,
+                );
+                return rest;
+            }
+        };
+
+        // everything else is replaced by the extended template
+        let mut overrides = Vec::new();
+        let mut ignored = None;
+        for control in rest {
+            match control {
+                Control::Block { .. } => overrides.push(control),
+                Control::Text { ref value, .. } if value.trim().is_empty() => (),
+                control => {
+                    ignored.get_or_insert(control.span());
+                }
+            }
+        }
+        if let Some(ignored) = ignored {
+            diagnostics.push(
+                Diagnostic::warning(
+                    ErrorKind::InvalidExtends,
+                    ignored,
+                    "content outside of blocks is ignored",
+                )
+                .with_label(span, &format!("the template extends `{}`", path))
+                .with_note("only blocks override the extended template"),
+            );
+        }
+
+        let source = match self.load(&path, Usage::Extend, span, diagnostics) {
+            Some(source) => source,
+            // fall back to the blocks on their own
+            None => return overrides,
+        };
+
+        let mut partial = Diagnostics::new();
+        let (parent, offset_map) = ::parse_mapped(&source, self.options, &[], 1, &mut partial);
+
+        self.stack.push(path.clone());
+        let mut nested = Diagnostics::new();
+        let mut parent = self.template(parent, &mut nested);
+        self.stack.pop();
+        partial.extend(nested.map_spans(|span| offset_map.span(span)));
+        relocate(&path, &source, &partial, span, diagnostics);
+
+        // everything from the extended template comes from the extends
+        for control in &mut parent {
+            control.map_spans(&|_| span);
+        }
+
+        let mut available = Vec::new();
+        blocks(&parent, &mut available);
+        let available: Vec<String> = available
+            .into_iter()
+            .map(|(name, _)| format!("`{}`", name))
+            .collect();
+
+        let mut overridden: Vec<String> = Vec::new();
+        for control in overrides {
+            let (name, body, base, suffix, block_span) = match control {
+                Control::Block {
+                    name,
+                    body,
+                    base,
+                    suffix,
+                    span,
+                } => (name, body, base, suffix, span),
+                _ => continue,
+            };
+            // duplicates were already reported
+            if overridden.contains(&name) {
+                continue;
+            }
+
+            match find_block(&mut parent, &name) {
+                Some(Control::Block {
+                    body: parent_body,
+                    base: parent_base,
+                    suffix: parent_suffix,
+                    ..
+                }) => {
+                    // paths in the override were resolved outside of any `each`
+                    if *parent_base != base || *parent_suffix != suffix {
+                        diagnostics.push(
+                            Diagnostic::error(
+                                ErrorKind::InvalidExtends,
+                                block_span,
+                                &format!("block `{}` is within an `each` in `{}`", name, path),
+                            )
+                            .with_note("only blocks outside of `each` can be overridden"),
+                        );
+                        continue;
+                    }
+
+                    let original = mem::take(parent_body);
+                    *parent_body = replace_super(body, &original);
+                }
+                _ => {
+                    let note = if available.is_empty() {
+                        format!("`{}` has no blocks", path)
+                    } else {
+                        format!("blocks in `{}`: {}", path, available.join(", "))
+                    };
+                    diagnostics.push(
+                        Diagnostic::error(
+                            ErrorKind::UnknownOverride,
+                            block_span,
+                            &format!("`{}` has no block `{}` to override", path, name),
+                        )
+                        .with_note(&note),
+                    );
+                }
+            }
+            overridden.push(name);
+        }
+
+        parent
+    }
+}
+
+/// replace the imports within a parsed template,
+/// inlining templates from `resolver` or calling them at runtime
+/// depending on `options.imports`
+/// then merge it into the template it extends from `resolver`, if any
+/// problems within imported and extended templates are reported
+/// where they're imported or extended
+pub fn resolve(
+    tree: Vec<Control>,
+    options: &Options,
+    resolver: &dyn TemplateResolver,
+    diagnostics: &mut Diagnostics,
+) -> Vec<Control> {
+    Importer {
+        options,
+        resolver,
+        stack: Vec::new(),
+        loops: 0,
+        imports: 0,
+        imported_bytes: 0,
+        exhausted: false,
+    }
+    .template(tree, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diagnostics::Severity;
+    use helpers::HelperRegistry;
+    use json::{self, JsonValue};
+    use render::render_with;
+
+    fn partials(list: &[(&str, &str)]) -> HashMap<String, String> {
+        list.iter()
+            .map(|(path, source)| (path.to_string(), source.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn inline() {
+        let resolver = partials(&[
+            ("post", "<b>{posts.title}</b>{{{ import \"by\" }}}"),
+            ("by", "{@index}"),
+        ]);
+        let template = "{{{ each posts }}}{{{ import \"post\" }}}{{{ end }}}";
+        let output = ::compile_with_resolver(template, &Options::default(), &resolver);
+
+        assert!(output.diagnostics.is_empty());
+        assert_eq!(
+            output.code,
+            ::compile("{{{ each posts }}}<b>{posts.title}</b>{@index}{{{ end }}}")
+        );
+
+        // everything inlined maps to the import
+        let (tree, _) = ::parse_with_resolver(template, &Options::default(), &resolver);
+        match &tree[0] {
+            Control::Iter { body, .. } => {
+                assert!(body
+                    .iter()
+                    .all(|control| control.span() == Span::new(18, 39)))
+            }
+            control => panic!("expected an iteration, found {:?}", control),
+        }
+    }
+
+    #[test]
+    fn runtime() {
+        let options = Options {
+            imports: ImportMode::Runtime,
+            ..Options::default()
+        };
+        let output = ::compile_with(r#"a{{{ import "partials/topic_list" }}}"#, &options);
+
+        assert!(output.diagnostics.is_empty());
+        assert!(output
+            .code
+            .contains(r#"helper(context, helpers, '__import', ["partials/topic_list"])"#));
+
+        let (tree, _) = ::parse_with(r#"a{{{ import "list" }}}"#, &options);
+        let mut helpers = HelperRegistry::new();
+        helpers.register_fn(IMPORT_HELPER, |context: &JsonValue, args: &[&JsonValue]| {
+            format!("<{}:{}>", args[0], context["b"]).into()
+        });
+        let data = json::parse(r#"{ "b": 1 }"#).unwrap();
+        assert_eq!(
+            render_with(&tree, &data, &helpers, &mut Diagnostics::new()),
+            "a<list:1>"
+        );
+    }
+
+    #[test]
+    fn runtime_in_each() {
+        let resolver = partials(&[("post", "{posts.title}")]);
+        let template = "{{{ each posts }}}{{{ import \"post\" }}}{{{ end }}}";
+        let data = json::parse(r#"{ "posts": [{ "title": "a" }, { "title": "b" }] }"#).unwrap();
+
+        let (tree, diagnostics) = ::parse_with_resolver(template, &Options::default(), &resolver);
+        assert!(diagnostics.is_empty());
+        assert_eq!(::render::render(&tree, &data), "ab");
+
+        // the runtime import would only see the root context, so it's reported
+        let options = Options {
+            imports: ImportMode::Runtime,
+            ..Options::default()
+        };
+        let (tree, diagnostics) = ::parse_with_resolver(template, &options, &resolver);
+        let warning = diagnostics.warnings().next().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(warning.code, ErrorKind::ImportInLoop);
+        assert_eq!(warning.span, Span::new(18, 39));
+
+        let mut helpers = HelperRegistry::new();
+        helpers.register_fn(IMPORT_HELPER, |context: &JsonValue, _: &[&JsonValue]| {
+            context["posts"]["title"].to_string().into()
+        });
+        assert_eq!(
+            render_with(&tree, &data, &helpers, &mut Diagnostics::new()),
+            "nullnull"
+        );
+
+        // outside of the loop's body it sees the same context either way
+        let (_, diagnostics) = ::parse_with_resolver(
+            "{{{ each posts }}}{{{ else }}}{{{ import \"post\" }}}{{{ end }}}",
+            &options,
+            &resolver,
+        );
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn unresolved() {
+        let (tree, diagnostics) = ::parse_with(r#"a {{{ import "b" }}}"#, &Options::default());
+
+        assert_eq!(tree.len(), 1);
+        let error = diagnostics.errors().next().unwrap();
+        assert_eq!(error.code, ErrorKind::UnresolvedImport);
+        assert_eq!(error.span, Span::new(2, 20));
+
+        let (_, diagnostics) = ::parse_with(r#"{{{ import b }}}"#, &Options::default());
+        assert_eq!(
+            diagnostics.errors().next().map(|error| error.code),
+            Some(ErrorKind::InvalidExpression)
+        );
+    }
+
+    #[test]
+    fn cycle() {
+        let resolver = partials(&[
+            ("a", "a{{{ import \"b\" }}}"),
+            ("b", "b{{{ import \"a\" }}}"),
+        ]);
+        let (tree, diagnostics) =
+            ::parse_with_resolver(r#"x{{{ import "a" }}}"#, &Options::default(), &resolver);
+
+        assert_eq!(
+            tree.iter()
+                .map(|control| match control {
+                    Control::Text { value, .. } => value.as_str(),
+                    _ => "",
+                })
+                .collect::<String>(),
+            "xab"
+        );
+
+        let error = diagnostics.errors().next().unwrap();
+        assert_eq!(error.code, ErrorKind::ImportCycle);
+        assert_eq!(error.span, Span::new(1, 19));
+        assert_eq!(
+            error.notes,
+            vec!["in a:1:2", "in b:1:2", "imported through a -> b -> a"]
+        );
+
+        // the root template is checked too, when it's named
+        let options = Options {
+            name: Some("a".to_string()),
+            ..Options::default()
+        };
+        let (_, diagnostics) = ::parse_with_resolver(r#"x{{{ import "b" }}}"#, &options, &resolver);
+
+        let error = diagnostics.errors().next().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(error.code, ErrorKind::ImportCycle);
+        assert_eq!(error.span, Span::new(1, 19));
+        assert_eq!(
+            error.notes,
+            vec!["in b:1:2", "imported through a -> b -> a"]
+        );
+    }
+
+    #[test]
+    fn too_deep() {
+        let resolver: BTreeMap<String, String> = (0..MAX_IMPORT_DEPTH * 2)
+            .map(|n| (n.to_string(), format!("{{{{{{ import \"{}\" }}}}}}", n + 1)))
+            .collect();
+        let (_, diagnostics) =
+            ::parse_with_resolver(r#"{{{ import "0" }}}"#, &Options::default(), &resolver);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics.errors().next().map(|error| error.code),
+            Some(ErrorKind::NestingLimit)
+        );
+    }
+
+    #[test]
+    fn too_many() {
+        // each template imports the next four times, so inlining them all
+        // would take 4^15 copies of the last
+        let leaf = MAX_IMPORT_DEPTH - 1;
+        let resolver: BTreeMap<String, String> = (0..leaf)
+            .map(|n| {
+                let import = format!("<p>{{{{{{ import \"{}\" }}}}}}</p>", n + 1);
+                (n.to_string(), import.repeat(4))
+            })
+            .chain(Some((leaf.to_string(), "x".repeat(100))))
+            .collect();
+        let (tree, diagnostics) =
+            ::parse_with_resolver(r#"{{{ import "0" }}}"#, &Options::default(), &resolver);
+
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|error| (error.code, error.span))
+                .collect::<Vec<_>>(),
+            vec![(ErrorKind::NestingLimit, Span::new(0, 18))]
+        );
+        assert!(::generator::generate(tree).len() < MAX_IMPORTED_BYTES * 2);
+
+        // large templates count against the budget too
+        let resolver = partials(&[("a", "a"), ("big", &"x".repeat(MAX_IMPORTED_BYTES))]);
+        let (tree, diagnostics) = ::parse_with_resolver(
+            r#"{{{ import "a" }}}{{{ import "big" }}}{{{ import "a" }}}"#,
+            &Options::default(),
+            &resolver,
+        );
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|error| (error.code, error.span))
+                .collect::<Vec<_>>(),
+            vec![(ErrorKind::NestingLimit, Span::new(18, 38))]
+        );
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn partial_diagnostics() {
+        let resolver = partials(&[("post", "a\n {{{ foo }}}")]);
+        let (_, diagnostics) = ::parse_with_resolver(
+            r#"<p>{{{ import "post" }}}"#,
+            &Options::default(),
+            &resolver,
+        );
+
+        let warning = diagnostics.warnings().next().unwrap();
+        assert_eq!(warning.code, ErrorKind::UnknownBlock);
+        assert_eq!(warning.span, Span::new(3, 24));
+        assert_eq!(warning.notes, vec!["in post:2:2"]);
+    }
+
+    fn render(template: &str, resolver: &dyn TemplateResolver) -> (String, Diagnostics) {
+        let (tree, diagnostics) = ::parse_with_resolver(template, &Options::default(), resolver);
+        let data = json::parse(r#"{"page": "Home", "text": "hi", "posts": [1, 2]}"#).unwrap();
+
+        (
+            render_with(
+                &tree,
+                &data,
+                &HelperRegistry::new(),
+                &mut Diagnostics::new(),
+            ),
+            diagnostics,
+        )
+    }
+
+    #[test]
+    fn extends() {
+        let resolver = partials(&[
+            (
+                "layout",
+                "<title>{{{ block title }}}Site{{{ end }}}</title>{{{ block content }}}{{{ end }}}",
+            ),
+            (
+                "page",
+                "{{{ extends \"layout\" }}}\n{{{ block title }}}{page} - {{{ super }}}{{{ end }}}",
+            ),
+            ("footer", "<footer>{{{ block footer }}}{{{ end }}}</footer>"),
+        ]);
+
+        // blocks render in place without extends
+        let (output, diagnostics) = render(r#"{{{ import "layout" }}}"#, &resolver);
+        assert!(diagnostics.is_empty());
+        assert_eq!(output, "<title>Site</title>");
+
+        let (output, diagnostics) = render(
+            "{{{ extends \"page\" }}}\n\
+             {{{ block content }}}{{{ each posts }}}<p>{text}</p>{{{ end }}}{{{ end }}}\n\
+             {{{ block title }}}{{{ super }}}!{{{ end }}}",
+            &resolver,
+        );
+        assert!(diagnostics.is_empty());
+        assert_eq!(output, "<title>Home - Site!</title><p>hi</p><p>hi</p>");
+
+        // overrides can import templates
+        let (output, diagnostics) = render(
+            "{{{ extends \"footer\" }}}{{{ block footer }}}{{{ import \"layout\" }}}{{{ end }}}",
+            &resolver,
+        );
+        assert!(diagnostics.is_empty());
+        assert_eq!(output, "<footer><title>Site</title></footer>");
+    }
+
+    #[test]
+    fn unknown_override() {
+        let resolver = partials(&[("layout", "{{{ block content }}}{{{ end }}}")]);
+        let (output, diagnostics) = render(
+            r#"{{{ extends "layout" }}}{{{ block sidebar }}}x{{{ end }}}"#,
+            &resolver,
+        );
+
+        assert_eq!(output, "");
+        let error = diagnostics.errors().next().unwrap();
+        assert_eq!(error.code, ErrorKind::UnknownOverride);
+        assert_eq!(error.span, Span::new(24, 45));
+        assert_eq!(error.notes, vec!["blocks in `layout`: `content`"]);
+    }
+
+    #[test]
+    fn block_else() {
+        let (output, diagnostics) = render(
+            "{{{ block a }}}x{{{ else }}}y{{ page }}{{{ else if c }}}z{{{ end }}}-",
+            &partials(&[]),
+        );
+
+        // content after an `else` isn't lost
+        assert_eq!(output, "x{{{ else }}}yHome{{{ else if c }}}z-");
+        assert!(!diagnostics.is_empty());
+        assert!(diagnostics
+            .warnings()
+            .all(|d| d.code == ErrorKind::StrayElse));
+        assert!(!diagnostics.has_errors());
+    }
+
+    #[test]
+    fn invalid_extends() {
+        let resolver = partials(&[
+            (
+                "layout",
+                "{{{ each posts }}}{{{ block post }}}{{{ end }}}{{{ end }}}",
+            ),
+            ("self", "{{{ extends \"self\" }}}"),
+        ]);
+        let codes = |template: &str| {
+            let (_, diagnostics) = render(template, &resolver);
+            diagnostics
+                .iter()
+                .map(|diagnostic| (diagnostic.severity, diagnostic.code))
+                .collect::<Vec<_>>()
+        };
+        let error = |code| vec![(Severity::Error, code)];
+
+        assert_eq!(codes("{{{ super }}}"), error(ErrorKind::InvalidExtends));
+        assert_eq!(
+            codes("{{{ if a }}}{{{ extends \"layout\" }}}{{{ end }}}"),
+            error(ErrorKind::InvalidExtends)
+        );
+        assert_eq!(
+            codes("{{{ extends \"layout\" }}}{{{ extends \"self\" }}}"),
+            error(ErrorKind::InvalidExtends)
+        );
+        assert_eq!(
+            codes("{{{ block a }}}{{{ end }}}{{{ block a }}}{{{ end }}}"),
+            error(ErrorKind::InvalidExtends)
+        );
+        assert_eq!(
+            codes("{{{ extends \"layout\" }}}{{{ block post }}}{{{ end }}}"),
+            error(ErrorKind::InvalidExtends)
+        );
+        assert_eq!(
+            codes("{{{ extends \"layout\" }}}text"),
+            vec![(Severity::Warning, ErrorKind::InvalidExtends)]
+        );
+        assert_eq!(
+            codes("{{{ block a }}}x{{{ else }}}y{{{ end }}}"),
+            vec![(Severity::Warning, ErrorKind::StrayElse)]
+        );
+        assert_eq!(
+            codes("{{{ extends \"self\" }}}"),
+            error(ErrorKind::ImportCycle)
+        );
+        assert_eq!(
+            codes("{{{ extends \"missing\" }}}"),
+            error(ErrorKind::UnresolvedImport)
+        );
+    }
+
+    #[test]
+    fn file_resolver() {
+        let resolver = FileResolver::new(env!("CARGO_MANIFEST_DIR"));
+
+        assert!(resolver.resolve("../README.md").is_err());
+        assert!(resolver.resolve("/etc/hosts").is_err());
+        assert!(resolver.resolve("missing").is_err());
+        assert_eq!(
+            resolver.file("partials/a"),
+            Ok(Path::new(env!("CARGO_MANIFEST_DIR")).join("partials/a.tpl"))
+        );
+        assert!(resolver
+            .extension("toml")
+            .resolve("Cargo")
+            .unwrap()
+            .contains("[package]"));
+    }
+}
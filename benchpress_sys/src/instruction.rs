@@ -1,16 +1,22 @@
+use error::Span;
 use token::Token;
 
 /// template instructions
 /// `{stuff}`, `{{{each people}}}`, etc
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
 pub enum Instruction {
-    Text(String),          // everything that not's an instruction
-    Escaped(Vec<Token>),   // `{stuff}`
-    Raw(Vec<Token>),       // `{{html}}`
-    IfStart(Vec<Token>),   // `{{{if animal.carnivorous}}}`, `<!-- IF animal.carnivorous -->`
-    IterStart(Vec<Token>), // `{{{each people}}}`, `<!-- BEGIN peopl -->`
-    Else,                  // `{{{else}}}`, `<!-- ELSE -->`
-    End(Vec<Token>),       // `{{{end}}}`, `<!-- END -->`, `<!-- ENDIF animal.carnivorous -->`
+    Text(String),           // everything that not's an instruction
+    Escaped(Vec<Token>),    // `{stuff}`
+    Raw(Vec<Token>),        // `{{html}}`
+    IfStart(Vec<Token>),    // `{{{if animal.carnivorous}}}`, `<!-- IF animal.carnivorous -->`
+    IterStart(Vec<Token>),  // `{{{each people}}}`, `<!-- BEGIN peopl -->`
+    Else,                   // `{{{else}}}`, `<!-- ELSE -->`
+    ElseIf(Vec<Token>),     // `{{{else if animal.fish}}}`, `<!-- ELSE IF animal.fish -->`
+    End(Vec<Token>),        // `{{{end}}}`, `<!-- END -->`, `<!-- ENDIF animal.carnivorous -->`
+    Import(Vec<Token>),     // `{{{import "partials/topic_list"}}}`
+    Extends(Vec<Token>),    // `{{{extends "layout"}}}`
+    BlockStart(Vec<Token>), // `{{{block content}}}`
+    Super,                  // `{{{super}}}`
 }
 
 /// a wrapper for Instructions, containing source position information
@@ -29,4 +35,9 @@ impl InstructionPos {
     pub fn get_source(&self, source: &str) -> String {
         source[self.start..self.end].to_string()
     }
+
+    /// get the source position of this instruction
+    pub fn span(&self) -> Span {
+        Span::new(self.start, self.end)
+    }
 }
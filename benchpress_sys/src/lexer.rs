@@ -1,3 +1,6 @@
+use diagnostics::{Diagnostic, Diagnostics};
+use error::{ErrorKind, Span};
+use options::MalformedPolicy;
 use token::{Token, TokenPos};
 
 /// iterate a slice over a string
@@ -30,14 +33,8 @@ impl<'a> StringSlicer<'a> {
 
     /// reset slice to length of 1
     fn reset(&mut self) {
-        self.end = self.start + 1;
-        if self.end > self.len {
-            self.end = self.len;
-        } else {
-            while !self.source.is_char_boundary(self.end) && self.end < self.len {
-                self.end += 1;
-            }
-        }
+        self.end = self.start;
+        self.grow();
     }
 
     /// move the beginning right one, reset length to 1
@@ -48,26 +45,39 @@ impl<'a> StringSlicer<'a> {
 
     /// step by `inc` units
     fn step_by(&mut self, inc: usize) {
-        self.grow_by(inc - 1);
+        self.grow_by(inc.saturating_sub(1));
         self.step();
     }
 
+    /// move the beginning to `offset`, reset length to 1
+    fn step_to(&mut self, offset: usize) {
+        self.start = offset.min(self.len);
+        while !self.source.is_char_boundary(self.start) {
+            self.start += 1;
+        }
+        self.reset();
+    }
+
+    /// check if the slice reaches the end of the source
+    fn at_end(&self) -> bool {
+        self.end >= self.len
+    }
+
     /// increment right end of slice, keeping the beginning in place
+    /// the slice never grows past the end of the source
     fn grow(&mut self) {
-        while {
+        if self.end < self.len {
             self.end += 1;
-            !self.source.is_char_boundary(self.end) && self.end < self.len
-        } {}
+            while !self.source.is_char_boundary(self.end) {
+                self.end += 1;
+            }
+        }
     }
 
     /// grow by `inc` units
     fn grow_by(&mut self, inc: usize) {
-        if self.end + inc > self.len {
-            self.end = self.len;
-        } else {
-            for _ in 0..inc {
-                self.grow();
-            }
+        for _ in 0..inc {
+            self.grow();
         }
     }
 
@@ -104,9 +114,105 @@ fn is_simple_char(ch: char) -> bool {
     ch.is_alphabetic() || ch.is_numeric() || matches!(ch, '@' | '/' | '_' | ':' | '\\' | '-' | '.')
 }
 
+/// how deeply negations, helper calls and groups
+/// can be nested in an expression
+pub const MAX_EXPRESSION_DEPTH: usize = 64;
+
+/// how many binary operators an expression can have in total,
+/// since each one nests the operands before it one level deeper once parsed
+pub const MAX_EXPRESSION_OPERATORS: usize = 256;
+
 /// lex an expression from the current slice position
 /// return an option of the token vector representing the expression
 fn lex_expression(slicer: &mut StringSlicer) -> Option<Vec<TokenPos>> {
+    lex_nested_expression(slicer, 0, &mut 0, true)
+}
+
+/// lex the subject of an interpolation from the current slice position
+/// binary operators and groups are only allowed in block subjects,
+/// so inline scripts and styles like `{a < b}` are left as text
+fn lex_interpolation(slicer: &mut StringSlicer) -> Option<Vec<TokenPos>> {
+    lex_nested_expression(slicer, 0, &mut 0, false)
+}
+
+/// lex an expression nested `depth` levels deep within another,
+/// counting its binary operators in `operators`
+/// expressions nested too deeply or with too many operators are rejected,
+/// as are operators and groups unless `binary` is set
+fn lex_nested_expression(
+    slicer: &mut StringSlicer,
+    depth: usize,
+    operators: &mut usize,
+    binary: bool,
+) -> Option<Vec<TokenPos>> {
+    let mut output = lex_operand(slicer, depth, operators, binary)?;
+    if !binary {
+        return Some(output);
+    }
+
+    while let Some(operator) = lex_operator(slicer) {
+        // the input ends without a right operand
+        if slicer.slice().is_empty() {
+            return None;
+        }
+
+        *operators += 1;
+        if *operators > MAX_EXPRESSION_OPERATORS {
+            return None;
+        }
+
+        output.push(operator);
+        output.append(&mut lex_operand(slicer, depth, operators, binary)?);
+    }
+
+    Some(output)
+}
+
+/// lex a binary operator after the current slice position, if there is one
+/// the slicer is left in place if there isn't
+fn lex_operator(slicer: &mut StringSlicer) -> Option<TokenPos> {
+    let mut ahead = slicer.clone();
+    ahead.skip_spaces();
+
+    let slice = ahead.slice();
+    let tok = match slice.as_str() {
+        "=" if ahead.followed_by("=") => Token::Equal,
+        "!" if ahead.followed_by("=") => Token::NotEqual,
+        "<" if ahead.followed_by("=") => Token::LessEqual,
+        "<" => Token::Less,
+        ">" if ahead.followed_by("=") => Token::GreaterEqual,
+        ">" => Token::Greater,
+        "&" if ahead.followed_by("&") => Token::And,
+        "|" if ahead.followed_by("|") => Token::Or,
+        _ => return None,
+    };
+    if !matches!(tok, Token::Less | Token::Greater) {
+        ahead.grow();
+    }
+
+    let operator = TokenPos {
+        start: ahead.start,
+        end: ahead.end,
+        tok,
+    };
+    ahead.step();
+    ahead.skip_spaces();
+    *slicer = ahead;
+
+    Some(operator)
+}
+
+/// lex a single operand of an expression, nested `depth` levels deep
+fn lex_operand(
+    slicer: &mut StringSlicer,
+    depth: usize,
+    operators: &mut usize,
+    binary: bool,
+) -> Option<Vec<TokenPos>> {
+    if depth > MAX_EXPRESSION_DEPTH {
+        return None;
+    }
+
     let mut output: Vec<TokenPos> = Vec::new();
 
     slicer.skip_spaces();
@@ -123,7 +229,7 @@ fn lex_expression(slicer: &mut StringSlicer) -> Option<Vec<TokenPos>> {
 
                 match string_lit.chars().last() {
                     // grow to include backslash and escaped char
-                    Some('\\') => slicer.grow_by(2),
+                    Some('\\') if !slicer.at_end() => slicer.grow_by(2),
                     // finish the string
                     Some('"') => {
                         // skip last character
@@ -136,8 +242,8 @@ fn lex_expression(slicer: &mut StringSlicer) -> Option<Vec<TokenPos>> {
                             tok: Token::StringLiteral(string_lit),
                         }]);
                     }
-                    Some(_) => slicer.grow(),
-                    None => return None,
+                    Some(_) if !slicer.at_end() => slicer.grow(),
+                    _ => return None,
                 }
             }
         }
@@ -150,12 +256,39 @@ fn lex_expression(slicer: &mut StringSlicer) -> Option<Vec<TokenPos>> {
             });
             slicer.step();
 
-            if let Some(mut sub_expr) = lex_expression(slicer) {
+            if let Some(mut sub_expr) = lex_operand(slicer, depth + 1, operators, binary) {
                 output.append(&mut sub_expr);
             } else {
                 return None;
             }
         }
+        // group (`(a || b)`)
+        "(" if binary => {
+            output.push(TokenPos {
+                start: slicer.start,
+                end: slicer.end,
+                tok: Token::LeftParen,
+            });
+            slicer.step();
+
+            if let Some(mut sub_expr) = lex_nested_expression(slicer, depth + 1, operators, binary)
+            {
+                output.append(&mut sub_expr);
+            } else {
+                return None;
+            }
+
+            if slicer.slice() == ")" {
+                output.push(TokenPos {
+                    start: slicer.start,
+                    end: slicer.end,
+                    tok: Token::RightParen,
+                });
+                slicer.step();
+            } else {
+                return None;
+            }
+        }
         // identifier or helper
         _ => {
             if slice.chars().all(|ch| ch != '-' && is_simple_char(ch)) {
@@ -198,7 +331,9 @@ fn lex_expression(slicer: &mut StringSlicer) -> Option<Vec<TokenPos>> {
                         });
                         slicer.step();
 
-                        if let Some(mut arg) = lex_expression(slicer) {
+                        if let Some(mut arg) =
+                            lex_nested_expression(slicer, depth + 1, operators, binary)
+                        {
                             output.append(&mut arg);
                         }
                         // allow a trailing comma
@@ -228,7 +363,9 @@ fn lex_expression(slicer: &mut StringSlicer) -> Option<Vec<TokenPos>> {
                         while {
                             slicer.step();
 
-                            if let Some(mut arg) = lex_expression(slicer) {
+                            if let Some(mut arg) =
+                                lex_nested_expression(slicer, depth + 1, operators, binary)
+                            {
                                 output.append(&mut arg);
                             }
                             // allow a trailing comma
@@ -270,7 +407,104 @@ fn lex_expression(slicer: &mut StringSlicer) -> Option<Vec<TokenPos>> {
     Some(output)
 }
 
-/// lex a block (`if expr`, `each expr`, `else`, `end`)
+/// get the block keyword at the start of the input, if any
+fn block_keyword(input: &str) -> Option<&str> {
+    let word = input.split(|ch: char| !ch.is_alphabetic()).next()?;
+
+    if matches!(
+        word,
+        "if" | "each" | "else" | "end" | "import" | "extends" | "block" | "super"
+    ) {
+        Some(word)
+    } else {
+        None
+    }
+}
+
+/// find the end of a block opened before `from`
+/// stopping at the next block opener
+fn block_end(input: &str, from: usize) -> Option<usize> {
+    let rest = &input[from..];
+    let end = rest.find("}}}")?;
+
+    match rest.find("{{{") {
+        Some(next) if next < end => None,
+        _ => Some(from + end + 3),
+    }
+}
+
+/// if a comment is opened at `start`, the offsets of its content
+/// and its end, which is `None` if the comment is never closed
+///
+/// `{{{! ... }}}` comments can contain other blocks and end at the matching `}}}`,
+/// while `<!-- # ... -->` comments end at the first `-->` like HTML comments
+/// (the space keeps `<!--#include -->` server-side includes as text)
+fn comment(input: &str, start: usize) -> Option<(usize, Option<(usize, usize)>)> {
+    let rest = &input[start..];
+
+    if rest.starts_with("{{{!") {
+        let content = start + 4;
+        let mut depth = 1;
+        let mut offset = content;
+
+        loop {
+            let rest = &input[offset..];
+            let close = match rest.find("}}}") {
+                Some(close) => close,
+                None => return Some((content, None)),
+            };
+            match rest.find("{{{") {
+                Some(open) if open < close => {
+                    depth += 1;
+                    offset += open + 3;
+                }
+                _ => {
+                    depth -= 1;
+                    offset += close + 3;
+
+                    if depth == 0 {
+                        return Some((content, Some((offset - 3, offset))));
+                    }
+                }
+            }
+        }
+    } else if rest.starts_with("<!-- ") && rest[4..].trim_start_matches(' ').starts_with('#') {
+        let content = start + rest.find('#')? + 1;
+        let end = input[content..]
+            .find("-->")
+            .map(|len| (content + len, content + len + 3));
+
+        Some((content, end))
+    } else {
+        None
+    }
+}
+
+/// the spans of every closed comment in `input`, so they can be left alone
+pub fn comments(input: &str) -> Vec<Span> {
+    let mut output = Vec::new();
+    let mut offset = 0;
+
+    while let Some(found) = input[offset..].find(['{', '<']) {
+        let start = offset + found;
+        offset = start + 1;
+
+        // escaped openers aren't comments
+        if input[..start].ends_with('\\') {
+            continue;
+        }
+
+        if let Some((_, Some((_, end)))) = comment(input, start) {
+            output.push(Span::new(start, end));
+            offset = end;
+        }
+    }
+
+    output
+}
+
+/// lex a block (`if expr`, `each expr`, `else`, `else if expr`, `end`,
+/// `import "path"`, `extends "path"`, `block name`, `super`)
 fn lex_block(slicer: &mut StringSlicer, legacy: bool) -> Option<Vec<TokenPos>> {
     let mut output: Vec<TokenPos> = Vec::new();
 
@@ -350,6 +584,62 @@ fn lex_block(slicer: &mut StringSlicer, legacy: bool) -> Option<Vec<TokenPos>> {
             }
             // end subject is optional
         }
+        // import tokens
+        "imp" if !legacy && slicer.followed_by("ort ") => {
+            slicer.grow_by(3);
+            output.push(TokenPos {
+                start: slicer.start,
+                end: slicer.end,
+                tok: Token::Import,
+            });
+            slicer.step();
+            slicer.skip_spaces();
+
+            if let Some(mut expr) = lex_expression(slicer) {
+                output.append(&mut expr);
+            } else {
+                return None;
+            }
+        }
+        // inheritance tokens
+        "ext" | "blo"
+            if !legacy
+                && match slice.as_str() {
+                    "ext" => slicer.followed_by("ends "),
+                    // "blo"
+                    _ => slicer.followed_by("ck "),
+                } =>
+        {
+            let tok = if slice == "ext" {
+                slicer.grow_by(4);
+                Token::Extends
+            } else {
+                slicer.grow_by(2);
+                Token::Block
+            };
+            output.push(TokenPos {
+                start: slicer.start,
+                end: slicer.end,
+                tok,
+            });
+            slicer.step();
+            slicer.skip_spaces();
+
+            if let Some(mut expr) = lex_expression(slicer) {
+                output.append(&mut expr);
+            } else {
+                return None;
+            }
+        }
+        "sup" if !legacy && slicer.followed_by("er") => {
+            slicer.grow_by(2);
+            output.push(TokenPos {
+                start: slicer.start,
+                end: slicer.end,
+                tok: Token::Super,
+            });
+            slicer.step();
+        }
         // else tokens
         "els" | "ELS" => {
             if match slice.as_str() {
@@ -358,12 +648,31 @@ fn lex_block(slicer: &mut StringSlicer, legacy: bool) -> Option<Vec<TokenPos>> {
                 _ => legacy && slicer.followed_by("E"),
             } {
                 slicer.grow();
-                output.push(TokenPos {
-                    start: slicer.start,
-                    end: slicer.end,
-                    tok: Token::Else,
-                });
-                slicer.step();
+
+                // `else if expr`, `ELSE IF expr`
+                if slicer.followed_by(if legacy { " IF " } else { " if " }) {
+                    slicer.grow_by(3);
+                    output.push(TokenPos {
+                        start: slicer.start,
+                        end: slicer.end,
+                        tok: Token::ElseIf,
+                    });
+                    slicer.step();
+                    slicer.skip_spaces();
+
+                    if let Some(mut expr) = lex_expression(slicer) {
+                        output.append(&mut expr);
+                    } else {
+                        return None;
+                    }
+                } else {
+                    output.push(TokenPos {
+                        start: slicer.start,
+                        end: slicer.end,
+                        tok: Token::Else,
+                    });
+                    slicer.step();
+                }
             } else {
                 return None;
             }
@@ -379,7 +688,13 @@ fn lex_block(slicer: &mut StringSlicer, legacy: bool) -> Option<Vec<TokenPos>> {
 }
 
 /// lex the input string into Tokens
-pub fn lex(input: &str) -> Vec<TokenPos> {
+/// malformed blocks are reported to `diagnostics`
+/// and passed through as text or dropped depending on `malformed`
+pub fn lex(
+    input: &str,
+    malformed: MalformedPolicy,
+    diagnostics: &mut Diagnostics,
+) -> Vec<TokenPos> {
     let mut output: Vec<TokenPos> = vec![];
     let mut slicer = StringSlicer::new(input);
     let length = slicer.len;
@@ -418,7 +733,7 @@ pub fn lex(input: &str) -> Vec<TokenPos> {
                     let mut copy = slicer.clone();
                     copy.step();
 
-                    let valid = if let Some(mut tokens) = lex_expression(&mut copy) {
+                    let valid = if let Some(mut tokens) = lex_interpolation(&mut copy) {
                         let closer = match slice.as_str() {
                             "{" => "}",
                             // "{{"
@@ -462,7 +777,7 @@ pub fn lex(input: &str) -> Vec<TokenPos> {
                             output.append(&mut tokens);
                             output.push(close_token);
 
-                            slicer.step_by(copy.end - orig_end + 1);
+                            slicer.step_to(copy.end);
 
                             true
                         } else {
@@ -482,6 +797,41 @@ pub fn lex(input: &str) -> Vec<TokenPos> {
                     }
                 }
             }
+            // comments
+            "<!--" | "{{{" if comment(input, slicer.start).is_some() => {
+                let start = slicer.start;
+
+                match comment(input, start) {
+                    Some((content, Some((close, end)))) => {
+                        output.push(TokenPos {
+                            start,
+                            end,
+                            tok: Token::Comment(input[content..close].to_string()),
+                        });
+                        slicer.step_to(end);
+                    }
+                    _ => {
+                        // legacy comments are indistinguishable from HTML comments
+                        if slice == "{{{" {
+                            diagnostics.push(
+                                Diagnostic::error(
+                                    ErrorKind::UnterminatedInstruction,
+                                    Span::new(start, slicer.end + 1),
+                                    "comment is never closed",
+                                )
+                                .with_note("close it with `}}}`"),
+                            );
+                        }
+
+                        output.push(TokenPos {
+                            start,
+                            end: slicer.end,
+                            tok: Token::Text(slice),
+                        });
+                        slicer.step();
+                    }
+                }
+            }
             // modern or legacy block
             "<!--" | "{{{" => {
                 let start = slicer.start;
@@ -493,8 +843,6 @@ pub fn lex(input: &str) -> Vec<TokenPos> {
                 let legacy = slice == "<!--";
 
                 let valid = if let Some(mut tokens) = lex_block(&mut copy, legacy) {
-                    let closer_len = 3;
-
                     let closer = if legacy { "-->" } else { "}}}" };
 
                     copy.grow_by(2);
@@ -512,7 +860,7 @@ pub fn lex(input: &str) -> Vec<TokenPos> {
                             tok: Token::BlockClose,
                         });
 
-                        slicer.step_by(copy.end + closer_len - orig_end - 2);
+                        slicer.step_to(copy.end);
 
                         true
                     } else {
@@ -523,12 +871,45 @@ pub fn lex(input: &str) -> Vec<TokenPos> {
                 };
 
                 if !valid {
-                    output.push(TokenPos {
-                        start: slicer.start,
-                        end: slicer.end,
-                        tok: Token::Text(slice),
-                    });
-                    slicer.step();
+                    let mut dropped = None;
+
+                    // legacy blocks are indistinguishable from HTML comments
+                    if !legacy {
+                        let rest = input[orig_end..].trim_start_matches(' ');
+                        let end = block_end(input, orig_end);
+                        let span = Span::new(start, end.unwrap_or(orig_end));
+
+                        if let Some(keyword) = block_keyword(rest) {
+                            diagnostics.error(
+                                ErrorKind::InvalidBlock,
+                                span,
+                                &format!("invalid `{}` block {}", keyword, malformed.outcome()),
+                            );
+                            dropped = end;
+                        } else if rest.starts_with(char::is_alphabetic) {
+                            diagnostics.push(Diagnostic::new(
+                                malformed.severity(),
+                                ErrorKind::UnknownBlock,
+                                span,
+                                &format!("unknown block keyword, {}", malformed.outcome()),
+                            ));
+                            dropped = end;
+                        }
+                    }
+
+                    match dropped {
+                        Some(end) if malformed == MalformedPolicy::Drop => {
+                            slicer.step_to(end);
+                        }
+                        _ => {
+                            output.push(TokenPos {
+                                start: slicer.start,
+                                end: slicer.end,
+                                tok: Token::Text(slice),
+                            });
+                            slicer.step();
+                        }
+                    }
                 }
             }
             // text
@@ -684,6 +1065,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn operators() {
+        assert_eq!(
+            to_tokens(lex_expression(&mut StringSlicer::new(
+                "a == \"b\" && !(c<=d || e)"
+            ))),
+            vec![
+                Token::Identifier("a".to_string()),
+                Token::Equal,
+                Token::StringLiteral("b".to_string()),
+                Token::And,
+                Token::Bang,
+                Token::LeftParen,
+                Token::Identifier("c".to_string()),
+                Token::LessEqual,
+                Token::Identifier("d".to_string()),
+                Token::Or,
+                Token::Identifier("e".to_string()),
+                Token::RightParen,
+            ]
+        );
+
+        assert_eq!(
+            to_tokens(lex_expression(&mut StringSlicer::new(
+                "a != b > c >= d < e"
+            ))),
+            vec![
+                Token::Identifier("a".to_string()),
+                Token::NotEqual,
+                Token::Identifier("b".to_string()),
+                Token::Greater,
+                Token::Identifier("c".to_string()),
+                Token::GreaterEqual,
+                Token::Identifier("d".to_string()),
+                Token::Less,
+                Token::Identifier("e".to_string()),
+            ]
+        );
+
+        // operators need both operands, and groups need closing
+        assert_eq!(lex_expression(&mut StringSlicer::new("a ==")), None);
+        assert_eq!(lex_expression(&mut StringSlicer::new("(a || b")), None);
+        assert_eq!(
+            lex_expression(&mut StringSlicer::new("a = b")).map(|t| t.len()),
+            Some(1)
+        );
+    }
+
     #[test]
     fn identifier() {
         assert_eq!(
@@ -839,6 +1268,68 @@ mod tests {
         );
     }
 
+    #[test]
+    fn import_block() {
+        assert_eq!(
+            to_tokens(lex_block(
+                &mut StringSlicer::new("import \"partials/topic_list\""),
+                false
+            )),
+            vec![
+                Token::Import,
+                Token::StringLiteral("partials/topic_list".to_string()),
+            ]
+        );
+        assert_eq!(
+            lex_block(&mut StringSlicer::new("import \"a\""), true),
+            None
+        );
+    }
+
+    #[test]
+    fn inheritance_blocks() {
+        assert_eq!(
+            to_tokens(lex_block(
+                &mut StringSlicer::new("extends \"layout\""),
+                false
+            )),
+            vec![Token::Extends, Token::StringLiteral("layout".to_string())]
+        );
+        assert_eq!(
+            to_tokens(lex_block(&mut StringSlicer::new("block content"), false)),
+            vec![Token::Block, Token::Identifier("content".to_string())]
+        );
+        assert_eq!(
+            to_tokens(lex_block(&mut StringSlicer::new("super"), false)),
+            vec![Token::Super]
+        );
+        assert_eq!(
+            lex_block(&mut StringSlicer::new("block content"), true),
+            None
+        );
+        assert_eq!(
+            lex_block(&mut StringSlicer::new("extck content"), false),
+            None
+        );
+    }
+
+    #[test]
+    fn else_if_block() {
+        assert_eq!(
+            to_tokens(lex_block(&mut StringSlicer::new("else if b"), false)),
+            vec![Token::ElseIf, Token::Identifier("b".to_string())]
+        );
+        assert_eq!(
+            to_tokens(lex_block(&mut StringSlicer::new("ELSE IF b"), true)),
+            vec![Token::ElseIf, Token::Identifier("b".to_string())]
+        );
+        assert_eq!(
+            to_tokens(lex_block(&mut StringSlicer::new("else"), false)),
+            vec![Token::Else]
+        );
+        assert_eq!(lex_block(&mut StringSlicer::new("ELSE IF b"), false), None);
+    }
+
     #[test]
     fn legacy_block() {
         assert_eq!(
@@ -861,6 +1352,165 @@ mod tests {
             }
         }
 
-        assert_eq!(lex(&text)[0].tok, Token::Text(text));
+        assert_eq!(
+            lex(&text, MalformedPolicy::Text, &mut Diagnostics::new())[0].tok,
+            Token::Text(text)
+        );
+    }
+
+    fn lex_text(input: &str) -> String {
+        lex(input, MalformedPolicy::Text, &mut Diagnostics::new())
+            .into_iter()
+            .map(|TokenPos { start, end, .. }| &input[start..end])
+            .collect()
+    }
+
+    #[test]
+    fn operators_in_interpolations() {
+        // inline scripts and styles stay text, operators are only lexed in block subjects
+        for input in &[
+            "{a < b}",
+            "{x && y}",
+            "{{ a || b }}",
+            "{(a)}",
+            "{f(a == b)}",
+        ] {
+            let mut diagnostics = Diagnostics::new();
+            let tokens = lex(input, MalformedPolicy::Text, &mut diagnostics);
+
+            assert!(
+                tokens.iter().all(|t| matches!(t.tok, Token::Text(_))),
+                "{}",
+                input
+            );
+            assert_eq!(lex_text(input), *input);
+            assert!(diagnostics.is_empty(), "{}", input);
+        }
+
+        assert!(lex(
+            "{{{ if a < b }}}<!-- IF x && y -->",
+            MalformedPolicy::Text,
+            &mut Diagnostics::new()
+        )
+        .iter()
+        .any(|t| t.tok == Token::And));
+    }
+
+    #[test]
+    fn unicode_at_end() {
+        // slices used to grow past the end of multi-byte input
+        for input in &["{{é", "!./{{foo", "{é", "{{{ if é", "{\"\\", "{ \"é\\"] {
+            assert_eq!(&lex_text(input), input);
+        }
+    }
+
+    #[test]
+    fn unicode_subject() {
+        // text after a multi-byte subject used to be skipped
+        assert_eq!(
+            lex("{é}abc", MalformedPolicy::Text, &mut Diagnostics::new()).last(),
+            Some(&TokenPos {
+                start: 4,
+                end: 7,
+                tok: Token::Text("abc".to_string())
+            })
+        );
+        assert_eq!(
+            lex(
+                "{{{ if é }}}abc",
+                MalformedPolicy::Text,
+                &mut Diagnostics::new()
+            )
+            .last(),
+            Some(&TokenPos {
+                start: 13,
+                end: 16,
+                tok: Token::Text("abc".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn nested_too_deep() {
+        let bangs = format!("{}a", "!".repeat(MAX_EXPRESSION_DEPTH * 100));
+        assert_eq!(lex_expression(&mut StringSlicer::new(&bangs)), None);
+
+        let calls = format!(
+            "{}{}",
+            "a(".repeat(MAX_EXPRESSION_DEPTH * 100),
+            ")".repeat(MAX_EXPRESSION_DEPTH * 100)
+        );
+        assert_eq!(lex_expression(&mut StringSlicer::new(&calls)), None);
+
+        let groups = format!(
+            "{}a{}",
+            "(".repeat(MAX_EXPRESSION_DEPTH * 100),
+            ")".repeat(MAX_EXPRESSION_DEPTH * 100)
+        );
+        assert_eq!(lex_expression(&mut StringSlicer::new(&groups)), None);
+
+        let operators = format!("a{}", " || a".repeat(MAX_EXPRESSION_OPERATORS + 1));
+        assert_eq!(lex_expression(&mut StringSlicer::new(&operators)), None);
+
+        let grouped = format!(
+            "a{}",
+            " || (a || a)".repeat(MAX_EXPRESSION_OPERATORS / 2 + 1)
+        );
+        assert_eq!(lex_expression(&mut StringSlicer::new(&grouped)), None);
+
+        let bangs = format!("{}a", "!".repeat(MAX_EXPRESSION_DEPTH));
+        assert!(lex_expression(&mut StringSlicer::new(&bangs)).is_some());
+
+        // a flat chain doesn't nest its operands
+        let chain = format!("a{}", " || a".repeat(MAX_EXPRESSION_DEPTH * 2));
+        assert!(lex_expression(&mut StringSlicer::new(&chain)).is_some());
+    }
+
+    #[test]
+    fn comments_test() {
+        let tokens = |input| {
+            lex(input, MalformedPolicy::Text, &mut Diagnostics::new())
+                .into_iter()
+                .map(|TokenPos { tok, .. }| tok)
+                .collect::<Vec<Token>>()
+        };
+
+        assert_eq!(
+            tokens("a{{{! note\n {{{ if b }}}{c}{{{ end }}} }}}d"),
+            vec![
+                Token::Text("a".to_string()),
+                Token::Comment(" note\n {{{ if b }}}{c}{{{ end }}} ".to_string()),
+                Token::Text("d".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokens("<!-- # {a} -->b"),
+            vec![
+                Token::Comment(" {a} ".to_string()),
+                Token::Text("b".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokens("<!--#include virtual=\"a\" -->"),
+            vec![Token::Text("<!--#include virtual=\"a\" -->".to_string())]
+        );
+        assert_eq!(
+            tokens("\\{{{! a }}}"),
+            vec![Token::Text("{{{! a }}}".to_string())]
+        );
+
+        let mut diagnostics = Diagnostics::new();
+        let input = "a {{{! b {{{ c }}}";
+        assert_eq!(lex_text(input), input);
+        lex(input, MalformedPolicy::Text, &mut diagnostics);
+        assert_eq!(
+            diagnostics.errors().next().map(|err| err.span),
+            Some(Span::new(2, 6))
+        );
+
+        assert_eq!(
+            comments("{{{! a }}} \\<!-- # b --> <!-- # c -->"),
+            vec![Span::new(0, 10), Span::new(25, 37)]
+        );
     }
 }
@@ -1,23 +1,625 @@
 #[macro_use]
 extern crate lazy_static;
 extern crate itertools;
-extern crate json;
+pub extern crate json;
 extern crate regex;
+extern crate unicode_width;
 
+pub mod build;
+pub mod context;
+pub mod declaration;
+pub mod diagnostics;
+pub mod error;
 pub mod generator;
+pub mod helpers;
+pub mod imports;
 pub mod instruction;
 pub mod lexer;
+pub mod minify;
+pub mod options;
 pub mod parser;
 pub mod paths;
 pub mod pre_fixer;
+pub mod render;
+pub mod report;
+pub mod rust_generator;
+pub mod source_map;
 pub mod templates;
 pub mod token;
 
+use diagnostics::{Diagnostics, Severity};
+use error::CompileError;
+use imports::{NoResolver, TemplateResolver};
+use instruction::InstructionPos;
+pub use options::{ImportMode, MalformedPolicy, Options, OutputFormat, Target};
+use parser::Control;
+use pre_fixer::OffsetMap;
+use token::TokenPos;
+
+/// generated code along with any diagnostics reported while compiling
+/// diagnostic spans refer to the original template
+#[derive(Debug, PartialEq, Clone)]
+pub struct Output {
+    pub code: String,
+    pub diagnostics: Diagnostics,
+    /// version 3 source map for the code, if requested
+    pub source_map: Option<String>,
+    /// TypeScript declaration of the context, if requested
+    pub declaration: Option<String>,
+}
+
+/// every intermediate stage of parsing a template
+/// spans within the stages refer to `pre_fixed`,
+/// which `offset_map` maps back to the template
+pub struct Stages {
+    pub pre_fixed: String,
+    pub offset_map: OffsetMap,
+    pub lexed: Vec<TokenPos>,
+    pub first_parsed: Vec<InstructionPos>,
+    /// instructions after fixing or checking blocks and limiting depth
+    pub instructions: Vec<InstructionPos>,
+    pub tree: Vec<Control>,
+}
+
+/// run every stage of parsing a template within the scope of `base` and `suffix`
+/// imports are left for `imports::resolve`
+fn parse_stages_within(
+    template: &str,
+    options: &Options,
+    base: &[String],
+    suffix: u16,
+    diagnostics: &mut Diagnostics,
+) -> Stages {
+    let (pre_fixed, offset_map) = pre_fixer::pre_fix(template, diagnostics);
+
+    // later phases report positions within the pre-fixed source
+    let mut fixed_diagnostics = Diagnostics::new();
+    let lexed = lexer::lex(&pre_fixed, options.malformed, &mut fixed_diagnostics);
+    let first_parsed =
+        parser::parse_instructions(&pre_fixed, lexed.clone(), &mut fixed_diagnostics);
+    let instructions = if options.strict {
+        parser::check_blocks(
+            &pre_fixed,
+            &first_parsed,
+            Severity::Error,
+            &mut fixed_diagnostics,
+        );
+        first_parsed.clone()
+    } else {
+        let extras_fixed = parser::fix_extra_instructions(
+            &pre_fixed,
+            first_parsed.clone(),
+            &mut fixed_diagnostics,
+        );
+        parser::check_blocks(
+            &pre_fixed,
+            &extras_fixed,
+            Severity::Warning,
+            &mut fixed_diagnostics,
+        );
+        extras_fixed
+    };
+    let instructions = parser::limit_depth(&pre_fixed, instructions, &mut fixed_diagnostics);
+    let (tree, _) = parser::parse_tree(
+        &pre_fixed,
+        &mut instructions.clone().into_iter(),
+        base,
+        suffix,
+        options.malformed,
+        &mut fixed_diagnostics,
+    );
+    diagnostics.extend(fixed_diagnostics.map_spans(|span| offset_map.span(span)));
+
+    Stages {
+        pre_fixed,
+        offset_map,
+        lexed,
+        first_parsed,
+        instructions,
+        tree,
+    }
+}
+
+/// parse a template into controls within the scope of `base` and `suffix`,
+/// along with the map from the pre-fixed source back to the template
+/// imports are left for `imports::resolve`
+fn parse_mapped(
+    template: &str,
+    options: &Options,
+    base: &[String],
+    suffix: u16,
+    diagnostics: &mut Diagnostics,
+) -> (Vec<Control>, OffsetMap) {
+    let stages = parse_stages_within(template, options, base, suffix, diagnostics);
+
+    (stages.tree, stages.offset_map)
+}
+
+/// run every stage of parsing a template, with imports replaced in the tree
+fn parse_resolved(
+    template: &str,
+    options: &Options,
+    resolver: &dyn TemplateResolver,
+    diagnostics: &mut Diagnostics,
+) -> Stages {
+    let mut stages = parse_stages_within(template, options, &[], 1, diagnostics);
+
+    let mut import_diagnostics = Diagnostics::new();
+    stages.tree = imports::resolve(stages.tree, options, resolver, &mut import_diagnostics);
+    diagnostics.extend(import_diagnostics.map_spans(|span| stages.offset_map.span(span)));
+
+    stages
+}
+
+/// run every stage of parsing a template with the given options,
+/// importing templates from `resolver`
+/// diagnostic spans refer to the original template
+pub fn parse_stages(
+    template: &str,
+    options: &Options,
+    resolver: &dyn TemplateResolver,
+) -> (Stages, Diagnostics) {
+    let mut diagnostics = Diagnostics::new();
+    let stages = parse_resolved(template, options, resolver, &mut diagnostics);
+
+    (stages, diagnostics)
+}
+
+/// parse a template into controls with the given options,
+/// collecting diagnostics from every phase
+/// control spans refer to the original template
+pub fn parse_with(template: &str, options: &Options) -> (Vec<Control>, Diagnostics) {
+    parse_with_resolver(template, options, &NoResolver)
+}
+
+/// parse a template into controls with the given options,
+/// importing templates from `resolver`
+pub fn parse_with_resolver(
+    template: &str,
+    options: &Options,
+    resolver: &dyn TemplateResolver,
+) -> (Vec<Control>, Diagnostics) {
+    let (
+        Stages {
+            mut tree,
+            offset_map,
+            ..
+        },
+        diagnostics,
+    ) = parse_stages(template, options, resolver);
+    for control in &mut tree {
+        control.map_spans(&|span| offset_map.span(span));
+    }
+
+    (tree, diagnostics)
+}
+
+/// compile a template with the given options,
+/// collecting diagnostics from every phase
+pub fn compile_with(template: &str, options: &Options) -> Output {
+    compile_with_resolver(template, options, &NoResolver)
+}
+
+/// compile a template with the given options,
+/// importing templates from `resolver`
+pub fn compile_with_resolver(
+    template: &str,
+    options: &Options,
+    resolver: &dyn TemplateResolver,
+) -> Output {
+    let (
+        Stages {
+            tree, offset_map, ..
+        },
+        diagnostics,
+    ) = parse_stages(template, options, resolver);
+    let declaration = if options.declaration {
+        Some(declaration::declaration(&tree))
+    } else {
+        None
+    };
+
+    if options.source_map {
+        let (code, mut mappings) = generator::generate_with_mappings(tree, options);
+        for mapping in &mut mappings {
+            mapping.span = mapping.span.map(|span| offset_map.span(span));
+        }
+
+        Output {
+            code,
+            diagnostics,
+            source_map: Some(source_map::source_map(
+                options.template_name(),
+                template,
+                &mappings,
+            )),
+            declaration,
+        }
+    } else {
+        Output {
+            code: generator::generate_with(tree, options),
+            diagnostics,
+            source_map: None,
+            declaration,
+        }
+    }
+}
+
+/// compile a template, collecting diagnostics from every phase
+pub fn compile_with_diagnostics(template: &str) -> Output {
+    compile_with(template, &Options::default())
+}
+
+/// compile a template, recovering from any errors
 pub fn compile(template: &str) -> String {
-    let pre_fixed = pre_fixer::pre_fix(template);
-    let lexed = lexer::lex(&pre_fixed);
-    let first_parsed = parser::parse_instructions(&pre_fixed, lexed);
-    let extras_fixed = parser::fix_extra_instructions(&pre_fixed, first_parsed);
-    let (tree, _) = parser::parse_tree(&pre_fixed, &mut extras_fixed.into_iter(), &Vec::new(), 1);
-    generator::generate(tree)
+    compile_with_diagnostics(template).code
+}
+
+/// compile a template with the given options, failing on the first error
+/// every error is in the diagnostics of `compile_with`
+pub fn try_compile_with(template: &str, options: &Options) -> Result<String, CompileError> {
+    let Output {
+        code, diagnostics, ..
+    } = compile_with(template, options);
+
+    match diagnostics.first_error() {
+        Some(err) => Err(err),
+        None => Ok(code),
+    }
+}
+
+/// compile a template, failing on the first error
+pub fn try_compile(template: &str) -> Result<String, CompileError> {
+    try_compile_with(template, &Options::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::{ErrorKind, Span};
+
+    #[test]
+    fn parse_stages_extra_end() {
+        let template = "{{{ if a }}}b{{{ end }}}{{{ end }}}";
+        let (stages, diagnostics) = parse_stages(template, &Options::default(), &NoResolver);
+
+        assert_eq!(stages.pre_fixed, template);
+        assert_eq!(stages.first_parsed.len(), 4);
+        assert_ne!(stages.instructions, stages.first_parsed);
+        assert_eq!(stages.tree, parse_with(template, &Options::default()).0);
+        assert_eq!(diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn try_compile_ok() {
+        let template = "{{{ if a }}}{b}{{{ end }}}";
+
+        assert_eq!(try_compile(template), Ok(compile(template)));
+    }
+
+    #[test]
+    fn try_compile_extra_end() {
+        let template = "{{{ if a }}}b{{{ end }}}{{{ end }}}";
+
+        // extra ends are recovered from with a warning
+        assert_eq!(try_compile(template), Ok(compile(template)));
+
+        let diagnostics = compile_with_diagnostics(template).diagnostics;
+        let warning = diagnostics.warnings().next().unwrap();
+        assert_eq!(warning.code, ErrorKind::ExtraEnd);
+        assert_eq!(warning.span, Span::new(24, 35));
+    }
+
+    #[test]
+    fn extra_end_label() {
+        let diagnostics =
+            compile_with_diagnostics("{{{ each a }}}{{{ end b }}}{{{ end }}}").diagnostics;
+        let warning = diagnostics.warnings().next().unwrap();
+
+        assert_eq!(warning.span, Span::new(14, 27));
+        assert_eq!(
+            warning.labels.iter().map(|l| l.span).collect::<Vec<_>>(),
+            vec![Span::new(0, 14)]
+        );
+    }
+
+    #[test]
+    fn unknown_block_warning() {
+        let diagnostics = compile_with_diagnostics("a {{{ foo }}} b").diagnostics;
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics.warnings().next().map(|w| w.code),
+            Some(ErrorKind::UnknownBlock)
+        );
+    }
+
+    #[test]
+    fn legacy_syntax_warnings() {
+        let diagnostics = compile_with_diagnostics("@key {function.foo}").diagnostics;
+
+        assert_eq!(
+            diagnostics.iter().map(|d| d.span).collect::<Vec<_>>(),
+            vec![Span::new(0, 4), Span::new(5, 19)]
+        );
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.code == ErrorKind::LegacySyntax && !d.notes.is_empty()));
+    }
+
+    #[test]
+    fn legacy_spans_mapped() {
+        let template =
+            "<!-- BEGIN outer --><!-- BEGIN inner -->{{{ each }}}<!-- END inner --><!-- END outer -->";
+        let diagnostics = compile_with_diagnostics(template).diagnostics;
+        let error = diagnostics.errors().next().unwrap();
+
+        // reported once, at the position in the original template
+        assert_eq!(diagnostics.errors().count(), 1);
+        assert_eq!(error.span.start, template.find("{{{ each").unwrap());
+    }
+
+    #[test]
+    fn comments() {
+        let template = "a{{{! {{{ each b }}} @value\n<!-- BEGIN c --> }}}b<!-- # {function.d} -->c";
+        let output = compile_with_diagnostics(template);
+
+        assert!(output.diagnostics.is_empty());
+        assert_eq!(output.code, compile("abc"));
+    }
+
+    #[test]
+    fn else_if_chain() {
+        let output = compile_with_diagnostics(
+            "{{{ if a }}}1{{{ else if b }}}2{{{ else if !c }}}3{{{ else }}}4{{{ end }}}",
+        );
+
+        assert!(output.diagnostics.is_empty());
+        // a flat chain of ternaries, without nested blocks to close
+        assert_eq!(output.code.matches(" ?").count(), 3);
+        assert!(output.code.contains("!guard(context && context['c']) ?"));
+    }
+
+    #[test]
+    fn long_operator_chain() {
+        let chain = |n: usize| {
+            (0..n)
+                .map(|i| format!("a{}", i))
+                .collect::<Vec<String>>()
+                .join(" || ")
+        };
+
+        let output =
+            compile_with_diagnostics(&format!("{{{{{{ if {} }}}}}}b{{{{{{ end }}}}}}", chain(70)));
+        assert!(output.diagnostics.is_empty());
+        assert!(output.code.contains("guard(context && context['a69'])) ?"));
+
+        // the longest chain allowed still renders without overflowing
+        let template = format!(
+            "{{{{{{ if {} }}}}}}b{{{{{{ end }}}}}}",
+            chain(lexer::MAX_EXPRESSION_OPERATORS + 1)
+        );
+        let (tree, diagnostics) = parse_with(&template, &Options::default());
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            render::render(&tree, &json::parse("{ \"a256\": true }").unwrap()),
+            "b"
+        );
+        assert!(rust_generator::generate(tree.clone()).contains("a256"));
+        assert!(declaration::declaration(&tree).contains("a256"));
+        assert!(generator::generate(tree).contains("a256"));
+    }
+
+    #[test]
+    fn string_literal_line_breaks() {
+        let code = compile("{{{ if f(\"a\nb\\\nc\\\\\nd\") }}}x{{{ end }}}");
+
+        // line breaks aren't allowed in JS string literals,
+        // and an escaped one continues the line
+        assert!(code.contains("helper(context, helpers, 'f', [\"a\\nbc\\\\\\nd\"])"));
+    }
+
+    #[test]
+    fn helper_names_escaped() {
+        let code = compile("{{a\\b()}}{function.c\\d}");
+
+        // `'a\b'` would be the name `a` followed by a backspace
+        assert!(code.contains("helper(context, helpers, 'a\\\\b', [])"));
+        assert!(code.contains("helper(context, helpers, 'c\\\\d', [guard(value)])"));
+    }
+
+    #[test]
+    fn try_compile_invalid_block() {
+        let err = try_compile("a {{{ each }}} b").unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::InvalidBlock);
+        assert_eq!(err.span, Span::new(2, 14));
+        // still passed through as text by `compile`
+        assert!(compile("a {{{ each }}} b").contains("{{{ each }}}"));
+    }
+
+    #[test]
+    fn try_compile_first_error() {
+        let template = "{{{ each }}} {{{ if }}}";
+        let err = try_compile(template).unwrap_err();
+
+        assert_eq!(err.span, Span::new(0, 12));
+        // every error is still in the diagnostics
+        assert_eq!(
+            compile_with_diagnostics(template)
+                .diagnostics
+                .errors()
+                .map(|error| (error.code, error.span))
+                .collect::<Vec<_>>(),
+            vec![
+                (ErrorKind::InvalidBlock, Span::new(0, 12)),
+                (ErrorKind::InvalidBlock, Span::new(13, 23)),
+            ]
+        );
+    }
+
+    #[test]
+    fn try_compile_strict() {
+        let template = "{{{ if a }}}b{{{ end }}}{{{ end }}}";
+        let options = Options {
+            strict: true,
+            ..Options::default()
+        };
+        let err = try_compile_with(template, &options).unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::ExtraEnd);
+        assert_eq!(err.span, Span::new(24, 35));
+    }
+
+    fn malformed(template: &str, malformed: MalformedPolicy) -> Output {
+        compile_with(
+            template,
+            &Options {
+                malformed,
+                ..Options::default()
+            },
+        )
+    }
+
+    #[test]
+    fn malformed_interpolation() {
+        let template = "a { b(c, ) } d";
+
+        let text = malformed(template, MalformedPolicy::Text);
+        let warning = text.diagnostics.warnings().next().unwrap();
+        assert_eq!(warning.code, ErrorKind::InvalidExpression);
+        assert_eq!(warning.span, Span::new(2, 12));
+        assert!(text.code.contains("{ b(c, ) }"));
+
+        let dropped = malformed(template, MalformedPolicy::Drop);
+        assert!(!dropped.diagnostics.has_errors());
+        assert!(!dropped.code.contains("b(c"));
+
+        let error = malformed(template, MalformedPolicy::Error);
+        assert_eq!(
+            error.diagnostics.errors().next().unwrap().span,
+            Span::new(2, 12)
+        );
+        assert_eq!(error.code, text.code);
+    }
+
+    #[test]
+    fn malformed_block() {
+        let template = "a {{{ foo }}} b {{{ each }}} c";
+
+        let text = malformed(template, MalformedPolicy::Text);
+        assert_eq!(
+            text.diagnostics
+                .iter()
+                .map(|d| (d.code, d.span))
+                .collect::<Vec<_>>(),
+            vec![
+                (ErrorKind::UnknownBlock, Span::new(2, 13)),
+                (ErrorKind::InvalidBlock, Span::new(16, 28))
+            ]
+        );
+        assert!(text.code.contains("{{{ foo }}}"));
+
+        let dropped = malformed(template, MalformedPolicy::Drop);
+        assert!(!dropped.code.contains("{{{"));
+
+        let error = malformed(template, MalformedPolicy::Error);
+        assert_eq!(error.diagnostics.errors().count(), 2);
+        assert_eq!(error.code, text.code);
+    }
+
+    #[test]
+    fn nested_blocks_too_deep() {
+        let depth = parser::MAX_BLOCK_DEPTH * 4;
+        let template = format!(
+            "{}x{}",
+            "{{{ if a }}}".repeat(depth),
+            "{{{ end }}}".repeat(depth)
+        );
+        let output = compile_with_diagnostics(&template);
+
+        assert_eq!(
+            output
+                .diagnostics
+                .errors()
+                .map(|e| (e.code, e.span.start))
+                .collect::<Vec<_>>(),
+            vec![(ErrorKind::NestingLimit, parser::MAX_BLOCK_DEPTH * 12)]
+        );
+        assert!(output.code.contains("{{{ if a }}}"));
+    }
+
+    #[test]
+    fn nested_expressions_too_deep() {
+        let template = format!(
+            "{{{{{{ if {}a }}}}}}b{{{{{{ end }}}}}}",
+            "!".repeat(100_000)
+        );
+
+        assert_eq!(
+            try_compile(&template).map_err(|e| e.kind),
+            Err(ErrorKind::InvalidBlock)
+        );
+    }
+
+    #[test]
+    fn nested_legacy_too_deep() {
+        let depth = pre_fixer::MAX_LEGACY_DEPTH * 4;
+        let template = format!(
+            "{}x{}",
+            (0..depth)
+                .map(|i| format!("<!-- BEGIN a{} -->", i))
+                .collect::<String>(),
+            (0..depth)
+                .rev()
+                .map(|i| format!("<!-- END a{} -->", i))
+                .collect::<String>()
+        );
+        let diagnostics = compile_with_diagnostics(&template).diagnostics;
+
+        assert_eq!(
+            diagnostics
+                .warnings()
+                .filter(|w| w.code == ErrorKind::NestingLimit)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn many_legacy_blocks() {
+        // sibling blocks used to be handled recursively
+        let template = "<!-- BEGIN a -->x<!-- END a -->".repeat(10_000);
+
+        assert!(try_compile(&template).is_ok());
+    }
+
+    #[test]
+    fn many_blocks() {
+        use instruction::{Instruction, InstructionPos};
+
+        // blocks used to be counted with 16-bit integers
+        let instructions: Vec<InstructionPos> = (0..70_000)
+            .flat_map(|_| vec![Instruction::IfStart(vec![]), Instruction::End(vec![])])
+            .chain(Some(Instruction::End(vec![])))
+            .enumerate()
+            .map(|(index, inst)| InstructionPos {
+                start: index,
+                end: index,
+                inst,
+            })
+            .collect();
+        let source = " ".repeat(instructions.len());
+        let mut diagnostics = Diagnostics::new();
+        let fixed = parser::fix_extra_instructions(&source, instructions, &mut diagnostics);
+
+        assert_eq!(
+            fixed
+                .iter()
+                .filter(|inst| matches!(inst.inst, Instruction::Text(_)))
+                .count(),
+            1
+        );
+        assert_eq!(diagnostics.len(), 1);
+    }
 }
@@ -0,0 +1,532 @@
+use generator::{MARK_END, MARK_START};
+use templates::{INDEX, KEY, LENGTH, VALUE};
+
+/// a token of generated code
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    /// identifier, keyword or number
+    Word(String),
+    /// string literal, including the quotes
+    Str(String),
+    /// part of a template literal, from the backtick or the end of a substitution
+    /// to the start of the next substitution or the closing backtick
+    Template(String),
+    /// operator or punctuation
+    Punct(String),
+    /// source map marker, which isn't part of the code
+    Mark(String),
+}
+
+impl Token {
+    fn text(&self) -> &str {
+        match self {
+            Token::Word(text)
+            | Token::Str(text)
+            | Token::Template(text)
+            | Token::Punct(text)
+            | Token::Mark(text) => text,
+        }
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self, Token::Punct(text) if text == punct)
+    }
+
+    fn is_mark(&self) -> bool {
+        matches!(self, Token::Mark(_))
+    }
+}
+
+// operators longer than one character, longest first
+static OPERATORS: &[&str] = &["===", "!==", "==", "!=", "<=", ">=", "&&", "||", "=>", "?."];
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_' || ch == '$'
+}
+
+/// length of the string literal or template chunk at the start of `source`,
+/// which ends with `end`, and whether it ends at the start of a substitution
+fn literal_len(source: &str, end: char, template: bool) -> (usize, bool) {
+    let mut chars = source.char_indices().skip(1);
+
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            '$' if template && source[i + 1..].starts_with('{') => return (i + 2, true),
+            _ if ch == end => return (i + ch.len_utf8(), false),
+            _ => (),
+        }
+    }
+
+    (source.len(), false)
+}
+
+/// split generated code into tokens, dropping whitespace
+fn tokenize(code: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    // braces opened within each template substitution being read
+    let mut substitutions: Vec<usize> = Vec::new();
+    let mut rest = code;
+
+    while let Some(ch) = rest.chars().next() {
+        let (token, len): (fn(String) -> Token, usize) = if ch.is_whitespace() {
+            rest = &rest[ch.len_utf8()..];
+            continue;
+        } else if ch == MARK_START {
+            let len = rest
+                .find(MARK_END)
+                .map_or(rest.len(), |i| i + MARK_END.len_utf8());
+            (Token::Mark, len)
+        } else if is_word_char(ch) {
+            let len = rest.find(|ch| !is_word_char(ch)).unwrap_or(rest.len());
+            (Token::Word, len)
+        } else if ch == '"' || ch == '\'' {
+            (Token::Str, literal_len(rest, ch, false).0)
+        } else if ch == '`' || (ch == '}' && substitutions.last() == Some(&0)) {
+            if ch == '}' {
+                substitutions.pop();
+            }
+            let (len, substitution) = literal_len(rest, '`', true);
+            if substitution {
+                substitutions.push(0);
+            }
+            (Token::Template, len)
+        } else {
+            if let Some(open) = substitutions.last_mut() {
+                match ch {
+                    '{' => *open += 1,
+                    '}' => *open -= 1,
+                    _ => (),
+                }
+            }
+            let len = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .map_or(ch.len_utf8(), |op| op.len());
+            (Token::Punct, len)
+        };
+
+        tokens.push(token(rest[..len].to_string()));
+        rest = &rest[len..];
+    }
+
+    tokens
+}
+
+/// the short name of a local variable
+/// prefixed with `$`, which no other generated identifier contains
+fn short_name(name: &str) -> Option<String> {
+    let short = match name {
+        _ if name == KEY => "$k",
+        _ if name == INDEX => "$i",
+        _ if name == LENGTH => "$l",
+        _ if name == VALUE => "$v",
+        _ => {
+            // keys with an indexed suffix
+            return name
+                .strip_prefix(KEY)
+                .filter(|suffix| !suffix.is_empty() && suffix.bytes().all(|b| b.is_ascii_digit()))
+                .map(|suffix| format!("$k{}", suffix));
+        }
+    };
+
+    Some(short.to_string())
+}
+
+/// shorten local variables, leaving properties alone
+fn rename(tokens: &mut [Token]) {
+    let mut property = false;
+
+    for token in tokens.iter_mut().filter(|token| !token.is_mark()) {
+        if let Token::Word(word) = token {
+            if !property {
+                if let Some(short) = short_name(word) {
+                    *word = short;
+                }
+            }
+        }
+
+        property = token.is_punct(".") || token.is_punct("?.");
+    }
+}
+
+/// whether a string concatenation can be merged after this token,
+/// because nothing to its left binds tighter than `+`
+fn merges_after(token: Option<&Token>) -> bool {
+    match token {
+        None => true,
+        Some(Token::Word(word)) => word == "return",
+        Some(Token::Punct(punct)) => {
+            ["+", "(", "[", "{", "?", ":", ",", "=", "=>"].contains(&punct.as_str())
+        }
+        Some(Token::Template(chunk)) => chunk.ends_with("${"),
+        _ => false,
+    }
+}
+
+/// whether a string concatenation can be merged before this token,
+/// because nothing to its right binds tighter than `+`
+fn merges_before(token: Option<&Token>) -> bool {
+    match token {
+        None => true,
+        Some(Token::Punct(punct)) => {
+            ["+", ")", "]", "}", "?", ":", ",", ";"].contains(&punct.as_str())
+        }
+        Some(Token::Template(chunk)) => chunk.starts_with('}'),
+        _ => false,
+    }
+}
+
+/// merge a string into a concatenation with the string at the end of `output`,
+/// moving any markers between them into the merged string
+fn merge_last(output: &mut Vec<Token>, second: &str) -> Option<Token> {
+    let mut significant = (0..output.len()).rev().filter(|&i| !output[i].is_mark());
+    let plus = significant.next()?;
+    let start = significant.next()?;
+    let before = significant.next().map(|i| &output[i]);
+
+    let first = match &output[start] {
+        Token::Str(first) => first,
+        _ => return None,
+    };
+    let first_body = &first[..first.len() - 1];
+    let second_body = &second[1..];
+
+    // a digit after an escape like `\0` would change the escape
+    let escape = first_body.ends_with(|ch: char| ch.is_ascii_digit())
+        && first_body.contains('\\')
+        && second_body.starts_with(|ch: char| ch.is_ascii_digit());
+
+    if !output[plus].is_punct("+") || !merges_after(before) || first[..1] != second[..1] || escape {
+        return None;
+    }
+
+    let marks: String = output[start + 1..]
+        .iter()
+        .filter(|token| token.is_mark())
+        .map(Token::text)
+        .collect();
+    let merged = Token::Str(format!("{}{}{}", first_body, marks, second_body));
+    output.truncate(start);
+
+    Some(merged)
+}
+
+/// merge concatenated string literals
+fn merge_strings(tokens: Vec<Token>) -> Vec<Token> {
+    let mut output: Vec<Token> = Vec::with_capacity(tokens.len());
+
+    for (i, token) in tokens.iter().enumerate() {
+        if let Token::Str(second) = token {
+            let next = tokens[i + 1..].iter().find(|token| !token.is_mark());
+            if merges_before(next) {
+                if let Some(merged) = merge_last(&mut output, second) {
+                    output.push(merged);
+                    continue;
+                }
+            }
+        }
+
+        output.push(token.clone());
+    }
+
+    output
+}
+
+/// whether two tokens would run together without a space between them
+fn needs_space(before: &str, after: &str) -> bool {
+    match (before.chars().last(), after.chars().next()) {
+        (Some(a), Some(b)) => {
+            (is_word_char(a) && is_word_char(b))
+                || ((a == '+' || a == '-') && a == b)
+                // a dot after a number would be a decimal point
+                || (b == '.' && before.bytes().all(|b| b.is_ascii_digit()))
+        }
+        _ => false,
+    }
+}
+
+/// remove layout whitespace from generated code,
+/// merge concatenated string literals and shorten local variables
+/// source map markers are kept in place
+pub fn minify(code: &str) -> String {
+    let mut tokens = tokenize(code);
+    rename(&mut tokens);
+    let tokens = merge_strings(tokens);
+
+    let mut output = String::with_capacity(code.len());
+    let mut last: Option<&Token> = None;
+
+    for (i, token) in tokens.iter().enumerate() {
+        if token.is_mark() {
+            output.push_str(token.text());
+            continue;
+        }
+
+        // a semicolon isn't needed before a closing brace
+        if token.is_punct(";")
+            && tokens[i + 1..]
+                .iter()
+                .find(|token| !token.is_mark())
+                .is_some_and(|token| token.is_punct("}"))
+        {
+            continue;
+        }
+
+        if last.is_some_and(|last| needs_space(last.text(), token.text())) {
+            output.push(' ');
+        }
+        output.push_str(token.text());
+        last = Some(token);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use generator::tests::run_node;
+    use json;
+    use options::{Options, OutputFormat, Target};
+
+    #[test]
+    fn tokenize_test() {
+        assert_eq!(
+            tokenize("a?.b === 'c\\'' + `d${ {e: `${f}`} }g`"),
+            vec![
+                Token::Word("a".to_string()),
+                Token::Punct("?.".to_string()),
+                Token::Word("b".to_string()),
+                Token::Punct("===".to_string()),
+                Token::Str("'c\\''".to_string()),
+                Token::Punct("+".to_string()),
+                Token::Template("`d${".to_string()),
+                Token::Punct("{".to_string()),
+                Token::Word("e".to_string()),
+                Token::Punct(":".to_string()),
+                Token::Template("`${".to_string()),
+                Token::Word("f".to_string()),
+                Token::Template("}`".to_string()),
+                Token::Punct("}".to_string()),
+                Token::Template("}g`".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn minify_test() {
+        assert_eq!(
+            minify(
+                "iter(context['value'], function each(key1, index, length, value) {
+  var key = key1;
+  return \"a\" +
+    \"b\" +
+    guard(value) + \"c\" + \"d\";
+}, function alt() {
+  return typeof x - \"e\" + \"f\";
+})"
+            ),
+            "iter(context['value'],function each($k1,$i,$l,$v){var $k=$k1;return\"ab\"+\
+             guard($v)+\"cd\"},function alt(){return typeof x-\"e\"+\"f\"})"
+        );
+
+        // properties aren't renamed
+        assert_eq!(minify("a.value + b?.index"), "a.value+b?.index");
+        // the escape would become `\\01`
+        assert_eq!(minify("'\\0' + '1'"), "'\\0'+'1'");
+        // markers are kept
+        assert_eq!(
+            minify("\"a\"\u{E000}0\u{E001} + \u{E000}1\u{E001}\"b\""),
+            "\"a\u{E000}0\u{E001}\u{E000}1\u{E001}b\""
+        );
+    }
+
+    #[test]
+    fn minify_compiled() {
+        let options = Options {
+            format: OutputFormat::Function,
+            minify: true,
+            ..Options::default()
+        };
+
+        assert_eq!(
+            ::compile_with(
+                "{{{ each a }}}{@index}{a.value}{{{ each ./b }}}{@key}{value}{{{ end }}}{{{ end }}}",
+                &options
+            )
+            .code,
+            "(function(){function compiled(helpers,context,guard,iter,helper){var __escape=helpers.__escape;var $v=context;\
+return compiled.blocks['a'](helpers,context,guard,iter,helper)}compiled.blocks={'a':function a(helpers,\
+context,guard,iter,helper){var __escape=helpers.__escape;var $v=context;return iter(guard(context&&context['a']),\
+function each($k1,$i,$l,$v){var $k=$k1;return __escape($i)+__escape(guard(context&&context['a']&&context['a'][$k1]\
+&&context['a'][$k1]['value']))+iter(guard(context&&context['a']&&context['a'][$k1]&&context['a'][$k1]['b']),\
+function each($k2,$i,$l,$v){var $k=$k2;return __escape($k)+__escape(guard(context&&context['value']))},\
+function alt(){return\"\"})},function alt(){return\"\"})}};return compiled})()"
+        );
+
+        let options = Options {
+            target: Target::Es2015,
+            ..options
+        };
+        assert_eq!(
+            ::compile_with("{{{ each a }}}{a.length}{a.value}{@value}{{{ end }}}", &options).code,
+            "(function(){function compiled(helpers,context,guard,iter,helper){const __escape=helpers.__escape;const $v=context;\
+return compiled.blocks['a'](helpers,context,guard,iter,helper)}compiled.blocks={'a':function a(helpers,\
+context,guard,iter,helper){const __escape=helpers.__escape;const $v=context;return iter(guard(context&&context.a),\
+($k1,$i,$l,$v)=>{const $k=$k1;return __escape(guard(context&&context.a&&context.a[$k1]&&context.a[$k1].length))+__escape(guard(context&&context.a&&context.a[$k1]&&context.a[$k1].value))+__escape(guard($v))},\
+()=>\"\")}};return compiled})()"
+        );
+    }
+
+    /// pretty local names, along with what they're shortened to
+    const RENAMED: &[(&str, &str)] = &[
+        ("key", "$k"),
+        ("key1", "$k1"),
+        ("key2", "$k2"),
+        ("index", "$i"),
+        ("length", "$l"),
+        ("value", "$v"),
+    ];
+
+    /// significant tokens, with semicolons before braces removed,
+    /// and concatenated double quoted strings decoded and joined
+    /// local names in `RENAMED` are shortened if `shorten` is set
+    fn normalized(code: &str, shorten: bool) -> Vec<Token> {
+        let mut tokens: Vec<Token> = tokenize(code)
+            .into_iter()
+            .filter(|token| !token.is_mark())
+            .collect();
+
+        if shorten {
+            for i in 0..tokens.len() {
+                let property =
+                    i > 0 && (tokens[i - 1].is_punct(".") || tokens[i - 1].is_punct("?."));
+                if let Token::Word(word) = &mut tokens[i] {
+                    match RENAMED.iter().find(|(pretty, _)| pretty == word) {
+                        Some((_, short)) if !property => *word = short.to_string(),
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        let mut output: Vec<Token> = Vec::new();
+        for (i, token) in tokens.iter().enumerate() {
+            if token.is_punct(";") && tokens.get(i + 1).is_some_and(|next| next.is_punct("}")) {
+                continue;
+            }
+
+            let token = match token {
+                Token::Str(text) if text.starts_with('"') => {
+                    Token::Str(json::parse(text).unwrap().as_str().unwrap().to_string())
+                }
+                _ => token.clone(),
+            };
+
+            let len = output.len();
+            if len >= 2 && output[len - 1].is_punct("+") {
+                if let (Token::Str(first), Token::Str(second)) = (&output[len - 2], &token) {
+                    let joined = Token::Str(format!("{}{}", first, second));
+                    output.truncate(len - 2);
+                    output.push(joined);
+                    continue;
+                }
+            }
+            output.push(token);
+        }
+
+        output
+    }
+
+    const TEMPLATES: &[&str] = &[
+        "",
+        "text only\nwith \"quotes\" and 'apostrophes' and $ and `backticks`",
+        "{a} and {{b.c}}\n<!-- IF d -->e<!-- ELSE -->f<!-- ENDIF d -->",
+        "{{{ each items }}}{@index}/{@key}: {../name}{{@value}}{{{ if @first }}}first{{{ end }}}{{{ else }}}none{{{ end }}}",
+        "<!-- BEGIN rows -->{rows.cells.length}<!-- BEGIN rows.cells -->{@value}{rows.cells.x}<!-- END rows.cells --><!-- END rows -->",
+        "{function.helper, a, \"b\\n\"}{{{ if !function.other, c }}}x{{{ end }}}",
+        "{{{ each a }}}{{{ each ./b }}}{key}{index}{length}{value}{@last}{{{ end }}}{{{ end }}}",
+    ];
+
+    #[test]
+    fn equivalent() {
+        for template in TEMPLATES.iter() {
+            for &target in [Target::Es5, Target::Es2015].iter() {
+                for &format in [OutputFormat::Umd, OutputFormat::Esm, OutputFormat::Iife].iter() {
+                    let options = Options {
+                        target,
+                        format,
+                        ..Options::default()
+                    };
+                    let pretty = ::compile_with(template, &options).code;
+                    let minified = ::compile_with(
+                        template,
+                        &Options {
+                            minify: true,
+                            ..options.clone()
+                        },
+                    )
+                    .code;
+
+                    assert!(minified.len() < pretty.len());
+                    assert!(!minified.contains('\n'));
+                    // the UMD wrapper is written differently when minified
+                    let compiled = |code: &str, shorten: bool| {
+                        normalized(code, shorten)
+                            .into_iter()
+                            .skip_while(|token| token.text() != "compiled")
+                            .collect::<Vec<Token>>()
+                    };
+                    assert_eq!(
+                        compiled(&minified, false),
+                        compiled(&pretty, true),
+                        "{}",
+                        template
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn renders_the_same() {
+        let data = r#"{
+            "a": [{ "b": [1, 0], "key": "k", "index": "i", "length": "l", "value": "v" }, { "b": [] }],
+            "b": { "c": "<bc>" },
+            "c": "",
+            "d": true,
+            "name": "n",
+            "items": { "x": "y", "z": [2] },
+            "rows": [{ "cells": ["p", { "x": "q" }] }, { "cells": [] }]
+        }"#;
+
+        for template in TEMPLATES.iter() {
+            for &target in [Target::Es5, Target::Es2015].iter() {
+                let options = Options {
+                    target,
+                    format: OutputFormat::Function,
+                    ..Options::default()
+                };
+                let pretty = ::compile_with(template, &options).code;
+                let minified = ::compile_with(
+                    template,
+                    &Options {
+                        minify: true,
+                        ..options.clone()
+                    },
+                )
+                .code;
+
+                match (run_node(&pretty, data), run_node(&minified, data)) {
+                    (Some(expected), Some(output)) => {
+                        assert_eq!(output, expected, "{} ({:?})", template, target)
+                    }
+                    _ => return,
+                }
+            }
+        }
+    }
+}
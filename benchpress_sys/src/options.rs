@@ -0,0 +1,192 @@
+use diagnostics::Severity;
+
+/// what to do with a malformed tag or interpolation
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default)]
+pub enum MalformedPolicy {
+    /// pass it through as text, with a warning
+    #[default]
+    Text,
+    /// remove it from the output, with a warning
+    Drop,
+    /// pass it through as text, with an error
+    Error,
+}
+
+impl MalformedPolicy {
+    /// the names accepted by `from_name`
+    pub const NAMES: &'static [&'static str] = &["text", "drop", "error"];
+
+    /// parse the name of a policy, like `text` or `drop`
+    pub fn from_name(name: &str) -> Option<MalformedPolicy> {
+        match name {
+            "text" => Some(MalformedPolicy::Text),
+            "drop" => Some(MalformedPolicy::Drop),
+            "error" => Some(MalformedPolicy::Error),
+            _ => None,
+        }
+    }
+
+    /// severity of the diagnostic reported for a malformed tag
+    pub fn severity(self) -> Severity {
+        match self {
+            MalformedPolicy::Error => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+
+    /// describe what happens to a malformed tag
+    pub fn outcome(self) -> &'static str {
+        match self {
+            MalformedPolicy::Drop => "removed from the output",
+            _ => "passed through as text",
+        }
+    }
+}
+
+/// the shape of the module generated code is wrapped in
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default)]
+pub enum OutputFormat {
+    /// a factory supporting both CommonJS and AMD
+    #[default]
+    Umd,
+    /// an ES module with `compiled` as the default export
+    /// and `blocks` as a named export
+    Esm,
+    /// a CommonJS module exporting `compiled`
+    CommonJs,
+    /// an AMD module defining `compiled`
+    Amd,
+    /// a script registering `compiled` as `window.templates[name]`
+    Iife,
+    /// an expression evaluating to `compiled`
+    Function,
+}
+
+impl OutputFormat {
+    /// the names accepted by `from_name`
+    pub const NAMES: &'static [&'static str] =
+        &["umd", "esm", "cjs", "commonjs", "amd", "iife", "function"];
+
+    /// parse the name of a format, like `esm` or `cjs`
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "umd" => Some(OutputFormat::Umd),
+            "esm" => Some(OutputFormat::Esm),
+            "cjs" | "commonjs" => Some(OutputFormat::CommonJs),
+            "amd" => Some(OutputFormat::Amd),
+            "iife" => Some(OutputFormat::Iife),
+            "function" => Some(OutputFormat::Function),
+            _ => None,
+        }
+    }
+}
+
+/// the JavaScript language level of generated code
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default)]
+pub enum Target {
+    /// `var`, function expressions and string concatenation
+    #[default]
+    Es5,
+    /// `const`, arrow functions, template literals and dotted property access
+    ///
+    /// template literals only join bodies starting with text,
+    /// others are joined with `+` like ES5 since it adds numbers and booleans
+    ///
+    /// paths are guarded with `&&` like ES5 rather than optional chaining,
+    /// which only stops at `null` and `undefined`, so both render the same
+    Es2015,
+}
+
+impl Target {
+    /// the names accepted by `from_name`
+    pub const NAMES: &'static [&'static str] = &["es5", "es2015", "es6"];
+
+    /// parse the name of a target, like `es5` or `es2015`
+    pub fn from_name(name: &str) -> Option<Target> {
+        match name {
+            "es5" => Some(Target::Es5),
+            "es2015" | "es6" => Some(Target::Es2015),
+            _ => None,
+        }
+    }
+}
+
+/// how `{{{import}}}` instructions are compiled
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default)]
+pub enum ImportMode {
+    /// inline the imported template, resolved while compiling
+    #[default]
+    Inline,
+    /// call the `__import` helper with the path while rendering,
+    /// which is passed the root context
+    /// so imports within `{{{each}}}` are reported, as they can't see the element
+    Runtime,
+}
+
+impl ImportMode {
+    /// the names accepted by `from_name`
+    pub const NAMES: &'static [&'static str] = &["inline", "runtime"];
+
+    /// parse the name of an import mode, like `inline` or `runtime`
+    pub fn from_name(name: &str) -> Option<ImportMode> {
+        match name {
+            "inline" => Some(ImportMode::Inline),
+            "runtime" => Some(ImportMode::Runtime),
+            _ => None,
+        }
+    }
+}
+
+/// options controlling how templates are compiled
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Options {
+    /// report unbalanced and mismatched blocks as errors
+    /// instead of recovering from them
+    pub strict: bool,
+    /// how to handle malformed tags and interpolations
+    pub malformed: MalformedPolicy,
+    /// generate a source map along with the code
+    pub source_map: bool,
+    /// generate a TypeScript declaration of the context along with the code
+    pub declaration: bool,
+    /// name of the template, used as the source in source maps
+    /// and the key in `window.templates` for the IIFE format
+    pub name: Option<String>,
+    /// the shape of the generated module
+    pub format: OutputFormat,
+    /// the language level of the generated code
+    pub target: Target,
+    /// remove layout whitespace and shorten local names in the generated code
+    pub minify: bool,
+    /// inline imported templates or import them while rendering
+    pub imports: ImportMode,
+}
+
+impl Options {
+    /// the name of the template, or `template` if it wasn't given
+    pub fn template_name(&self) -> &str {
+        self.name.as_ref().map_or("template", |name| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert!(MalformedPolicy::NAMES
+            .iter()
+            .all(|name| MalformedPolicy::from_name(name).is_some()));
+        assert!(OutputFormat::NAMES
+            .iter()
+            .all(|name| OutputFormat::from_name(name).is_some()));
+        assert!(Target::NAMES
+            .iter()
+            .all(|name| Target::from_name(name).is_some()));
+        assert!(ImportMode::NAMES
+            .iter()
+            .all(|name| ImportMode::from_name(name).is_some()));
+        assert_eq!(OutputFormat::from_name("esmodule"), None);
+    }
+}
@@ -1,4 +1,7 @@
+use diagnostics::{Diagnostic, Diagnostics, Severity};
+use error::{ErrorKind, Span};
 use instruction::{Instruction, InstructionPos};
+use options::MalformedPolicy;
 use token::{Token, TokenPos};
 
 use itertools::Itertools;
@@ -6,7 +9,11 @@ use std::iter::{Iterator, Peekable};
 
 /// parse the lexer output
 /// into instructions (like `{{each people}}` and `{stuff}`)
-pub fn parse_instructions(_source: &str, tokens: Vec<TokenPos>) -> Vec<InstructionPos> {
+pub fn parse_instructions(
+    _source: &str,
+    tokens: Vec<TokenPos>,
+    diagnostics: &mut Diagnostics,
+) -> Vec<InstructionPos> {
     let mut output: Vec<InstructionPos> = vec![];
 
     let mut iter = tokens.into_iter().peekable();
@@ -27,45 +34,78 @@ pub fn parse_instructions(_source: &str, tokens: Vec<TokenPos>) -> Vec<Instructi
             TokenPos {
                 tok: Token::BlockOpen,
                 start,
-                ..
+                end,
             } => {
-                let TokenPos { tok: keyword, .. } = iter.next().unwrap();
+                let keyword = match iter.next() {
+                    Some(TokenPos { tok, .. }) => tok,
+                    None => {
+                        diagnostics.error(
+                            ErrorKind::UnterminatedInstruction,
+                            Span::new(start, end),
+                            "block is missing a keyword",
+                        );
+                        break;
+                    }
+                };
 
                 // collect tokens for expression
                 let expr: Vec<Token> = iter
                     .peeking_take_while(|x| {
-                        !matches!(x, TokenPos {
-                            tok: Token::BlockClose,
-                            ..
-                        })
+                        !matches!(
+                            x,
+                            TokenPos {
+                                tok: Token::BlockClose,
+                                ..
+                            }
+                        )
                     })
                     .map(|TokenPos { tok, .. }| tok)
                     .collect();
 
-                let TokenPos { end, .. } = iter.next().unwrap();
+                let end = match iter.next() {
+                    Some(TokenPos { end, .. }) => end,
+                    None => {
+                        diagnostics.error(
+                            ErrorKind::UnterminatedInstruction,
+                            Span::new(start, end),
+                            "block is missing a closer",
+                        );
+                        break;
+                    }
+                };
 
-                if let Some(inst) = match keyword {
+                let inst = match keyword {
                     Token::If => Some(Instruction::IfStart(expr)),
                     Token::Iter => Some(Instruction::IterStart(expr)),
                     Token::Else => Some(Instruction::Else),
+                    Token::ElseIf => Some(Instruction::ElseIf(expr)),
                     Token::End => Some(Instruction::End(expr)),
-                    _ => None,
-                } {
-                    Some(InstructionPos { start, end, inst })
-                } else {
-                    None
-                }
+                    Token::Import => Some(Instruction::Import(expr)),
+                    Token::Extends => Some(Instruction::Extends(expr)),
+                    Token::Block => Some(Instruction::BlockStart(expr)),
+                    Token::Super => Some(Instruction::Super),
+                    _ => {
+                        diagnostics.error(
+                            ErrorKind::InvalidBlock,
+                            Span::new(start, end),
+                            "block has an unknown keyword",
+                        );
+                        None
+                    }
+                };
+
+                inst.map(|inst| InstructionPos { start, end, inst })
             }
             // parse interpolation mustaches into instructions
             TokenPos {
                 tok: Token::RawOpen,
                 start,
-                ..
+                end,
             }
             | TokenPos {
                 tok: Token::EscapedOpen,
                 start,
-                ..
+                end,
             } => {
                 let closer = match opener {
                     TokenPos {
@@ -82,7 +122,17 @@ pub fn parse_instructions(_source: &str, tokens: Vec<TokenPos>) -> Vec<Instructi
                     .map(|TokenPos { tok, .. }| tok)
                     .collect();
 
-                let TokenPos { end, .. } = iter.next().unwrap();
+                let end = match iter.next() {
+                    Some(TokenPos { end, .. }) => end,
+                    None => {
+                        diagnostics.error(
+                            ErrorKind::UnterminatedInstruction,
+                            Span::new(start, end),
+                            "interpolation is missing a closer",
+                        );
+                        break;
+                    }
+                };
 
                 let inst = match opener {
                     TokenPos {
@@ -95,16 +145,39 @@ pub fn parse_instructions(_source: &str, tokens: Vec<TokenPos>) -> Vec<Instructi
 
                 Some(InstructionPos { start, end, inst })
             }
+            // comments produce nothing
+            TokenPos {
+                tok: Token::Comment(_),
+                ..
+            } => None,
             _ => None,
         } {
-            output.push(inst_pos);
+            match (output.last_mut(), inst_pos) {
+                // join text split by a comment
+                (
+                    Some(InstructionPos {
+                        inst: Instruction::Text(prev),
+                        end: prev_end,
+                        ..
+                    }),
+                    InstructionPos {
+                        inst: Instruction::Text(text),
+                        end,
+                        ..
+                    },
+                ) => {
+                    prev.push_str(&text);
+                    *prev_end = end;
+                }
+                (_, inst_pos) => output.push(inst_pos),
+            }
         }
     }
 
     output
 }
 
-use std::collections::HashSet;
+use std::collections::HashMap;
 
 /// check if a vector starts with the elements of another vector
 pub fn starts_with<T>(full: &[T], part: &[T]) -> bool
@@ -126,13 +199,18 @@ where
 
 /// in a case where there are extra End instructions
 /// try to match them to Ifs or Iters
-/// and remove the extra ones
-pub fn fix_extra_instructions(source: &str, input: Vec<InstructionPos>) -> Vec<InstructionPos> {
-    let mut remove: HashSet<InstructionPos> = HashSet::new();
-    let mut expected_subjects: Vec<Vec<Token>> = Vec::new();
+/// and replace the extra ones with text, reporting each to `diagnostics`
+pub fn fix_extra_instructions(
+    source: &str,
+    input: Vec<InstructionPos>,
+    diagnostics: &mut Diagnostics,
+) -> Vec<InstructionPos> {
+    // extra instructions, along with the block they were expected to close
+    let mut remove: HashMap<InstructionPos, Option<Span>> = HashMap::new();
+    let mut expected_subjects: Vec<(Vec<Token>, Span)> = Vec::new();
 
-    let mut starts_count: u16 = 0;
-    let mut ends_count: u16 = 0;
+    let mut starts_count: usize = 0;
+    let mut ends_count: usize = 0;
 
     // try to find a Close with no corresponding Open
     for index in 0..input.len() {
@@ -147,8 +225,12 @@ pub fn fix_extra_instructions(source: &str, input: Vec<InstructionPos>) -> Vec<I
             | InstructionPos {
                 inst: Instruction::IterStart(subject),
                 ..
+            }
+            | InstructionPos {
+                inst: Instruction::BlockStart(subject),
+                ..
             } => {
-                expected_subjects.push(subject.clone());
+                expected_subjects.push((subject.clone(), elem.span()));
                 starts_count += 1;
             }
             InstructionPos {
@@ -157,11 +239,11 @@ pub fn fix_extra_instructions(source: &str, input: Vec<InstructionPos>) -> Vec<I
             } => {
                 ends_count += 1;
 
-                if let Some(expected_subject) = expected_subjects.pop() {
+                if let Some((expected_subject, opener)) = expected_subjects.pop() {
                     if !subject.is_empty() && !starts_with(&expected_subject, subject) {
                         // doesn't start with what we expect, so remove it
-                        remove.insert(elem.clone());
-                        expected_subjects.push(expected_subject);
+                        remove.insert(elem.clone(), Some(opener));
+                        expected_subjects.push((expected_subject, opener));
                     } else {
                         // search for an end within close proximity
                         // that has the expected subject
@@ -174,6 +256,10 @@ pub fn fix_extra_instructions(source: &str, input: Vec<InstructionPos>) -> Vec<I
                                 | InstructionPos {
                                     inst: Instruction::IterStart(_),
                                     ..
+                                }
+                                | InstructionPos {
+                                    inst: Instruction::BlockStart(_),
+                                    ..
                                 } => {
                                     break;
                                 }
@@ -183,8 +269,8 @@ pub fn fix_extra_instructions(source: &str, input: Vec<InstructionPos>) -> Vec<I
                                 } => {
                                     if ahead_subject == &expected_subject {
                                         // found one ahead, so remove the current one
-                                        remove.insert(elem.clone());
-                                        expected_subjects.push(expected_subject);
+                                        remove.insert(elem.clone(), Some(opener));
+                                        expected_subjects.push((expected_subject, opener));
 
                                         break;
                                     }
@@ -195,7 +281,7 @@ pub fn fix_extra_instructions(source: &str, input: Vec<InstructionPos>) -> Vec<I
                     }
                 } else {
                     // no subject expected, so remove it
-                    remove.insert(elem.clone());
+                    remove.insert(elem.clone(), None);
                 }
             }
             _ => (),
@@ -206,71 +292,20 @@ pub fn fix_extra_instructions(source: &str, input: Vec<InstructionPos>) -> Vec<I
     if ends_count > starts_count {
         let mut diff = ends_count - starts_count;
 
-        println!("[benchpress] Found extra token(s)");
-        println!("=================================");
-
-        let output: Vec<InstructionPos> = input
+        input
             .into_iter()
             .map(|inst| {
-                if remove.contains(&inst) && diff > 0 {
-                    let mut start_of_line = None;
-                    let mut end_of_line = None;
-
-                    // trace back two lines or beginning of file
-                    let mut start = inst.start;
-                    let mut found = 0;
-                    while start > 0 {
-                        // three times:
-                        //  1. start of current line
-                        //  2. start of previous line
-                        //  3. start of previous previous line
-                        if source.is_char_boundary(start) && source[start..].starts_with('\n') {
-                            found += 1;
-                            if found >= 3 {
-                                break;
-                            }
-
-                            // when we find the first one, save the position so we can insert a highlight line
-                            start_of_line.get_or_insert(start);
-                        }
-                        start -= 1;
-                    }
-
-                    // trace forward two lines or end of file
-                    let mut end = inst.end;
-                    let mut found = 0;
-                    while end < source.len() {
-                        // three times:
-                        //  1. end of current line
-                        //  2. end of next line
-                        //  3. end of next next line
-                        if source.is_char_boundary(end) && source[end..].starts_with('\n') {
-                            found += 1;
-                            if found >= 3 {
-                                break;
-                            }
-
-                            // when we find the first one, save the position so we can insert a highlight line
-                            end_of_line.get_or_insert(end);
-                        }
-                        end += 1;
-                    }
-
-                    let start_of_line = start_of_line.unwrap();
-                    let end_of_line = end_of_line.unwrap();
-
-                    // get instruction with context on either side
-                    let context_before =
-                        source[start..end_of_line].trim_start_matches(|c| c == '\n' || c == '\r');
-                    let highlight_line = format!(
-                        "\n{}{}",
-                        " ".repeat(inst.start - start_of_line - 1),
-                        "^".repeat(inst.end - inst.start)
+                if let (Some(opener), true) = (remove.get(&inst), diff > 0) {
+                    let mut diagnostic = Diagnostic::warning(
+                        ErrorKind::ExtraEnd,
+                        inst.span(),
+                        "found extra token which will be passed through as text",
                     );
-                    let context_after =
-                        source[end_of_line..end].trim_end_matches(|c| c == '\n' || c == '\r');
-                    println!("{}{}{}", context_before, highlight_line, context_after);
-                    println!("---------------------------------");
+                    if let Some(opener) = opener {
+                        diagnostic = diagnostic.with_label(*opener, "innermost open block");
+                    }
+                    diagnostics
+                        .push(diagnostic.with_note("remove it to prevent issues in the future"));
 
                     diff -= 1;
                     // replace removed instructions with their source Text
@@ -283,16 +318,183 @@ pub fn fix_extra_instructions(source: &str, input: Vec<InstructionPos>) -> Vec<I
                     inst
                 }
             })
-            .collect();
-
-        println!("These tokens will be passed through as text, but you should remove them to prevent issues in the future.");
-
-        output
+            .collect()
     } else {
         input
     }
 }
 
+/// check that every block is balanced, reporting
+/// unclosed openers, extra or mismatched `End`s, and stray `Else`s
+/// with the given severity
+pub fn check_blocks(
+    source: &str,
+    input: &[InstructionPos],
+    severity: Severity,
+    diagnostics: &mut Diagnostics,
+) {
+    struct Open<'a> {
+        subject: &'a [Token],
+        span: Span,
+        alt: Option<Span>,
+        // whether it can have an `else if`
+        conditional: bool,
+    }
+
+    let mut open: Vec<Open> = Vec::new();
+
+    for elem in input {
+        match &elem.inst {
+            Instruction::IfStart(subject)
+            | Instruction::IterStart(subject)
+            | Instruction::BlockStart(subject) => open.push(Open {
+                subject,
+                span: elem.span(),
+                alt: None,
+                conditional: matches!(elem.inst, Instruction::IfStart(_)),
+            }),
+            Instruction::ElseIf(_) => match open.last() {
+                Some(Open { alt: Some(alt), .. }) => diagnostics.push(
+                    Diagnostic::new(
+                        severity,
+                        ErrorKind::StrayElse,
+                        elem.span(),
+                        "`else if` after an `else`",
+                    )
+                    .with_label(*alt, "`else` here"),
+                ),
+                Some(Open {
+                    conditional: true, ..
+                }) => (),
+                Some(block) => diagnostics.push(
+                    Diagnostic::new(
+                        severity,
+                        ErrorKind::StrayElse,
+                        elem.span(),
+                        "`else if` in a block which isn't an `if`",
+                    )
+                    .with_label(block.span, "block opened here"),
+                ),
+                None => diagnostics.push(Diagnostic::new(
+                    severity,
+                    ErrorKind::StrayElse,
+                    elem.span(),
+                    "`else if` outside of a block",
+                )),
+            },
+            Instruction::Else => match open.last_mut() {
+                Some(Open { alt: Some(alt), .. }) => diagnostics.push(
+                    Diagnostic::new(
+                        severity,
+                        ErrorKind::StrayElse,
+                        elem.span(),
+                        "block already has an `else`",
+                    )
+                    .with_label(*alt, "first `else` here"),
+                ),
+                Some(block) => block.alt = Some(elem.span()),
+                None => diagnostics.push(Diagnostic::new(
+                    severity,
+                    ErrorKind::StrayElse,
+                    elem.span(),
+                    "`else` outside of a block",
+                )),
+            },
+            Instruction::End(subject) => match open.pop() {
+                Some(block) => {
+                    if !subject.is_empty() && !starts_with(block.subject, subject) {
+                        diagnostics.push(
+                            Diagnostic::new(
+                                severity,
+                                ErrorKind::MismatchedEnd,
+                                elem.span(),
+                                "`end` subject doesn't match the block it closes",
+                            )
+                            .with_label(block.span, "block opened here"),
+                        );
+                    }
+                }
+                None => diagnostics.push(Diagnostic::new(
+                    severity,
+                    ErrorKind::ExtraEnd,
+                    elem.span(),
+                    "`end` without a matching block",
+                )),
+            },
+            _ => (),
+        }
+    }
+
+    for block in open {
+        diagnostics.push(
+            Diagnostic::new(
+                severity,
+                ErrorKind::UnclosedBlock,
+                block.span,
+                "block is never closed",
+            )
+            .with_label(Span::new(source.len(), source.len()), "template ends here"),
+        );
+    }
+}
+
+/// how deeply blocks can be nested
+pub const MAX_BLOCK_DEPTH: usize = 128;
+
+/// replace blocks nested deeper than `MAX_BLOCK_DEPTH`,
+/// along with their `Else`s and `End`s, with text,
+/// reporting each to `diagnostics`
+pub fn limit_depth(
+    source: &str,
+    input: Vec<InstructionPos>,
+    diagnostics: &mut Diagnostics,
+) -> Vec<InstructionPos> {
+    // whether each open block was replaced
+    let mut open: Vec<bool> = Vec::new();
+
+    input
+        .into_iter()
+        .map(|elem| {
+            let replaced = match elem.inst {
+                Instruction::IfStart(_)
+                | Instruction::IterStart(_)
+                | Instruction::BlockStart(_) => {
+                    let replaced = open.len() >= MAX_BLOCK_DEPTH;
+                    // only report the outermost replaced blocks
+                    if open.len() == MAX_BLOCK_DEPTH {
+                        diagnostics.push(
+                            Diagnostic::error(
+                                ErrorKind::NestingLimit,
+                                elem.span(),
+                                &format!(
+                                    "blocks nested more than {} deep are passed through as text",
+                                    MAX_BLOCK_DEPTH
+                                ),
+                            )
+                            .with_note("move the inner blocks into a separate template"),
+                        );
+                    }
+
+                    open.push(replaced);
+                    replaced
+                }
+                Instruction::Else | Instruction::ElseIf(_) => open.last().cloned().unwrap_or(false),
+                Instruction::End(_) => open.pop().unwrap_or(false),
+                _ => false,
+            };
+
+            if replaced {
+                InstructionPos {
+                    inst: Instruction::Text(elem.get_source(source)),
+                    ..elem
+                }
+            } else {
+                elem
+            }
+        })
+        .collect()
+}
+
 /// an expression specified inside an instruction
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expression {
@@ -309,19 +511,75 @@ pub enum Expression {
     NegativeExpression {
         expr: Box<Expression>,
     },
+    ComparisonExpression {
+        operator: Comparison,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    LogicalExpression {
+        operator: Logical,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+}
+
+/// an operator comparing two values, like in JS
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Comparison {
+    /// the operator in JS, which compares loosely like templates do
+    pub fn js(self) -> &'static str {
+        match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterEqual => ">=",
+        }
+    }
+}
+
+/// an operator evaluating to one of two values, like in JS
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum Logical {
+    And,
+    Or,
+}
+
+impl Logical {
+    /// the operator in JS
+    pub fn js(self) -> &'static str {
+        match self {
+            Logical::And => "&&",
+            Logical::Or => "||",
+        }
+    }
 }
 
 /// built from instructions
 /// controls how the template behaves
+/// each has the span of the instruction it was built from
 #[derive(Debug, PartialEq, Clone)]
 pub enum Control {
     Text {
         value: String,
+        span: Span,
     },
     If {
         subject: Expression,
         body: Vec<Control>,
+        else_ifs: Vec<Branch>,
         alt: Vec<Control>,
+        span: Span,
     },
     Iter {
         suffix: u16,
@@ -329,23 +587,195 @@ pub enum Control {
         subject: Expression,
         body: Vec<Control>,
         alt: Vec<Control>,
+        span: Span,
     },
     Escaped {
         subject: Expression,
+        span: Span,
     },
     Raw {
         subject: Expression,
+        span: Span,
+    },
+    /// a partial which hasn't been inlined,
+    /// along with the scope it was imported within
+    Import {
+        path: String,
+        base: Vec<String>,
+        suffix: u16,
+        span: Span,
+    },
+    /// a region a template extending this one can override,
+    /// along with the scope it was defined within
+    Block {
+        name: String,
+        body: Vec<Control>,
+        base: Vec<String>,
+        suffix: u16,
+        span: Span,
+    },
+    /// the template this one overrides blocks of
+    Extends {
+        path: String,
+        span: Span,
+    },
+    /// the body of the overridden block
+    Super {
+        span: Span,
     },
 }
 
+/// an `{{{else if}}}` of an if-then-else control,
+/// used when no earlier subject is truthy
+#[derive(Debug, PartialEq, Clone)]
+pub struct Branch {
+    pub subject: Expression,
+    pub body: Vec<Control>,
+    pub span: Span,
+}
+
+impl Control {
+    /// get the source position of the instruction this was built from
+    pub fn span(&self) -> Span {
+        match self {
+            Control::Text { span, .. }
+            | Control::If { span, .. }
+            | Control::Iter { span, .. }
+            | Control::Escaped { span, .. }
+            | Control::Raw { span, .. }
+            | Control::Import { span, .. }
+            | Control::Block { span, .. }
+            | Control::Extends { span, .. }
+            | Control::Super { span } => *span,
+        }
+    }
+
+    /// replace the spans of this and any nested controls
+    pub fn map_spans<F: Fn(Span) -> Span>(&mut self, f: &F) {
+        match self {
+            Control::If {
+                span,
+                body,
+                else_ifs,
+                alt,
+                ..
+            } => {
+                *span = f(*span);
+                for branch in else_ifs.iter_mut() {
+                    branch.span = f(branch.span);
+                }
+                let branches = else_ifs
+                    .iter_mut()
+                    .flat_map(|branch| branch.body.iter_mut());
+                for control in body.iter_mut().chain(branches).chain(alt.iter_mut()) {
+                    control.map_spans(f);
+                }
+            }
+            Control::Iter {
+                span, body, alt, ..
+            } => {
+                *span = f(*span);
+                for control in body.iter_mut().chain(alt.iter_mut()) {
+                    control.map_spans(f);
+                }
+            }
+            Control::Block { span, body, .. } => {
+                *span = f(*span);
+                for control in body {
+                    control.map_spans(f);
+                }
+            }
+            Control::Text { span, .. }
+            | Control::Escaped { span, .. }
+            | Control::Raw { span, .. }
+            | Control::Import { span, .. }
+            | Control::Extends { span, .. }
+            | Control::Super { span } => *span = f(*span),
+        }
+    }
+}
+
 use paths;
 
+/// how tightly a binary operator binds its operands,
+/// from `||` binding the loosest to comparisons binding the tightest
+fn precedence(tok: &Token) -> Option<u8> {
+    match tok {
+        Token::Or => Some(1),
+        Token::And => Some(2),
+        Token::Equal | Token::NotEqual => Some(3),
+        Token::Less | Token::LessEqual | Token::Greater | Token::GreaterEqual => Some(4),
+        _ => None,
+    }
+}
+
+/// combine the operands of a binary operator
+fn binary(operator: Token, left: Expression, right: Expression) -> Option<Expression> {
+    let (left, right) = (Box::new(left), Box::new(right));
+
+    match operator {
+        Token::And | Token::Or => Some(Expression::LogicalExpression {
+            operator: if operator == Token::And {
+                Logical::And
+            } else {
+                Logical::Or
+            },
+            left,
+            right,
+        }),
+        _ => Some(Expression::ComparisonExpression {
+            operator: match operator {
+                Token::Equal => Comparison::Equal,
+                Token::NotEqual => Comparison::NotEqual,
+                Token::Less => Comparison::Less,
+                Token::LessEqual => Comparison::LessEqual,
+                Token::Greater => Comparison::Greater,
+                Token::GreaterEqual => Comparison::GreaterEqual,
+                _ => return None,
+            },
+            left,
+            right,
+        }),
+    }
+}
+
 /// generate an expression from an interator of Tokens
 fn generate_expression<I>(
     iter: &mut Peekable<I>,
     base: &[String],
     suffix: u16,
 ) -> Option<Expression>
+where
+    I: Iterator<Item = Token>,
+{
+    generate_binary(iter, base, suffix, 0)
+}
+
+/// generate an expression of operators binding at least as tightly as `min`,
+/// grouping operators of the same precedence from the left
+/// chains are built in a loop, recursing only for tighter operators on the right
+fn generate_binary<I>(
+    iter: &mut Peekable<I>,
+    base: &[String],
+    suffix: u16,
+    min: u8,
+) -> Option<Expression>
+where
+    I: Iterator<Item = Token>,
+{
+    let mut left = generate_operand(iter, base, suffix)?;
+
+    while let Some(current) = iter.peek().and_then(precedence).filter(|&p| p >= min) {
+        let operator = iter.next()?;
+        let right = generate_binary(iter, base, suffix, current + 1)?;
+        left = binary(operator, left, right)?;
+    }
+
+    Some(left)
+}
+
+/// generate a single operand of an expression
+fn generate_operand<I>(iter: &mut Peekable<I>, base: &[String], suffix: u16) -> Option<Expression>
 where
     I: Iterator<Item = Token>,
 {
@@ -354,15 +784,11 @@ where
 
     match (first, second) {
         // negative expression (`!stuff`)
-        (Some(Token::Bang), Some(_)) => {
-            if let Some(expr) = generate_expression(iter.by_ref(), base, suffix) {
-                Some(Expression::NegativeExpression {
-                    expr: Box::new(expr),
-                })
-            } else {
-                None
+        (Some(Token::Bang), Some(_)) => generate_operand(iter.by_ref(), base, suffix).map(|expr| {
+            Expression::NegativeExpression {
+                expr: Box::new(expr),
             }
-        }
+        }),
         // helper expression (`function.name, arg1, arg2`, `name(arg1, arg2)`)
         (Some(Token::Identifier(name)), Some(Token::LeftParen))
         | (Some(Token::LegacyHelper), Some(Token::Identifier(name))) => {
@@ -403,6 +829,15 @@ where
                 args,
             })
         }
+        // group (`(a || b)`)
+        (Some(Token::LeftParen), Some(_)) => {
+            let expr = generate_expression(iter.by_ref(), base, suffix)?;
+
+            match iter.next() {
+                Some(Token::RightParen) => Some(expr),
+                _ => None,
+            }
+        }
         // string literal (`"a literal string"`)
         (Some(Token::StringLiteral(value)), _) => Some(Expression::StringLiteral { value }),
         // identifier (`object.prop`, `../name`)
@@ -418,11 +853,16 @@ where
 }
 
 /// build the tree
+/// blocks with invalid subjects are passed through as text
+/// and reported to `diagnostics`
+/// malformed interpolations are handled according to `malformed`
 pub fn parse_tree<I>(
     source: &str,
     input: &mut I,
     base: &[String],
     suffix: u16,
+    malformed: MalformedPolicy,
+    diagnostics: &mut Diagnostics,
 ) -> (Vec<Control>, Option<InstructionPos>)
 where
     I: Iterator<Item = InstructionPos>,
@@ -434,21 +874,39 @@ where
         let InstructionPos { inst, .. } = inst_pos.clone();
         match inst {
             // convert a text instruction to a text control
-            Instruction::Text(value) => output.push(Control::Text { value }),
+            Instruction::Text(value) => output.push(Control::Text {
+                value,
+                span: inst_pos.span(),
+            }),
             // convert instruction to control
             // generate expression
-            Instruction::Escaped(subject) => {
-                if let Some(subject) =
-                    generate_expression(&mut subject.into_iter().peekable(), base, suffix)
-                {
-                    output.push(Control::Escaped { subject });
-                }
-            }
-            Instruction::Raw(subject) => {
-                if let Some(subject) =
-                    generate_expression(&mut subject.into_iter().peekable(), base, suffix)
-                {
-                    output.push(Control::Raw { subject });
+            Instruction::Escaped(subject) | Instruction::Raw(subject) => {
+                let raw = matches!(inst_pos.inst, Instruction::Raw(_));
+
+                match generate_expression(&mut subject.into_iter().peekable(), base, suffix) {
+                    Some(subject) if raw => output.push(Control::Raw {
+                        subject,
+                        span: inst_pos.span(),
+                    }),
+                    Some(subject) => output.push(Control::Escaped {
+                        subject,
+                        span: inst_pos.span(),
+                    }),
+                    None => {
+                        diagnostics.push(Diagnostic::new(
+                            malformed.severity(),
+                            ErrorKind::InvalidExpression,
+                            inst_pos.span(),
+                            &format!("malformed interpolation {}", malformed.outcome()),
+                        ));
+
+                        if malformed != MalformedPolicy::Drop {
+                            output.push(Control::Text {
+                                value: inst_pos.get_source(source),
+                                span: inst_pos.span(),
+                            });
+                        }
+                    }
                 }
             }
             // create an if-then-else control
@@ -457,23 +915,82 @@ where
                     generate_expression(&mut subject.into_iter().peekable(), base, suffix)
                 {
                     // recursively parse for body and alt child trees
-                    let (body, last) = parse_tree(source, input.by_ref(), base, suffix);
+                    let (body, mut last) =
+                        parse_tree(source, input.by_ref(), base, suffix, malformed, diagnostics);
+
+                    // each `else if` continues the chain until an `else` or `end`
+                    let mut else_ifs = Vec::new();
+                    while let Some(InstructionPos {
+                        inst: Instruction::ElseIf(condition),
+                        start,
+                        end,
+                    }) = last
+                    {
+                        let (b, next) = parse_tree(
+                            source,
+                            input.by_ref(),
+                            base,
+                            suffix,
+                            malformed,
+                            diagnostics,
+                        );
+                        last = next;
+
+                        match generate_expression(
+                            &mut condition.into_iter().peekable(),
+                            base,
+                            suffix,
+                        ) {
+                            Some(subject) => else_ifs.push(Branch {
+                                subject,
+                                body: b,
+                                span: Span::new(start, end),
+                            }),
+                            None => diagnostics.push(
+                                Diagnostic::error(
+                                    ErrorKind::InvalidExpression,
+                                    Span::new(start, end),
+                                    "`else if` subject is not a valid expression",
+                                )
+                                .with_note("the branch is removed"),
+                            ),
+                        }
+                    }
 
                     let alt = match last {
                         Some(InstructionPos {
                             inst: Instruction::Else,
                             ..
                         }) => {
-                            let (a, _) = parse_tree(source, input.by_ref(), base, suffix);
+                            let (a, _) = parse_tree(
+                                source,
+                                input.by_ref(),
+                                base,
+                                suffix,
+                                malformed,
+                                diagnostics,
+                            );
                             a
                         }
                         _ => Vec::new(),
                     };
 
-                    output.push(Control::If { subject, body, alt });
+                    output.push(Control::If {
+                        subject,
+                        body,
+                        else_ifs,
+                        alt,
+                        span: inst_pos.span(),
+                    });
                 } else {
+                    diagnostics.error(
+                        ErrorKind::InvalidExpression,
+                        inst_pos.span(),
+                        "block subject is not a valid expression",
+                    );
                     output.push(Control::Text {
                         value: inst_pos.get_source(source),
+                        span: inst_pos.span(),
                     });
                 }
             }
@@ -495,11 +1012,14 @@ where
                         input.by_ref(),
                         &paths::iter_element(&path, suffix),
                         suffix + 1,
+                        malformed,
+                        diagnostics,
                     );
 
+                    // `else if` was already reported, and is used as an `else`
                     let alt = match last {
                         Some(InstructionPos {
-                            inst: Instruction::Else,
+                            inst: Instruction::Else | Instruction::ElseIf(_),
                             ..
                         }) => {
                             let (a, _) = parse_tree(
@@ -507,6 +1027,8 @@ where
                                 input.by_ref(),
                                 &paths::iter_element(&path, suffix),
                                 suffix + 1,
+                                malformed,
+                                diagnostics,
                             );
                             a
                         }
@@ -519,14 +1041,121 @@ where
                         subject,
                         body,
                         alt,
+                        span: inst_pos.span(),
                     });
                 } else {
+                    diagnostics.error(
+                        ErrorKind::InvalidExpression,
+                        inst_pos.span(),
+                        "block subject is not a valid expression",
+                    );
                     output.push(Control::Text {
                         value: inst_pos.get_source(source),
+                        span: inst_pos.span(),
                     });
                 }
             }
-            Instruction::Else | Instruction::End(_) => {
+            // keep the scope of an import so it can be inlined
+            Instruction::Import(subject) => match subject.as_slice() {
+                [Token::StringLiteral(path)] => output.push(Control::Import {
+                    path: path.clone(),
+                    base: base.to_vec(),
+                    suffix,
+                    span: inst_pos.span(),
+                }),
+                _ => {
+                    diagnostics.error(
+                        ErrorKind::InvalidExpression,
+                        inst_pos.span(),
+                        "import path must be a string literal",
+                    );
+                    output.push(Control::Text {
+                        value: inst_pos.get_source(source),
+                        span: inst_pos.span(),
+                    });
+                }
+            },
+            // create a block, which can't have an alt
+            Instruction::BlockStart(subject) => match subject.as_slice() {
+                [Token::Identifier(name)] => {
+                    let (mut body, mut last) =
+                        parse_tree(source, input.by_ref(), base, suffix, malformed, diagnostics);
+
+                    // keep an `else` and everything after it in the body
+                    while let Some(
+                        alt @ InstructionPos {
+                            inst: Instruction::Else | Instruction::ElseIf(_),
+                            ..
+                        },
+                    ) = last
+                    {
+                        diagnostics.push(
+                            Diagnostic::warning(
+                                ErrorKind::StrayElse,
+                                alt.span(),
+                                "blocks can't have an `else`",
+                            )
+                            .with_label(inst_pos.span(), "block opened here")
+                            .with_note("it is passed through as text"),
+                        );
+                        body.push(Control::Text {
+                            value: alt.get_source(source),
+                            span: alt.span(),
+                        });
+
+                        let (rest, next) = parse_tree(
+                            source,
+                            input.by_ref(),
+                            base,
+                            suffix,
+                            malformed,
+                            diagnostics,
+                        );
+                        body.extend(rest);
+                        last = next;
+                    }
+
+                    output.push(Control::Block {
+                        name: name.clone(),
+                        body,
+                        base: base.to_vec(),
+                        suffix,
+                        span: inst_pos.span(),
+                    });
+                }
+                _ => {
+                    diagnostics.error(
+                        ErrorKind::InvalidExpression,
+                        inst_pos.span(),
+                        "block name must be an identifier",
+                    );
+                    output.push(Control::Text {
+                        value: inst_pos.get_source(source),
+                        span: inst_pos.span(),
+                    });
+                }
+            },
+            Instruction::Extends(subject) => match subject.as_slice() {
+                [Token::StringLiteral(path)] => output.push(Control::Extends {
+                    path: path.clone(),
+                    span: inst_pos.span(),
+                }),
+                _ => {
+                    diagnostics.error(
+                        ErrorKind::InvalidExpression,
+                        inst_pos.span(),
+                        "extended path must be a string literal",
+                    );
+                    output.push(Control::Text {
+                        value: inst_pos.get_source(source),
+                        span: inst_pos.span(),
+                    });
+                }
+            },
+            Instruction::Super => output.push(Control::Super {
+                span: inst_pos.span(),
+            }),
+            Instruction::Else | Instruction::ElseIf(_) | Instruction::End(_) => {
                 last = Some(inst_pos);
                 break;
             }
@@ -535,3 +1164,152 @@ where
 
     (output, last)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer;
+
+    /// diagnostics from checking the blocks of `template` with `severity`
+    fn check(template: &str, severity: Severity) -> Vec<(ErrorKind, Span, Vec<Span>)> {
+        let mut diagnostics = Diagnostics::new();
+        let tokens = lexer::lex(template, MalformedPolicy::Text, &mut diagnostics);
+        let instructions = parse_instructions(template, tokens, &mut diagnostics);
+        check_blocks(template, &instructions, severity, &mut diagnostics);
+
+        diagnostics
+            .iter()
+            .inspect(|d| assert_eq!(d.severity, severity))
+            .map(|d| (d.code, d.span, d.labels.iter().map(|l| l.span).collect()))
+            .collect()
+    }
+
+    fn strict(template: &str) -> Vec<(ErrorKind, Span, Vec<Span>)> {
+        check(template, Severity::Error)
+    }
+
+    #[test]
+    fn strict_balanced() {
+        assert_eq!(
+            strict("{{{ each a }}}{{{ if b }}}c{{{ else }}}d{{{ end }}}{{{ end a }}}"),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn strict_unclosed() {
+        assert_eq!(
+            strict("{{{ each a }}}{{{ if b }}}c{{{ end }}}"),
+            vec![(
+                ErrorKind::UnclosedBlock,
+                Span::new(0, 14),
+                vec![Span::new(38, 38)]
+            )]
+        );
+
+        // lenient mode reports it as a warning
+        assert_eq!(
+            check("{{{ each a }}}", Severity::Warning),
+            vec![(
+                ErrorKind::UnclosedBlock,
+                Span::new(0, 14),
+                vec![Span::new(14, 14)]
+            )]
+        );
+    }
+
+    #[test]
+    fn strict_extra_end() {
+        assert_eq!(
+            strict("{{{ if a }}}b{{{ end }}}{{{ end }}}"),
+            vec![(ErrorKind::ExtraEnd, Span::new(24, 35), vec![])]
+        );
+    }
+
+    #[test]
+    fn strict_mismatched_end() {
+        assert_eq!(
+            strict("<!-- IF foo -->a<!-- ENDIF bar -->"),
+            vec![(
+                ErrorKind::MismatchedEnd,
+                Span::new(16, 34),
+                vec![Span::new(0, 15)]
+            )]
+        );
+    }
+
+    #[test]
+    fn strict_stray_else() {
+        assert_eq!(
+            strict("a{{{ else }}}b"),
+            vec![(ErrorKind::StrayElse, Span::new(1, 13), vec![])]
+        );
+        assert_eq!(
+            strict("{{{ if a }}}b{{{ else }}}c{{{ else }}}d{{{ end }}}"),
+            vec![(
+                ErrorKind::StrayElse,
+                Span::new(26, 38),
+                vec![Span::new(13, 25)]
+            )]
+        );
+    }
+
+    #[test]
+    fn strict_else_if() {
+        assert_eq!(
+            strict("{{{ if a }}}b{{{ else if c }}}d{{{ else if e }}}f{{{ else }}}g{{{ end }}}"),
+            vec![]
+        );
+        assert_eq!(
+            strict("{{{ if a }}}b{{{ else }}}c{{{ else if d }}}e{{{ end }}}"),
+            vec![(
+                ErrorKind::StrayElse,
+                Span::new(26, 43),
+                vec![Span::new(13, 25)]
+            )]
+        );
+        assert_eq!(
+            strict("{{{ each a }}}b{{{ else if c }}}d{{{ end }}}"),
+            vec![(
+                ErrorKind::StrayElse,
+                Span::new(15, 32),
+                vec![Span::new(0, 14)]
+            )]
+        );
+    }
+
+    #[test]
+    fn else_if_chain() {
+        let template = "{{{ if a }}}1{{{ else if b }}}2{{{ else if !c }}}3{{{ else }}}4{{{ end }}}";
+        let mut diagnostics = Diagnostics::new();
+        let tokens = lexer::lex(template, MalformedPolicy::Text, &mut diagnostics);
+        let instructions = parse_instructions(template, tokens, &mut diagnostics);
+        let (tree, _) = parse_tree(
+            template,
+            &mut instructions.into_iter(),
+            &[],
+            0,
+            MalformedPolicy::Text,
+            &mut diagnostics,
+        );
+
+        assert!(diagnostics.is_empty());
+        match tree.as_slice() {
+            [Control::If { else_ifs, alt, .. }] => {
+                assert_eq!(
+                    else_ifs
+                        .iter()
+                        .map(|branch| branch.span)
+                        .collect::<Vec<_>>(),
+                    vec![Span::new(13, 30), Span::new(31, 49)]
+                );
+                assert!(matches!(
+                    else_ifs[1].subject,
+                    Expression::NegativeExpression { .. }
+                ));
+                assert_eq!(alt.len(), 1);
+            }
+            _ => panic!("expected a single `if`, got {:?}", tree),
+        }
+    }
+}
@@ -22,8 +22,8 @@ pub fn relative(base: &[String], rel: &[String]) -> Vec<String> {
         let mut output = base.to_vec();
         let mut iter = rel.iter().peekable();
 
-        match iter.peek().unwrap().as_str() {
-            "../" | "./" => {
+        match iter.peek().map(|part| part.as_str()) {
+            Some("../") | Some("./") => {
                 iter.next();
             }
             _ => (),
@@ -173,5 +173,8 @@ mod tests {
             ),
             ["thing".to_string()]
         );
+
+        let base = ["a".to_string()];
+        assert_eq!(relative(&base, &[]), base);
     }
 }
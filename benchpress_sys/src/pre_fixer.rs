@@ -1,47 +1,275 @@
+use diagnostics::{Diagnostic, Diagnostics};
+use error::{ErrorKind, Span};
+use lexer;
+use report::{self, Position};
+
 use regex::{Captures, Regex};
 
+/// maps each position in the pre-fixed source
+/// back to a position in the original template
+#[derive(Debug, PartialEq, Clone)]
+pub struct OffsetMap {
+    // one entry for each byte, plus one for the end
+    offsets: Vec<usize>,
+}
+
+impl OffsetMap {
+    /// a map for source which wasn't rewritten
+    pub fn identity(len: usize) -> OffsetMap {
+        OffsetMap {
+            offsets: (0..=len).collect(),
+        }
+    }
+
+    /// translate a byte offset back to the original template
+    pub fn offset(&self, offset: usize) -> usize {
+        let last = self.offsets.len() - 1;
+        self.offsets[offset.min(last)]
+    }
+
+    /// translate a span back to the original template
+    pub fn span(&self, span: Span) -> Span {
+        let start = self.offset(span.start);
+        let end = if span.end > span.start {
+            self.offset(span.end - 1) + 1
+        } else {
+            start
+        };
+
+        Span::new(start, end.max(start))
+    }
+
+    /// translate a byte offset back to a line and column in the original template
+    pub fn position(&self, template: &str, offset: usize) -> Position {
+        report::position(template, self.offset(offset))
+    }
+
+    /// chain this map with one for the source it was rewritten from
+    fn then(&self, inner: &OffsetMap) -> OffsetMap {
+        OffsetMap {
+            offsets: self.offsets.iter().map(|&o| inner.offset(o)).collect(),
+        }
+    }
+}
+
+/// rewritten text, along with the offset each byte came from
+#[derive(Debug, Clone, Default)]
+struct Rewrite {
+    text: String,
+    offsets: Vec<usize>,
+}
+
+impl Rewrite {
+    fn new() -> Rewrite {
+        Rewrite::default()
+    }
+
+    /// copy `text` unchanged from `start`
+    fn copy(&mut self, text: &str, start: usize) {
+        self.text.push_str(text);
+        self.offsets.extend(start..start + text.len());
+    }
+
+    /// replace `orig` at `start` with `new`
+    /// text they have in common keeps its original position
+    /// and anything inserted points to where it was inserted
+    fn replace(&mut self, new: &str, orig: &str, start: usize) {
+        fn common_len<I: Iterator<Item = (char, char)>>(pairs: I) -> usize {
+            pairs
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a.len_utf8())
+                .sum()
+        }
+
+        let prefix = common_len(new.chars().zip(orig.chars()));
+        let suffix = common_len(
+            new[prefix..]
+                .chars()
+                .rev()
+                .zip(orig[prefix..].chars().rev()),
+        );
+
+        let new_middle = &new[prefix..new.len() - suffix];
+        let orig_middle = &orig[prefix..orig.len() - suffix];
+        let middle_start = start + prefix;
+        // original text may still appear within the inserted text
+        let found = if orig_middle.is_empty() {
+            None
+        } else {
+            new_middle.find(orig_middle)
+        };
+
+        self.text.push_str(new);
+        self.offsets.extend(start..middle_start);
+        self.offsets
+            .extend((0..new_middle.len()).map(|i| match found {
+                Some(at) if i >= at && i < at + orig_middle.len() => middle_start + i - at,
+                Some(at) if i >= at => middle_start + orig_middle.len() - 1,
+                _ => middle_start,
+            }));
+        self.offsets
+            .extend(start + orig.len() - suffix..start + orig.len());
+    }
+
+    fn append(&mut self, other: Rewrite) {
+        self.text.push_str(&other.text);
+        self.offsets.extend(other.offsets);
+    }
+
+    /// finish with a map, given the length of the source
+    fn into_map(mut self, len: usize) -> (String, OffsetMap) {
+        self.offsets.push(len);
+        (
+            self.text,
+            OffsetMap {
+                offsets: self.offsets,
+            },
+        )
+    }
+}
+
+/// check if `offset` is within one of the `comments`
+fn in_comment(comments: &[Span], offset: usize) -> bool {
+    comments
+        .iter()
+        .any(|comment| comment.start <= offset && offset < comment.end)
+}
+
+/// how deeply legacy `BEGIN` blocks are duplicated with a fallback
+pub const MAX_LEGACY_DEPTH: usize = 6;
+
 // `<!-- BEGIN stuff -->` => `<!-- BEGIN ../stuff -->` and `<!-- BEGIN stuff -->`
 // we need to add the fallback by duplicating under a different key
 // only apply to nested blocks
-fn fix_iter(input: &str, first: bool) -> String {
+// `offset` is the position of `input` within the template
+// `depth` is how many blocks `input` is nested within
+// blocks within `comments` are left alone
+fn fix_iter(
+    input: &str,
+    depth: usize,
+    offset: usize,
+    comments: &[Span],
+    diagnostics: &mut Diagnostics,
+) -> Rewrite {
     lazy_static! {
         static ref LEGACY_ITER_PATTERN: Regex =
-            Regex::new(r"<!-- BEGIN ([^./][@a-zA-Z0-9/.\-_:]+?) -->([\s\S]*)").unwrap();
+            Regex::new(r"<!-- BEGIN ([^./][@a-zA-Z0-9/.\-_:]+?) -->").unwrap();
     }
 
-    LEGACY_ITER_PATTERN.replace(input, |caps: &Captures| {
+    let mut output = Rewrite::new();
+    let mut input = input;
+    let mut offset = offset;
+
+    // handle each block at this level in turn
+    while let Some(caps) = LEGACY_ITER_PATTERN.captures(input) {
+        let (tag_start, after_start) = caps.get(0).map_or((0, 0), |m| (m.start(), m.end()));
+
+        output.copy(&input[..tag_start], offset);
+
         let subject = &caps[1];
-        let after = &caps[2];
+        let after = &input[after_start..];
+        let tag = &input[tag_start..after_start];
+        let tag_offset = offset + tag_start;
+        let after_offset = offset + after_start;
+
+        if in_comment(comments, tag_offset) {
+            output.copy(tag, tag_offset);
+
+            input = after;
+            offset = after_offset;
+            continue;
+        }
 
         let end = format!("<!-- END {} -->", subject);
 
-        let mut split = after.splitn(2, end.as_str());
-        match (split.next(), split.next()) {
-            (Some(body), Some(rest)) => {
-                let body = fix_iter(body, false);
-                let rest = fix_iter(rest, first);
+        match after.find(end.as_str()) {
+            Some(body_len) => {
+                let body = &after[..body_len];
+                let end_offset = after_offset + body_len;
 
-                if first {
-                    format!("<!-- BEGIN {} -->{}<!-- END {} -->{}", subject, body, subject, rest)
+                if depth == 0 {
+                    output.copy(tag, tag_offset);
+                    output.append(fix_iter(
+                        body,
+                        depth + 1,
+                        after_offset,
+                        comments,
+                        diagnostics,
+                    ));
+                    output.copy(&end, end_offset);
+                } else if depth > MAX_LEGACY_DEPTH {
+                    diagnostics.push(
+                        Diagnostic::warning(
+                            ErrorKind::NestingLimit,
+                            Span::new(tag_offset, after_offset),
+                            &format!(
+                                "legacy `BEGIN` blocks nested more than {} deep have no fallback",
+                                MAX_LEGACY_DEPTH
+                            ),
+                        )
+                        .with_note("use `{{{each}}}` with an explicit path instead"),
+                    );
+
+                    output.copy(tag, tag_offset);
+                    output.copy(body, after_offset);
+                    output.copy(&end, end_offset);
                 } else {
-                    format!(
-                        "<!-- IF ../{} --><!-- BEGIN ../{} -->{}<!-- END ../{} --><!-- ELSE --><!-- BEGIN {} -->{}<!-- END {} --><!-- ENDIF ../{} -->{}",
-                        subject, subject, body, subject, subject, body, subject, subject, rest
-                    )
+                    let body = fix_iter(body, depth + 1, after_offset, comments, diagnostics);
+                    let message = format!(
+                        "legacy nested `BEGIN {}` is duplicated to fall back to `../{}`",
+                        subject, subject
+                    );
+                    diagnostics.push(
+                        Diagnostic::warning(
+                            ErrorKind::LegacySyntax,
+                            Span::new(tag_offset, after_offset),
+                            &message,
+                        )
+                        .with_note("use `{{{each}}}` with an explicit path instead"),
+                    );
+
+                    output.replace(
+                        &format!("<!-- IF ../{} --><!-- BEGIN ../{} -->", subject, subject),
+                        tag,
+                        tag_offset,
+                    );
+                    output.append(body.clone());
+                    output.replace(
+                        &format!(
+                            "<!-- END ../{} --><!-- ELSE --><!-- BEGIN {} -->",
+                            subject, subject
+                        ),
+                        &end,
+                        end_offset,
+                    );
+                    output.append(body);
+                    output.replace(
+                        &format!("<!-- END {} --><!-- ENDIF ../{} -->", subject, subject),
+                        &end,
+                        end_offset,
+                    );
                 }
-            },
-            (Some(rest), None) => {
-                let rest = fix_iter(rest, first);
 
-                format!("<!-- BEGIN {} -->{}", subject, rest)
-            },
-            _ => unreachable!(),
+                input = &after[body_len + end.len()..];
+                offset = end_offset + end.len();
+            }
+            None => {
+                output.copy(tag, tag_offset);
+
+                input = after;
+                offset = after_offset;
+            }
         }
-    }).into_owned()
+    }
+
+    output.copy(input, offset);
+
+    output
 }
 
 // combined regex replacement
-fn combined(input: &str) -> String {
+// anything within a comment is left alone
+fn combined(input: &str, diagnostics: &mut Diagnostics) -> Rewrite {
     lazy_static! {
         static ref COMBINED: Regex = Regex::new(
             r"(?x)
@@ -71,41 +299,98 @@ fn combined(input: &str) -> String {
         .unwrap();
     }
 
-    COMBINED
-        .replace_all(input, |caps: &Captures| {
-            if caps.name("if_helpers").is_some() {
-                // add root data to legacy if helpers
-                let name = &caps["if_helpers_name"].to_string();
-                let args = &caps["if_helpers_args"].to_string();
+    let mut replacement = |caps: &Captures| {
+        let span = caps
+            .get(0)
+            .map_or(Span::default(), |m| Span::new(m.start(), m.end()));
 
-                if args.is_empty() {
-                    format!("<!-- IF function.{}, @root -->", name)
-                } else {
-                    format!("<!-- IF function.{}, @root, {} -->", name, args)
-                }
-            } else if caps.name("loop_helpers").is_some() {
-                // add value context for in-loop helpers
-                let name = &caps["loop_helpers_name"].to_string();
-                format!("{{function.{}, @value}}", name)
-            } else if caps.name("outside_tokens").is_some() {
-                // wrap `@key`, `@value`, `@index` in mustaches
-                // if they aren't in a mustache already
-                let orig = &caps[0];
-
-                if let Some(lone) = caps.name("outside_tokens_lone") {
-                    format!("{{{}}}", lone.as_str())
-                } else {
-                    orig.to_string()
-                }
+        if caps.name("if_helpers").is_some() {
+            // add root data to legacy if helpers
+            let name = &caps["if_helpers_name"].to_string();
+            let args = &caps["if_helpers_args"].to_string();
+
+            diagnostics.push(
+                Diagnostic::warning(
+                    ErrorKind::LegacySyntax,
+                    span,
+                    &format!("legacy helper `function.{}` is passed `@root`", name),
+                )
+                .with_note("use `{{{if helper(@root)}}}` instead"),
+            );
+
+            if args.is_empty() {
+                format!("<!-- IF function.{}, @root -->", name)
+            } else {
+                format!("<!-- IF function.{}, @root, {} -->", name, args)
+            }
+        } else if caps.name("loop_helpers").is_some() {
+            // add value context for in-loop helpers
+            let name = &caps["loop_helpers_name"].to_string();
+
+            diagnostics.push(
+                Diagnostic::warning(
+                    ErrorKind::LegacySyntax,
+                    span,
+                    &format!("legacy helper `function.{}` is passed `@value`", name),
+                )
+                .with_note("use `{helper(@value)}` instead"),
+            );
+            format!("{{function.{}, @value}}", name)
+        } else if caps.name("outside_tokens").is_some() {
+            // wrap `@key`, `@value`, `@index` in mustaches
+            // if they aren't in a mustache already
+            let orig = &caps[0];
+
+            if let Some(lone) = caps.name("outside_tokens_lone") {
+                diagnostics.push(
+                    Diagnostic::warning(
+                        ErrorKind::LegacySyntax,
+                        span,
+                        &format!("`{}` outside of an interpolation", lone.as_str()),
+                    )
+                    .with_note(&format!("use `{{{}}}` instead", lone.as_str())),
+                );
+
+                format!("{{{}}}", lone.as_str())
             } else {
-                String::new()
+                orig.to_string()
             }
-        })
-        .into_owned()
+        } else {
+            String::new()
+        }
+    };
+
+    let comments = lexer::comments(input);
+    let mut output = Rewrite::new();
+    let mut last = 0;
+    for caps in COMBINED.captures_iter(input) {
+        if let Some(found) = caps.get(0) {
+            if in_comment(&comments, found.start()) {
+                continue;
+            }
+
+            output.copy(&input[last..found.start()], last);
+            output.replace(&replacement(&caps), found.as_str(), found.start());
+            last = found.end();
+        }
+    }
+    output.copy(&input[last..], last);
+
+    output
 }
 
-pub fn pre_fix(input: &str) -> String {
-    combined(&fix_iter(input, true))
+/// rewrite legacy syntax, reporting each rewrite to `diagnostics`
+/// returns the rewritten source with a map back to `input`
+pub fn pre_fix(input: &str, diagnostics: &mut Diagnostics) -> (String, OffsetMap) {
+    let comments = lexer::comments(input);
+    let (fixed, fixed_map) = fix_iter(input, 0, 0, &comments, diagnostics).into_map(input.len());
+
+    // rewrites by `combined` are reported relative to `fixed`
+    let mut combined_diagnostics = Diagnostics::new();
+    let (output, combined_map) = combined(&fixed, &mut combined_diagnostics).into_map(fixed.len());
+    diagnostics.extend(combined_diagnostics.map_spans(|span| fixed_map.span(span)));
+
+    (output, combined_map.then(&fixed_map))
 }
 
 #[cfg(test)]
@@ -150,7 +435,14 @@ mod tests {
         <!-- END -->
         ";
 
-        assert_eq!(fix_iter(source, true), expected);
+        let mut diagnostics = Diagnostics::new();
+        assert_eq!(fix_iter(source, 0, 0, &[], &mut diagnostics).text, expected);
+
+        let inner = source.find("<!-- BEGIN inner -->").unwrap();
+        assert_eq!(
+            diagnostics.iter().map(|d| d.span).collect::<Vec<_>>(),
+            vec![Span::new(inner, inner + 20)]
+        );
 
         let source = r##"
         <div id="container">
@@ -192,7 +484,10 @@ mod tests {
         </div><!-- END container -->
         "##;
 
-        assert_eq!(fix_iter(source, true), expected);
+        assert_eq!(
+            fix_iter(source, 0, 0, &[], &mut Diagnostics::new()).text,
+            expected
+        );
     }
 
     #[test]
@@ -204,7 +499,7 @@ mod tests {
         {function.foo_bar, @value}
         ";
 
-        assert_eq!(combined(source), expected);
+        assert_eq!(combined(source, &mut Diagnostics::new()).text, expected);
     }
 
     #[test]
@@ -222,7 +517,7 @@ mod tests {
         {@index} : {@value}
         ";
 
-        assert_eq!(combined(source), expected);
+        assert_eq!(combined(source, &mut Diagnostics::new()).text, expected);
     }
 
     #[test]
@@ -246,6 +541,55 @@ mod tests {
         <!-- END -->
         ";
 
-        assert_eq!(combined(source), expected);
+        assert_eq!(combined(source, &mut Diagnostics::new()).text, expected);
+    }
+
+    #[test]
+    fn offset_map() {
+        let source = "<!-- BEGIN outer -->
+<!-- BEGIN inner -->{inner.c}<!-- END inner -->
+<!-- END outer -->{function.d} @key";
+        let (output, map) = pre_fix(source, &mut Diagnostics::new());
+
+        // both copies of the duplicated body map back to the original
+        let original = source.find("{inner.c}").unwrap();
+        let copies: Vec<usize> = output.match_indices("{inner.c}").map(|(i, _)| i).collect();
+        assert_eq!(copies.len(), 2);
+        for copy in copies {
+            assert_eq!(
+                map.span(Span::new(copy, copy + 9)),
+                Span::new(original, original + 9)
+            );
+        }
+
+        // inserted arguments point to where they were inserted
+        let helper = source.find("{function.d}").unwrap();
+        let rewritten = output.find("{function.d, @value}").unwrap();
+        assert_eq!(
+            map.span(Span::new(rewritten, rewritten + 20)),
+            Span::new(helper, helper + 12)
+        );
+
+        let key = output.find("{@key}").unwrap();
+        assert_eq!(
+            map.span(Span::new(key, key + 6)),
+            Span::new(source.len() - 4, source.len())
+        );
+        assert_eq!(
+            map.position(source, key + 1),
+            Position {
+                line: 3,
+                column: 32
+            }
+        );
+        assert_eq!(map.offset(output.len()), source.len());
+    }
+
+    #[test]
+    fn identity_map() {
+        let (output, map) = pre_fix("{a} {{b}}", &mut Diagnostics::new());
+
+        assert_eq!(output, "{a} {{b}}");
+        assert_eq!(map, OffsetMap::identity(9));
     }
 }
@@ -0,0 +1,942 @@
+use diagnostics::{Diagnostic, Diagnostics};
+use error::{ErrorKind, Span};
+use helpers::HelperRegistry;
+use imports::IMPORT_HELPER;
+use parser::{Comparison, Control, Expression, Logical};
+use templates;
+
+use json::JsonValue;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::ops::Add;
+use std::ptr;
+use std::rc::Rc;
+
+/// a value within a rendering template,
+/// following the JS semantics of the values in generated code
+#[derive(Debug, Clone)]
+pub enum Value<'a> {
+    Undefined,
+    Json(&'a JsonValue),
+    /// returned from a helper
+    Owned(Rc<JsonValue>),
+    Bool(bool),
+    Number(f64),
+    Str(Cow<'a, str>),
+}
+
+/// format a number like JS `Number.prototype.toString`
+fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if n == 0.0 {
+        // including negative zero
+        "0".to_string()
+    } else if n.abs() >= 1e21 || n.abs() < 1e-6 {
+        let exp = format!("{:e}", n);
+        if exp.contains("e-") {
+            exp
+        } else {
+            exp.replace('e', "e+")
+        }
+    } else {
+        format!("{}", n)
+    }
+}
+
+/// whether a JSON value is truthy in JS
+fn json_truthy(value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Null => false,
+        JsonValue::Boolean(b) => *b,
+        JsonValue::Number(_) => {
+            let n = value.as_f64().unwrap_or(0.0);
+            n != 0.0 && !n.is_nan()
+        }
+        JsonValue::Short(_) | JsonValue::String(_) => !value.is_empty(),
+        JsonValue::Object(_) | JsonValue::Array(_) => true,
+    }
+}
+
+/// convert a JSON value to a string like JS `String`
+fn json_to_string(value: &JsonValue) -> Cow<'_, str> {
+    match value {
+        JsonValue::Null => "null".into(),
+        JsonValue::Boolean(b) => b.to_string().into(),
+        JsonValue::Number(_) => number_to_string(value.as_f64().unwrap_or(0.0)).into(),
+        JsonValue::Short(_) | JsonValue::String(_) => value.as_str().unwrap_or("").into(),
+        JsonValue::Object(_) => "[object Object]".into(),
+        // elements are joined, with empty strings for null
+        JsonValue::Array(items) => items
+            .iter()
+            .map(|item| {
+                if item.is_null() {
+                    "".into()
+                } else {
+                    json_to_string(item)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+            .into(),
+    }
+}
+
+/// get the UTF-16 code unit at an index of a string, as a string
+fn char_at(value: &str, index: usize) -> Option<String> {
+    value
+        .encode_utf16()
+        .nth(index)
+        .map(|unit| String::from_utf16_lossy(&[unit]))
+}
+
+/// parse a canonical array index, like JS property keys
+fn array_index(key: &str) -> Option<usize> {
+    match key.parse::<u32>() {
+        Ok(index) if index < u32::MAX && index.to_string() == key => Some(index as usize),
+        _ => None,
+    }
+}
+
+/// JS property access `value[key]` on a string
+fn str_get<'a>(value: &str, key: &str) -> Value<'a> {
+    if key == "length" {
+        Value::Number(value.encode_utf16().count() as f64)
+    } else {
+        array_index(key)
+            .and_then(|index| char_at(value, index))
+            .map_or(Value::Undefined, |ch| Value::Str(ch.into()))
+    }
+}
+
+/// JS property access `value[key]` on a JSON value
+fn json_get<'a>(value: &'a JsonValue, key: &str) -> Value<'a> {
+    match value {
+        JsonValue::Object(object) => object.get(key).map_or(Value::Undefined, Value::Json),
+        JsonValue::Array(items) => {
+            if key == "length" {
+                Value::Number(items.len() as f64)
+            } else {
+                array_index(key)
+                    .and_then(|index| items.get(index))
+                    .map_or(Value::Undefined, Value::Json)
+            }
+        }
+        JsonValue::Short(_) | JsonValue::String(_) => str_get(value.as_str().unwrap_or(""), key),
+        _ => Value::Undefined,
+    }
+}
+
+/// keys of an object or array, in the order of JS `Object.keys`
+fn json_keys(value: &JsonValue) -> Vec<(String, &JsonValue)> {
+    match value {
+        JsonValue::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| (index.to_string(), item))
+            .collect(),
+        JsonValue::Object(object) => {
+            // integer keys come first, in ascending order
+            let mut entries: Vec<(String, &JsonValue)> = object
+                .iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect();
+            entries.sort_by_key(|(key, _)| array_index(key).unwrap_or(usize::MAX));
+
+            entries
+        }
+        _ => Vec::new(),
+    }
+}
+
+impl<'a> Value<'a> {
+    /// a string, like text in a template
+    pub fn text(value: &'a str) -> Value<'a> {
+        Value::Str(value.into())
+    }
+
+    fn json(&self) -> Option<&JsonValue> {
+        match self {
+            Value::Json(value) => Some(value),
+            Value::Owned(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// take ownership of any borrowed data
+    fn detach<'b>(self) -> Value<'b> {
+        match self {
+            Value::Undefined => Value::Undefined,
+            Value::Json(value) => Value::Owned(Rc::new(value.clone())),
+            Value::Owned(value) => Value::Owned(value),
+            Value::Bool(b) => Value::Bool(b),
+            Value::Number(n) => Value::Number(n),
+            Value::Str(s) => Value::Str(s.into_owned().into()),
+        }
+    }
+
+    /// convert to a JSON value, unless it already is one
+    fn to_json(&self) -> Option<JsonValue> {
+        match self {
+            Value::Undefined => Some(JsonValue::Null),
+            Value::Json(_) | Value::Owned(_) => None,
+            Value::Bool(b) => Some((*b).into()),
+            Value::Number(n) => Some((*n).into()),
+            Value::Str(s) => Some(s.as_ref().into()),
+        }
+    }
+
+    pub fn truthy(&self) -> bool {
+        match self {
+            Value::Undefined => false,
+            Value::Json(_) | Value::Owned(_) => self.json().is_some_and(json_truthy),
+            Value::Bool(b) => *b,
+            Value::Number(n) => *n != 0.0 && !n.is_nan(),
+            Value::Str(s) => !s.is_empty(),
+        }
+    }
+
+    /// convert to a string like JS `String`
+    pub fn to_js_string(&self) -> Cow<'a, str> {
+        match self {
+            Value::Undefined => "undefined".into(),
+            Value::Json(value) => json_to_string(value),
+            Value::Owned(value) => json_to_string(value).into_owned().into(),
+            Value::Bool(b) => b.to_string().into(),
+            Value::Number(n) => number_to_string(*n).into(),
+            Value::Str(s) => s.clone(),
+        }
+    }
+
+    /// convert to a number like JS `Number`,
+    /// for values which aren't converted to strings when added
+    fn to_number(&self) -> f64 {
+        match self {
+            Value::Undefined => f64::NAN,
+            Value::Bool(b) => {
+                if *b {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Value::Number(n) => *n,
+            Value::Str(s) if s.trim().is_empty() => 0.0,
+            Value::Str(s) => s.trim().parse().unwrap_or(f64::NAN),
+            Value::Json(_) | Value::Owned(_) => match self.json() {
+                Some(JsonValue::Null) => 0.0,
+                Some(JsonValue::Boolean(b)) => Value::Bool(*b).to_number(),
+                Some(value) => value.as_f64().unwrap_or(f64::NAN),
+                None => f64::NAN,
+            },
+        }
+    }
+
+    /// whether JS `+` would concatenate this as a string
+    fn is_stringy(&self) -> bool {
+        match self {
+            Value::Str(_) => true,
+            _ => self
+                .json()
+                .is_some_and(|value| value.is_string() || value.is_object() || value.is_array()),
+        }
+    }
+
+    /// JS property access `value[key]`
+    fn get(&self, key: &str) -> Value<'a> {
+        match *self {
+            Value::Json(value) => json_get(value, key),
+            Value::Owned(ref value) => json_get(value, key).detach(),
+            Value::Str(ref s) => str_get(s, key),
+            _ => Value::Undefined,
+        }
+    }
+
+    /// the `guard` runtime function,
+    /// which converts null, undefined, and empty arrays to empty strings
+    pub fn guard(self) -> Value<'a> {
+        let empty = match self.json() {
+            Some(JsonValue::Null) => true,
+            Some(JsonValue::Array(items)) => items.is_empty(),
+            Some(_) => false,
+            None => matches!(self, Value::Undefined),
+        };
+
+        if empty {
+            Value::Str("".into())
+        } else {
+            self
+        }
+    }
+
+    /// join values with JS `+`, the way a body is in generated code,
+    /// or an empty string if there aren't any
+    pub fn concat<I>(values: I) -> Value<'a>
+    where
+        I: IntoIterator<Item = Value<'a>>,
+    {
+        values
+            .into_iter()
+            .fold(None, |acc: Option<Value<'a>>, value| match acc {
+                Some(acc) => Some(acc + value),
+                None => Some(value),
+            })
+            .unwrap_or_else(|| Value::text(""))
+    }
+
+    /// JS `!`
+    pub fn not(&self) -> Value<'a> {
+        Value::Bool(!self.truthy())
+    }
+
+    /// convert to a primitive like JS `ToPrimitive`,
+    /// with objects and arrays converted to strings
+    fn primitive(&self) -> Value<'a> {
+        match self.json() {
+            Some(JsonValue::Null) => self.clone(),
+            Some(JsonValue::Boolean(b)) => Value::Bool(*b),
+            Some(JsonValue::Number(_)) => Value::Number(self.to_number()),
+            Some(_) => Value::Str(self.to_js_string()),
+            None => self.clone(),
+        }
+    }
+
+    /// JS `==`
+    pub fn loose_eq(&self, other: &Value<'a>) -> bool {
+        let nullish = |value: &Value| {
+            matches!(value, Value::Undefined) || value.json().is_some_and(JsonValue::is_null)
+        };
+        if nullish(self) || nullish(other) {
+            return nullish(self) && nullish(other);
+        }
+
+        // objects and arrays are only equal to themselves
+        let object = |json: &&JsonValue| json.is_object() || json.is_array();
+        if let (Some(a), Some(b)) = (self.json().filter(object), other.json().filter(object)) {
+            return ptr::eq(a, b);
+        }
+
+        match (self.primitive(), other.primitive()) {
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (a, b) => a.to_number() == b.to_number(),
+        }
+    }
+
+    /// the order of two values for JS `<` and `>`,
+    /// or `None` if they can't be compared, like with `NaN`
+    fn js_cmp(&self, other: &Value<'a>) -> Option<Ordering> {
+        match (self.primitive(), other.primitive()) {
+            // strings are compared by UTF-16 code units
+            (Value::Str(a), Value::Str(b)) => Some(a.encode_utf16().cmp(b.encode_utf16())),
+            (a, b) => a.to_number().partial_cmp(&b.to_number()),
+        }
+    }
+
+    /// JS comparison operators
+    pub fn compare(&self, operator: Comparison, other: &Value<'a>) -> Value<'a> {
+        let order = self.js_cmp(other);
+
+        Value::Bool(match operator {
+            Comparison::Equal => self.loose_eq(other),
+            Comparison::NotEqual => !self.loose_eq(other),
+            Comparison::Less => order == Some(Ordering::Less),
+            Comparison::LessEqual => matches!(order, Some(Ordering::Less | Ordering::Equal)),
+            Comparison::Greater => order == Some(Ordering::Greater),
+            Comparison::GreaterEqual => {
+                matches!(order, Some(Ordering::Greater | Ordering::Equal))
+            }
+        })
+    }
+
+    /// JS `&&`, only evaluating `other` if this is truthy
+    pub fn and<F: FnOnce() -> Value<'a>>(self, other: F) -> Value<'a> {
+        if self.truthy() {
+            other()
+        } else {
+            self
+        }
+    }
+
+    /// JS `||`, only evaluating `other` if this is falsy
+    pub fn or<F: FnOnce() -> Value<'a>>(self, other: F) -> Value<'a> {
+        if self.truthy() {
+            self
+        } else {
+            other()
+        }
+    }
+
+    /// the `__escape` runtime helper
+    pub fn escape(&self) -> Value<'a> {
+        Value::Str(match self {
+            Value::Undefined => "".into(),
+            _ if self.json().is_some_and(JsonValue::is_null) => "".into(),
+            _ if !self.truthy() => self.to_js_string(),
+            _ => {
+                let s = self.to_js_string();
+                if !s.contains(|ch| "&<>\"'`=".contains(ch)) {
+                    return Value::Str(s);
+                }
+
+                s.chars()
+                    .map(|ch| match ch {
+                        '&' => "&amp;".into(),
+                        '<' => "&lt;".into(),
+                        '>' => "&gt;".into(),
+                        '"' => "&quot;".into(),
+                        '\'' => "&#x27;".into(),
+                        '`' => "&#x60;".into(),
+                        '=' => "&#x3D;".into(),
+                        _ => ch.to_string(),
+                    })
+                    .collect::<String>()
+                    .into()
+            }
+        })
+    }
+
+    /// keys and values of an iterable object or array
+    fn keys(&self) -> Vec<(String, Value<'a>)> {
+        match *self {
+            Value::Json(value) => json_keys(value)
+                .into_iter()
+                .map(|(key, value)| (key, Value::Json(value)))
+                .collect(),
+            Value::Owned(ref value) => json_keys(value)
+                .into_iter()
+                .map(|(key, value)| (key, Value::Json(value).detach()))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// JS `+`
+impl<'a> Add for Value<'a> {
+    type Output = Value<'a>;
+
+    fn add(self, other: Value<'a>) -> Value<'a> {
+        if self.is_stringy() || other.is_stringy() {
+            let mut s = self.to_js_string().into_owned();
+            s.push_str(&other.to_js_string());
+            Value::Str(s.into())
+        } else {
+            Value::Number(self.to_number() + other.to_number())
+        }
+    }
+}
+
+/// variables in scope while rendering,
+/// with a scope for each iteration
+pub struct Scope<'s, 'a: 's> {
+    context: &'a JsonValue,
+    helpers: &'s HelperRegistry,
+    parent: Option<&'s Scope<'s, 'a>>,
+    suffix: u16,
+    key: Value<'a>,
+    index: Value<'a>,
+    length: Value<'a>,
+    value: Value<'a>,
+}
+
+impl<'s, 'a> Scope<'s, 'a> {
+    /// the top level scope for rendering `context` with `helpers`
+    pub fn new(context: &'a JsonValue, helpers: &'s HelperRegistry) -> Scope<'s, 'a> {
+        Scope {
+            context,
+            helpers,
+            parent: None,
+            suffix: 0,
+            key: Value::Undefined,
+            index: Value::Undefined,
+            length: Value::Undefined,
+            value: Value::Json(context),
+        }
+    }
+
+    /// `@root`
+    pub fn root(&self) -> Value<'a> {
+        Value::Json(self.context)
+    }
+
+    /// `@key`
+    pub fn key(&self) -> Value<'a> {
+        self.key.clone()
+    }
+
+    /// `@index`
+    pub fn index(&self) -> Value<'a> {
+        self.index.clone()
+    }
+
+    /// `@value`
+    pub fn value(&self) -> Value<'a> {
+        self.value.clone().guard()
+    }
+
+    /// `@first`
+    pub fn first(&self) -> Value<'a> {
+        Value::Bool(self.index.to_number() == 0.0)
+    }
+
+    /// `@last`
+    pub fn last(&self) -> Value<'a> {
+        Value::Bool(self.index.to_number() == self.length.to_number() - 1.0)
+    }
+
+    /// guarded chained property access from the context,
+    /// stopping at a falsy value
+    /// each part has the suffix of the iteration key which indexes into it, if any
+    pub fn path(&self, parts: &[(&str, Option<char>)]) -> Value<'a> {
+        let mut current = Value::Json(self.context);
+        for &(part, index) in parts {
+            if !current.truthy() {
+                break;
+            }
+            current = current.get(part);
+
+            if let Some(n) = index {
+                if !current.truthy() {
+                    break;
+                }
+                current = current.get(&self.key_i(n).to_js_string());
+            }
+        }
+
+        current.guard()
+    }
+
+    /// call a helper the way the `helper` runtime function does,
+    /// rendering an unknown helper as an empty string
+    pub fn helper(&self, name: &str, args: &[Value<'a>]) -> Value<'a> {
+        match self.helpers.get(name) {
+            Some(helper) => {
+                let converted: Vec<Option<JsonValue>> = args.iter().map(Value::to_json).collect();
+                let args: Vec<&JsonValue> = args
+                    .iter()
+                    .zip(&converted)
+                    .filter_map(|(arg, converted)| converted.as_ref().or_else(|| arg.json()))
+                    .collect();
+
+                let output = Value::Owned(Rc::new(helper.call(self.context, &args)));
+                if output.truthy() {
+                    output
+                } else {
+                    Value::text("")
+                }
+            }
+            None => Value::text(""),
+        }
+    }
+
+    /// the `iter` runtime function,
+    /// concatenating `body` rendered in a scope for each element of `subject`
+    /// `None` if there aren't any elements, so the alternative should be rendered
+    pub fn iter<F>(&self, suffix: u16, subject: Value<'a>, mut body: F) -> Option<Value<'a>>
+    where
+        F: FnMut(&Scope<'_, 'a>) -> Value<'a>,
+    {
+        let keys = if subject.truthy() {
+            subject.keys()
+        } else {
+            Vec::new()
+        };
+
+        if keys.is_empty() {
+            return None;
+        }
+
+        let length = keys.len();
+        let mut output = String::new();
+        for (index, (key, value)) in keys.into_iter().enumerate() {
+            let inner = Scope {
+                context: self.context,
+                helpers: self.helpers,
+                parent: Some(self),
+                suffix,
+                key: Value::Str(key.into()),
+                index: Value::Number(index as f64),
+                length: Value::Number(length as f64),
+                value,
+            };
+            output.push_str(&body(&inner).to_js_string());
+        }
+
+        Some(Value::Str(output.into()))
+    }
+
+    /// the key of the iteration with the given suffix
+    fn key_i(&self, suffix: char) -> Value<'a> {
+        let mut scope = Some(self);
+        while let Some(current) = scope {
+            if current.parent.is_some() && suffix.to_digit(10) == Some(u32::from(current.suffix)) {
+                return current.key.clone();
+            }
+            scope = current.parent;
+        }
+
+        Value::Undefined
+    }
+}
+
+/// call a helper, reporting it if it isn't registered
+fn helper<'a>(
+    name: &str,
+    args: &[Value<'a>],
+    scope: &Scope<'_, 'a>,
+    diagnostics: &mut Diagnostics,
+    span: Span,
+) -> Value<'a> {
+    if !scope.helpers.contains(name) {
+        diagnostics.push(
+            Diagnostic::warning(
+                ErrorKind::UnknownHelper,
+                span,
+                &format!("unknown helper `{}`", name),
+            )
+            .with_note("it was rendered as an empty string"),
+        );
+    }
+
+    scope.helper(name, args)
+}
+
+/// evaluate an expression within the control at `span`
+fn expression<'a>(
+    input: &'a Expression,
+    scope: &Scope<'_, 'a>,
+    diagnostics: &mut Diagnostics,
+    span: Span,
+) -> Value<'a> {
+    match input {
+        Expression::StringLiteral { value } => Value::text(value),
+        Expression::PathExpression { path } => match path.first().map(|part| part.as_str()) {
+            Some("@root") => scope.root(),
+            Some("@key") => scope.key(),
+            Some("@index") => scope.index(),
+            Some("@value") => scope.value(),
+            Some("@first") => scope.first(),
+            Some("@last") => scope.last(),
+            _ => {
+                let parts: Vec<(&str, Option<char>)> = path
+                    .iter()
+                    .map(|part| templates::split_index(part))
+                    .collect();
+
+                scope.path(&parts)
+            }
+        },
+        Expression::HelperExpression { helper_name, args } => {
+            let args: Vec<Value<'a>> = args
+                .iter()
+                .map(|arg| expression(arg, scope, diagnostics, span))
+                .collect();
+
+            helper(helper_name, &args, scope, diagnostics, span)
+        }
+        Expression::NegativeExpression { expr } => expression(expr, scope, diagnostics, span).not(),
+        Expression::ComparisonExpression {
+            operator,
+            left,
+            right,
+        } => {
+            let left = expression(left, scope, diagnostics, span);
+            left.compare(*operator, &expression(right, scope, diagnostics, span))
+        }
+        Expression::LogicalExpression {
+            operator: Logical::And,
+            left,
+            right,
+        } => expression(left, scope, diagnostics, span)
+            .and(|| expression(right, scope, diagnostics, span)),
+        Expression::LogicalExpression {
+            operator: Logical::Or,
+            left,
+            right,
+        } => expression(left, scope, diagnostics, span)
+            .or(|| expression(right, scope, diagnostics, span)),
+    }
+}
+
+/// render a body to the value of its string concatenation
+fn body<'a>(
+    entry: &'a [Control],
+    scope: &Scope<'_, 'a>,
+    diagnostics: &mut Diagnostics,
+) -> Value<'a> {
+    Value::concat(entry.iter().map(|elem| match elem {
+        Control::Text { value, .. } => Value::text(value),
+        Control::If {
+            subject,
+            body: b,
+            else_ifs,
+            alt,
+            span,
+        } => {
+            if expression(subject, scope, diagnostics, *span).truthy() {
+                return body(b, scope, diagnostics);
+            }
+            for branch in else_ifs {
+                if expression(&branch.subject, scope, diagnostics, branch.span).truthy() {
+                    return body(&branch.body, scope, diagnostics);
+                }
+            }
+
+            body(alt, scope, diagnostics)
+        }
+        Control::Iter {
+            suffix,
+            subject,
+            body: b,
+            alt,
+            span,
+            ..
+        } => {
+            let subject = expression(subject, scope, diagnostics, *span);
+            match scope.iter(*suffix, subject, |inner| body(b, inner, diagnostics)) {
+                Some(output) => output,
+                None => body(alt, scope, diagnostics),
+            }
+        }
+        Control::Escaped { subject, span } => {
+            expression(subject, scope, diagnostics, *span).escape()
+        }
+        Control::Raw { subject, span } => expression(subject, scope, diagnostics, *span),
+        Control::Import { path, span, .. } => helper(
+            IMPORT_HELPER,
+            &[Value::text(path)],
+            scope,
+            diagnostics,
+            *span,
+        ),
+        Control::Block { body: b, .. } => body(b, scope, diagnostics),
+        Control::Extends { .. } | Control::Super { .. } => Value::text(""),
+    }))
+}
+
+/// render a parsed template with the given data,
+/// behaving the same as the generated code run by the JS runtime
+/// without any helpers
+pub fn render(tree: &[Control], data: &JsonValue) -> String {
+    render_with(tree, data, &HelperRegistry::new(), &mut Diagnostics::new())
+}
+
+/// render a parsed template with the given data and helpers,
+/// reporting any unknown helpers to `diagnostics`
+pub fn render_with(
+    tree: &[Control],
+    data: &JsonValue,
+    helpers: &HelperRegistry,
+    diagnostics: &mut Diagnostics,
+) -> String {
+    body(tree, &Scope::new(data, helpers), diagnostics)
+        .guard()
+        .to_js_string()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json;
+
+    fn render_str(template: &str, data: &str) -> String {
+        let (tree, diagnostics) = ::parse_with(template, &::Options::default());
+        assert!(!diagnostics.has_errors());
+
+        render(&tree, &json::parse(data).unwrap())
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(number_to_string(1.0), "1");
+        assert_eq!(number_to_string(-0.0), "0");
+        assert_eq!(number_to_string(0.1), "0.1");
+        assert_eq!(number_to_string(1e21), "1e+21");
+        assert_eq!(number_to_string(1.5e-7), "1.5e-7");
+        assert_eq!(number_to_string(123456.789), "123456.789");
+    }
+
+    #[test]
+    fn interpolation() {
+        let data =
+            r#"{ "a": "<b>", "n": 0, "f": false, "e": [], "l": [1, null, [2, 3]], "o": {} }"#;
+
+        assert_eq!(render_str("{a} {{a}}", data), "&lt;b&gt; <b>");
+        assert_eq!(
+            render_str("{n}{f}{e}{missing}|{a.length}", data),
+            "0false|3"
+        );
+        assert_eq!(render_str("{l} {o}", data), "1,,2,3 [object Object]");
+        // JS `+` adds numbers
+        assert_eq!(render_str("{{n}}{{l.length}}", data), "3");
+    }
+
+    #[test]
+    fn conditions() {
+        let data = r#"{ "yes": 1, "e": [], "o": {}, "s": "" }"#;
+
+        assert_eq!(
+            render_str("{{{ if yes }}}a{{{ else }}}b{{{ end }}}", data),
+            "a"
+        );
+        assert_eq!(
+            render_str("{{{ if !yes }}}a{{{ else }}}b{{{ end }}}", data),
+            "b"
+        );
+        assert_eq!(
+            render_str(
+                "{{{ if e }}}a{{{ end }}}{{{ if o }}}b{{{ end }}}{{{ if s }}}c{{{ end }}}",
+                data
+            ),
+            "b"
+        );
+
+        let chain = "{{{ if !o }}}a{{{ else if s }}}b{{{ else if yes }}}c{{{ else }}}d{{{ end }}}";
+        assert_eq!(render_str(chain, data), "c");
+        assert_eq!(render_str(chain, r#"{ "o": 1, "s": "x" }"#), "b");
+        assert_eq!(render_str(chain, r#"{ "o": 1 }"#), "d");
+        assert_eq!(
+            render_str("<!-- IF e -->a<!-- ELSE IF o -->b<!-- ENDIF e -->", data),
+            "b"
+        );
+    }
+
+    #[test]
+    fn operators() {
+        let data = r#"{ "n": 5, "s": "5", "t": true, "l": [1], "o": {}, "e": "" }"#;
+        let truth = |subjects: &[&str]| {
+            subjects
+                .iter()
+                .map(|subject| {
+                    render_str(
+                        &format!(
+                            "{{{{{{ if {} }}}}}}1{{{{{{ else }}}}}}0{{{{{{ end }}}}}}",
+                            subject
+                        ),
+                        data,
+                    )
+                })
+                .collect::<String>()
+        };
+
+        // loose equality, like JS `==`
+        assert_eq!(
+            truth(&[
+                "n == s",
+                "t == \"1\"",
+                "l == \"1\"",
+                "o == o",
+                "missing == e"
+            ]),
+            "11111"
+        );
+        assert_eq!(truth(&["n != \"6\"", "s == \"05\"", "o != l"]), "101");
+        // strings compare by code units, anything else by number
+        assert_eq!(
+            truth(&["\"10\" < \"9\"", "n < \"10\"", "n >= s", "n > o", "n <= o"]),
+            "11100"
+        );
+        // operands are the values of `&&` and `||`
+        assert_eq!(
+            render_str(
+                "{{{ each e || l }}}{@value}{{{ end }}}|{{{ each l && o }}}a{{{ else }}}b{{{ end }}}",
+                data
+            ),
+            "1|b"
+        );
+        // comparisons bind tighter than `&&`, which binds tighter than `||`
+        assert_eq!(
+            render_str(
+                "{{{ if t || n == 6 && !t }}}a{{{ end }}}{{{ if (t || n == 6) && !t }}}b{{{ end }}}",
+                data
+            ),
+            "a"
+        );
+        assert_eq!(
+            render_str("{{{ if e == \"\" && !(n < 3) }}}c{{{ end }}}", data),
+            "c"
+        );
+    }
+
+    #[test]
+    fn iteration() {
+        let data = r#"{
+            "title": "t",
+            "posts": [
+                { "title": "a", "tags": ["x", "y"] },
+                { "title": "b", "tags": [] }
+            ],
+            "obj": { "b": 1, "2": 2, "a": 3, "1": 4 }
+        }"#;
+
+        assert_eq!(
+            render_str(
+                "{{{ each posts }}}{@index}{@key}{posts.title}\
+                 {{{ if @first }}}F{{{ end }}}{{{ if @last }}}L{{{ end }}}\
+                 [{{{ each posts.tags }}}{@value}{{{ else }}}none{{{ end }}}]{{{ end }}}",
+                data
+            ),
+            "00aF[xy]11bL[none]"
+        );
+        assert_eq!(
+            render_str("{{{ each obj }}}{@key}={@value},{{{ end }}}", data),
+            "1=4,2=2,b=1,a=3,"
+        );
+        assert_eq!(
+            render_str("{{{ each title }}}a{{{ else }}}b{{{ end }}}", data),
+            "b"
+        );
+    }
+
+    #[test]
+    fn helpers() {
+        let template = "{{{ each items }}}{caps(items.name, \"!\")}{function.count}{{{ end }}}\
+                        <!-- IF function.has, \"b\" -->yes<!-- ENDIF function.has -->\
+                        {missing(items)}";
+        let (tree, _) = ::parse_with(template, &::Options::default());
+        let data = json::parse(r#"{ "items": [{ "name": "a<" }, { "name": "b" }] }"#).unwrap();
+
+        let mut helpers = HelperRegistry::new();
+        helpers
+            .register_fn("caps", |_, args| {
+                format!("{}{}", args[0].to_string().to_uppercase(), args[1]).into()
+            })
+            // legacy helpers get `@value`
+            .register_fn("count", |_, args| args[0].len().into())
+            // and `@root` in conditions
+            .register_fn("has", |context, args| {
+                (args[0] == context && context["items"][1]["name"] == *args[1]).into()
+            });
+
+        let mut diagnostics = Diagnostics::new();
+        assert_eq!(
+            render_with(&tree, &data, &helpers, &mut diagnostics),
+            "A&lt;!1B!1yes"
+        );
+
+        let unknown: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.code, &template[d.span.start..d.span.end]))
+            .collect();
+        assert_eq!(
+            unknown,
+            vec![(ErrorKind::UnknownHelper, "{missing(items)}")]
+        );
+    }
+
+    #[test]
+    fn legacy() {
+        let data = r#"{ "rooms": [{ "name": "r", "users": [{ "name": "u" }] }] }"#;
+
+        assert_eq!(
+            render_str(
+                "<!-- BEGIN rooms -->{rooms.name}<!-- BEGIN rooms.users -->{rooms.users.name}<!-- END rooms.users --><!-- END rooms -->",
+                data
+            ),
+            "ru"
+        );
+    }
+}
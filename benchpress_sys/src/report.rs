@@ -0,0 +1,358 @@
+use diagnostics::{Diagnostic, Diagnostics, Severity};
+use error::Span;
+
+use json::JsonValue;
+use unicode_width::UnicodeWidthChar;
+
+/// a 1-based line and column in the source
+/// columns count characters rather than bytes
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// move an offset back to the nearest char boundary within the source
+fn floor_boundary(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
+    offset
+}
+
+/// get the line and column of a byte offset
+pub fn position(source: &str, offset: usize) -> Position {
+    let before = &source[..floor_boundary(source, offset)];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+    Position {
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
+
+// ANSI styles
+static BOLD: &str = "\x1b[1m";
+static RED: &str = "\x1b[1;31m";
+static YELLOW: &str = "\x1b[1;33m";
+static BLUE: &str = "\x1b[1;34m";
+static RESET: &str = "\x1b[0m";
+
+fn paint(text: &str, style: &str, color: bool) -> String {
+    if color && !text.is_empty() {
+        format!("{}{}{}", style, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// display width of a string, expanding tabs
+fn width(text: &str) -> usize {
+    text.chars()
+        .map(|ch| {
+            if ch == '\t' {
+                4
+            } else {
+                ch.width().unwrap_or(0)
+            }
+        })
+        .sum()
+}
+
+/// a line of source
+struct Line<'a> {
+    start: usize,
+    text: &'a str,
+}
+
+/// a span to underline
+struct Marker<'a> {
+    span: Span,
+    primary: bool,
+    message: &'a str,
+}
+
+/// render a diagnostic like rustc does
+/// with a `name:line:column` header, numbered source lines
+/// and markers under each span
+pub fn render(diagnostic: &Diagnostic, source: &str, name: &str, color: bool) -> String {
+    let style = match diagnostic.severity {
+        Severity::Error => RED,
+        Severity::Warning => YELLOW,
+    };
+
+    let lines: Vec<Line> = source
+        .split('\n')
+        .scan(0, |start, text| {
+            let line = Line {
+                start: *start,
+                text: text.trim_end_matches('\r'),
+            };
+            *start += text.len() + 1;
+            Some(line)
+        })
+        .collect();
+    let line_index = |offset: usize| position(source, offset).line - 1;
+
+    let mut markers = vec![Marker {
+        span: diagnostic.span,
+        primary: true,
+        message: "",
+    }];
+    markers.extend(diagnostic.labels.iter().map(|label| Marker {
+        span: label.span,
+        primary: false,
+        message: &label.message,
+    }));
+
+    // underlines for each line, as (line, column, width, label, marker)
+    // only the last line of a multi-line span is labeled
+    let mut underlines: Vec<(usize, usize, usize, &str, &Marker)> = Vec::new();
+    for marker in &markers {
+        let start = floor_boundary(source, marker.span.start);
+        let end = floor_boundary(source, marker.span.end).max(start);
+        let last = line_index(if end > start { end - 1 } else { start });
+
+        for (index, line) in lines
+            .iter()
+            .enumerate()
+            .take(last + 1)
+            .skip(line_index(start))
+        {
+            let line_end = line.start + line.text.len();
+            let from = start.max(line.start).min(line_end);
+            let to = end.min(line_end).max(from);
+
+            underlines.push((
+                index,
+                width(&source[line.start..from]),
+                width(&source[from..to]).max(1),
+                if index == last { marker.message } else { "" },
+                marker,
+            ));
+        }
+    }
+    underlines.sort_by_key(|&(index, column, _, _, marker)| (index, !marker.primary, column));
+
+    let mut shown: Vec<usize> = underlines.iter().map(|u| u.0).collect();
+    shown.dedup();
+
+    let gutter = (shown.last().cloned().unwrap_or(0) + 1).to_string().len();
+    let pad = " ".repeat(gutter);
+    let bar = paint("|", BLUE, color);
+    let location = position(source, diagnostic.span.start);
+
+    let mut output = format!(
+        "{}{}\n{}{} {}:{}:{}\n{} {}\n",
+        paint(
+            &format!("{}[{}]", diagnostic.severity, diagnostic.code),
+            style,
+            color
+        ),
+        paint(&format!(": {}", diagnostic.message), BOLD, color),
+        pad,
+        paint("-->", BLUE, color),
+        name,
+        location.line,
+        location.column,
+        pad,
+        bar
+    );
+
+    let mut previous: Option<usize> = None;
+    for &index in &shown {
+        if let Some(previous) = previous {
+            if index > previous + 1 {
+                output.push_str(&paint("...", BLUE, color));
+                output.push('\n');
+            }
+        }
+        previous = Some(index);
+
+        let number = format!("{:>width$}", index + 1, width = gutter);
+        output.push_str(&format!(
+            "{} {} {}\n",
+            paint(&number, BLUE, color),
+            bar,
+            lines[index].text.replace('\t', "    ")
+        ));
+
+        for &(_, column, length, message, marker) in underlines.iter().filter(|u| u.0 == index) {
+            let (ch, marker_style) = if marker.primary {
+                ("^", style)
+            } else {
+                ("-", BLUE)
+            };
+
+            let underline = format!("{} {}", ch.repeat(length), message);
+            output.push_str(&format!(
+                "{} {} {}{}\n",
+                pad,
+                bar,
+                " ".repeat(column),
+                paint(underline.trim_end(), marker_style, color)
+            ));
+        }
+    }
+
+    for note in &diagnostic.notes {
+        output.push_str(&format!(
+            "{} {} {}\n",
+            pad,
+            paint("=", BLUE, color),
+            paint(&format!("note: {}", note), BOLD, color)
+        ));
+    }
+
+    output
+}
+
+/// a span as JSON, with its byte offsets and where it starts and ends
+fn span_json(source: &str, span: Span) -> JsonValue {
+    let start = position(source, span.start);
+    let end = position(source, span.end);
+
+    let mut value = JsonValue::new_object();
+    value["start"] = span.start.into();
+    value["end"] = span.end.into();
+    value["line"] = start.line.into();
+    value["column"] = start.column.into();
+    value["end_line"] = end.line.into();
+    value["end_column"] = end.column.into();
+
+    value
+}
+
+/// render diagnostics as a JSON array, for tools and other languages
+pub fn json(diagnostics: &Diagnostics, source: &str) -> String {
+    let diagnostics: Vec<JsonValue> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let mut value = JsonValue::new_object();
+            value["severity"] = diagnostic.severity.to_string().into();
+            value["code"] = diagnostic.code.code().into();
+            value["message"] = diagnostic.message.as_str().into();
+            value["span"] = span_json(source, diagnostic.span);
+            value["labels"] = JsonValue::Array(
+                diagnostic
+                    .labels
+                    .iter()
+                    .map(|label| {
+                        let mut value = JsonValue::new_object();
+                        value["message"] = label.message.as_str().into();
+                        value["span"] = span_json(source, label.span);
+                        value
+                    })
+                    .collect(),
+            );
+            value["notes"] = JsonValue::Array(
+                diagnostic
+                    .notes
+                    .iter()
+                    .map(|note| note.as_str().into())
+                    .collect(),
+            );
+
+            value
+        })
+        .collect();
+
+    JsonValue::Array(diagnostics).dump()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::ErrorKind;
+
+    #[test]
+    fn position_test() {
+        assert_eq!(position("ab\ncd", 0), Position { line: 1, column: 1 });
+        assert_eq!(position("ab\ncd", 4), Position { line: 2, column: 2 });
+        assert_eq!(position("ab\ncd", 99), Position { line: 2, column: 3 });
+        // middle of `é`
+        assert_eq!(position("é\né", 4), Position { line: 2, column: 1 });
+    }
+
+    #[test]
+    fn render_first_line() {
+        let diagnostic = Diagnostic::warning(ErrorKind::ExtraEnd, Span::new(4, 15), "extra token")
+            .with_note("remove it");
+
+        assert_eq!(
+            render(&diagnostic, "abc {{{ end }}}\ndef", "test.tpl", false),
+            "warning[B0003]: extra token
+ --> test.tpl:1:5
+  |
+1 | abc {{{ end }}}
+  |     ^^^^^^^^^^^
+  = note: remove it
+"
+        );
+    }
+
+    #[test]
+    fn render_label() {
+        let source = "{{{ each a }}}\nb\n\nc\n{{{ end }}}{{{ end }}}";
+        let diagnostic = Diagnostic::warning(ErrorKind::ExtraEnd, Span::new(20, 31), "extra token")
+            .with_label(Span::new(0, 14), "opened here");
+
+        assert_eq!(
+            render(&diagnostic, source, "test.tpl", false),
+            "warning[B0003]: extra token
+ --> test.tpl:5:1
+  |
+1 | {{{ each a }}}
+  | -------------- opened here
+...
+5 | {{{ end }}}{{{ end }}}
+  | ^^^^^^^^^^^
+"
+        );
+    }
+
+    #[test]
+    fn render_multi_line_unicode() {
+        let source = "日本 {{{ if\na }}}";
+        let diagnostic =
+            Diagnostic::error(ErrorKind::InvalidBlock, Span::new(7, 20), "invalid block");
+
+        assert_eq!(
+            render(&diagnostic, source, "test.tpl", false),
+            "error[B0001]: invalid block
+ --> test.tpl:1:4
+  |
+1 | 日本 {{{ if
+  |      ^^^^^^
+2 | a }}}
+  | ^^^^^
+"
+        );
+    }
+
+    #[test]
+    fn render_color() {
+        let diagnostic = Diagnostic::error(ErrorKind::InvalidBlock, Span::new(0, 1), "invalid");
+
+        assert!(render(&diagnostic, "a", "test.tpl", true).starts_with("\x1b[1;31merror[B0001]"));
+    }
+
+    #[test]
+    fn json_test() {
+        let source = "{{{ each a }}}\n{{{ end }}}{{{ end }}}";
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.push(
+            Diagnostic::warning(ErrorKind::ExtraEnd, Span::new(26, 37), "extra \"end\"")
+                .with_label(Span::new(0, 14), "opened here")
+                .with_note("remove it"),
+        );
+
+        assert_eq!(
+            json(&diagnostics, source),
+            r#"[{"severity":"warning","code":"B0003","message":"extra \"end\"","span":{"start":26,"end":37,"line":2,"column":12,"end_line":2,"end_column":23},"labels":[{"message":"opened here","span":{"start":0,"end":14,"line":1,"column":1,"end_line":1,"end_column":15}}],"notes":["remove it"]}]"#
+        );
+        assert_eq!(json(&Diagnostics::new(), source), "[]");
+    }
+}
@@ -0,0 +1,209 @@
+use imports;
+use parser::{Control, Expression, Logical};
+use templates::{self, indent};
+
+/// create Rust code for an expression,
+/// evaluated with the methods of `render::Scope` in `scope`
+fn expression(input: &Expression) -> String {
+    match input {
+        Expression::StringLiteral { value } => format!("Value::text({:?})", value),
+        Expression::PathExpression { path } => match path.first().map(String::as_str) {
+            Some("@root") => "scope.root()".to_string(),
+            Some("@key") => "scope.key()".to_string(),
+            Some("@index") => "scope.index()".to_string(),
+            Some("@value") => "scope.value()".to_string(),
+            Some("@first") => "scope.first()".to_string(),
+            Some("@last") => "scope.last()".to_string(),
+            _ => {
+                let parts = path
+                    .iter()
+                    .map(|part| match templates::split_index(part) {
+                        (part, Some(n)) => format!("({:?}, Some({:?}))", part, n),
+                        (part, None) => format!("({:?}, None)", part),
+                    })
+                    .collect::<Vec<String>>();
+
+                format!("scope.path(&[{}])", parts.join(", "))
+            }
+        },
+        Expression::HelperExpression { helper_name, args } => {
+            let args = args.iter().map(expression).collect::<Vec<String>>();
+
+            format!("scope.helper({:?}, &[{}])", helper_name, args.join(", "))
+        }
+        Expression::NegativeExpression { expr } => format!("{}.not()", expression(expr)),
+        Expression::ComparisonExpression {
+            operator,
+            left,
+            right,
+        } => format!(
+            "{}.compare(::benchpress_sys::parser::Comparison::{:?}, &{})",
+            expression(left),
+            operator,
+            expression(right)
+        ),
+        Expression::LogicalExpression {
+            operator: Logical::And,
+            left,
+            right,
+        } => format!("{}.and(|| {})", expression(left), expression(right)),
+        Expression::LogicalExpression {
+            operator: Logical::Or,
+            left,
+            right,
+        } => format!("{}.or(|| {})", expression(left), expression(right)),
+    }
+}
+
+/// create Rust code for a body,
+/// evaluating to the value of its string concatenation
+fn body(entry: &[Control]) -> String {
+    let pieces = entry
+        .iter()
+        .map(|elem| match elem {
+            Control::Text { value, .. } => format!("Value::text({:?})", value),
+            Control::If {
+                subject,
+                body: b,
+                else_ifs,
+                alt,
+                ..
+            } => {
+                let else_ifs: String = else_ifs
+                    .iter()
+                    .map(|branch| {
+                        format!(
+                            " else if {}.truthy() {{\n    {}\n}}",
+                            expression(&branch.subject),
+                            indent(&body(&branch.body), 4)
+                        )
+                    })
+                    .collect();
+
+                format!(
+                    "if {}.truthy() {{\n    {}\n}}{} else {{\n    {}\n}}",
+                    expression(subject),
+                    indent(&body(b), 4),
+                    else_ifs,
+                    indent(&body(alt), 4)
+                )
+            }
+            Control::Iter {
+                suffix,
+                subject,
+                body: b,
+                alt,
+                ..
+            } => format!(
+                "scope\n    .iter({}, {}, |scope| {{\n        {}\n    }})\n    .unwrap_or_else(|| {{\n        {}\n    }})",
+                suffix,
+                expression(subject),
+                indent(&body(b), 8),
+                indent(&body(alt), 8)
+            ),
+            Control::Escaped { subject, .. } => format!("{}.escape()", expression(subject)),
+            Control::Raw { subject, .. } => expression(subject),
+            Control::Import { path, .. } => expression(&imports::import_call(path)),
+            Control::Block { body: b, .. } => body(b),
+            Control::Extends { .. } | Control::Super { .. } => "Value::text(\"\")".to_string(),
+        })
+        .collect::<Vec<String>>();
+
+    match pieces.len() {
+        0 => "Value::text(\"\")".to_string(),
+        1 => pieces.into_iter().next().unwrap(),
+        _ => format!(
+            "Value::concat(vec![\n    {},\n])",
+            indent(&pieces.join(",\n"), 4)
+        ),
+    }
+}
+
+/// create a Rust module rendering a template,
+/// which uses `benchpress_sys` at runtime instead of parsing the template
+pub fn generate(input: Vec<Control>) -> String {
+    format!(
+        "// generated by benchpress, do not edit
+
+use ::benchpress_sys::helpers::HelperRegistry;
+use ::benchpress_sys::json::JsonValue;
+use ::benchpress_sys::render::{{Scope, Value}};
+
+/// render the template with `ctx` and `helpers` into `out`
+#[allow(unused_variables)]
+pub fn render(ctx: &JsonValue, helpers: &HelperRegistry, out: &mut String) {{
+    let scope = &Scope::new(ctx, helpers);
+    let value: Value = {};
+    out.push_str(&value.guard().to_js_string());
+}}
+",
+        indent(&body(&input), 4)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate_str(template: &str) -> String {
+        let (tree, _) = ::parse_with(template, &::Options::default());
+
+        body(&tree)
+    }
+
+    #[test]
+    fn expressions() {
+        assert_eq!(generate_str(""), "Value::text(\"\")");
+        assert_eq!(
+            generate_str("{posts.title}"),
+            "scope.path(&[(\"posts\", None), (\"title\", None)]).escape()"
+        );
+        assert_eq!(
+            generate_str("{{{ if !helper(@root, \"b\") }}}{{{ end }}}"),
+            "if scope.helper(\"helper\", &[scope.root(), Value::text(\"b\")]).not().truthy() {
+    Value::text(\"\")
+} else {
+    Value::text(\"\")
+}"
+        );
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(
+            generate_str("{{{ if a == \"b\" || !c }}}d{{{ end }}}"),
+            "if scope.path(&[(\"a\", None)]).compare(::benchpress_sys::parser::Comparison::Equal, &Value::text(\"b\")).or(|| scope.path(&[(\"c\", None)]).not()).truthy() {
+    Value::text(\"d\")
+} else {
+    Value::text(\"\")
+}"
+        );
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(
+            generate_str("a{{{ each rows }}}{rows.name}{{{ else }}}none{{{ end }}}"),
+            "Value::concat(vec![
+    Value::text(\"a\"),
+    scope
+        .iter(1, scope.path(&[(\"rows\", None)]), |scope| {
+            scope.path(&[(\"rows\", Some('1')), (\"name\", None)]).escape()
+        })
+        .unwrap_or_else(|| {
+            Value::text(\"none\")
+        }),
+])"
+        );
+        assert_eq!(
+            generate_str("{{{ if a }}}1{{{ else if b }}}2{{{ else }}}3{{{ end }}}"),
+            "if scope.path(&[(\"a\", None)]).truthy() {
+    Value::text(\"1\")
+} else if scope.path(&[(\"b\", None)]).truthy() {
+    Value::text(\"2\")
+} else {
+    Value::text(\"3\")
+}"
+        );
+    }
+}
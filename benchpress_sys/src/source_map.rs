@@ -0,0 +1,306 @@
+use generator::Mapping;
+
+use json;
+
+static BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// append a base64 VLQ encoded value
+fn vlq(output: &mut String, value: i64) {
+    // sign is stored in the lowest bit
+    let mut rest = if value < 0 {
+        ((-value) << 1) | 1
+    } else {
+        value << 1
+    };
+
+    loop {
+        let mut digit = rest & 0b1_1111;
+        rest >>= 5;
+        if rest > 0 {
+            // continuation bit
+            digit |= 0b10_0000;
+        }
+        output.push(BASE64[digit as usize] as char);
+
+        if rest == 0 {
+            break;
+        }
+    }
+}
+
+/// finds the 0-based line and UTF-16 column of byte offsets in a template
+struct Lines<'a> {
+    source: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(source: &'a str) -> Lines<'a> {
+        let starts = Some(0)
+            .into_iter()
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Lines { source, starts }
+    }
+
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let mut offset = offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let line = match self.starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let column = self.source[self.starts[line]..offset]
+            .chars()
+            .map(char::len_utf16)
+            .sum();
+
+        (line, column)
+    }
+}
+
+/// encode mappings as the `mappings` field of a source map
+/// spans must refer to `source`
+fn encode(source: &str, mappings: &[Mapping]) -> String {
+    let lines = Lines::new(source);
+    let mut output = String::new();
+
+    // fields are relative to the previous segment
+    let mut line = 0;
+    let mut column = 0;
+    let mut orig_line = 0;
+    let mut orig_column = 0;
+
+    for mapping in mappings {
+        if mapping.line > line {
+            output.push_str(&";".repeat(mapping.line - line));
+            line = mapping.line;
+            column = 0;
+        } else if !output.is_empty() && !output.ends_with(';') {
+            output.push(',');
+        }
+
+        vlq(&mut output, mapping.column as i64 - column as i64);
+        column = mapping.column;
+
+        if let Some(span) = mapping.span {
+            let (l, c) = lines.position(span.start);
+
+            // always the only source
+            vlq(&mut output, 0);
+            vlq(&mut output, l as i64 - orig_line as i64);
+            vlq(&mut output, c as i64 - orig_column as i64);
+
+            orig_line = l;
+            orig_column = c;
+        }
+    }
+
+    output
+}
+
+/// create a version 3 source map for code generated from `source`,
+/// which is embedded in the map under the given name
+pub fn source_map(name: &str, source: &str, mappings: &[Mapping]) -> String {
+    let mut map = json::JsonValue::new_object();
+    map["version"] = 3.into();
+    map["sources"] = vec![name].into();
+    map["sourcesContent"] = vec![source].into();
+    map["names"] = json::JsonValue::new_array();
+    map["mappings"] = encode(source, mappings).into();
+
+    map.dump()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Span;
+
+    fn vlq_str(value: i64) -> String {
+        let mut output = String::new();
+        vlq(&mut output, value);
+        output
+    }
+
+    #[test]
+    fn vlq_test() {
+        assert_eq!(vlq_str(0), "A");
+        assert_eq!(vlq_str(1), "C");
+        assert_eq!(vlq_str(-1), "D");
+        assert_eq!(vlq_str(15), "e");
+        assert_eq!(vlq_str(16), "gB");
+        assert_eq!(vlq_str(-17), "jB");
+        assert_eq!(vlq_str(1000), "w+B");
+    }
+
+    #[test]
+    fn positions() {
+        let lines = Lines::new("ab\n😀c\n");
+
+        assert_eq!(lines.position(0), (0, 0));
+        assert_eq!(lines.position(3), (1, 0));
+        // surrogate pair
+        assert_eq!(lines.position(7), (1, 2));
+        // middle of a char
+        assert_eq!(lines.position(5), (1, 0));
+        assert_eq!(lines.position(9), (2, 0));
+    }
+
+    #[test]
+    fn encode_test() {
+        let mapping = |line, column, span: Option<(usize, usize)>| Mapping {
+            line,
+            column,
+            span: span.map(|(start, end)| Span::new(start, end)),
+        };
+
+        assert_eq!(
+            encode(
+                "ab\ncd",
+                &[
+                    mapping(1, 4, Some((0, 2))),
+                    mapping(1, 8, None),
+                    mapping(3, 2, Some((4, 5))),
+                    mapping(3, 5, Some((3, 4))),
+                ]
+            ),
+            ";IAAA,I;;EACC,GAAD"
+        );
+    }
+
+    /// a line and column
+    type Position = (usize, usize);
+
+    /// decode mappings into generated and original positions
+    fn decode(mappings: &str) -> Vec<(Position, Option<Position>)> {
+        let mut decoded = Vec::new();
+        let mut fields = [0i64; 4];
+
+        for (line, segments) in mappings.split(';').enumerate() {
+            fields[0] = 0;
+            for segment in segments.split(',').filter(|s| !s.is_empty()) {
+                let mut values = Vec::new();
+                let (mut value, mut shift) = (0, 0);
+                for b in segment.bytes() {
+                    let digit = BASE64.iter().position(|&c| c == b).unwrap() as i64;
+                    value |= (digit & 0b1_1111) << shift;
+                    shift += 5;
+                    if digit & 0b10_0000 == 0 {
+                        let sign = if value & 1 == 1 { -1 } else { 1 };
+                        values.push(sign * (value >> 1));
+                        value = 0;
+                        shift = 0;
+                    }
+                }
+
+                for (field, value) in fields.iter_mut().zip(&values) {
+                    *field += value;
+                }
+                let orig = if values.len() > 1 {
+                    Some((fields[2] as usize, fields[3] as usize))
+                } else {
+                    None
+                };
+                decoded.push(((line, fields[0] as usize), orig));
+            }
+        }
+
+        decoded
+    }
+
+    #[test]
+    fn compiled() {
+        let template = "<!-- IF a -->\n  {b}\n<!-- ENDIF a -->{c}";
+        let output = ::compile_with(
+            template,
+            &::Options {
+                source_map: true,
+                name: Some("a.tpl".to_string()),
+                ..::Options::default()
+            },
+        );
+        assert_eq!(output.code, ::compile(template));
+
+        let map = json::parse(&output.source_map.unwrap()).unwrap();
+        assert_eq!(map["sources"][0], "a.tpl");
+        assert_eq!(map["sourcesContent"][0], template);
+
+        let generated: Vec<&str> = output.code.lines().collect();
+        let original: Vec<&str> = template.lines().collect();
+        let mapped: Vec<(&str, &str)> = decode(map["mappings"].as_str().unwrap())
+            .into_iter()
+            .filter_map(|((line, column), orig)| {
+                orig.map(|(orig_line, orig_column)| {
+                    (
+                        &generated[line][column..],
+                        &original[orig_line][orig_column..],
+                    )
+                })
+            })
+            .collect();
+
+        assert_eq!(
+            mapped,
+            vec![
+                ("(guard(context && context['a']) ?", "<!-- IF a -->"),
+                // the line break after the comment
+                ("\"\\n  \" + ", ""),
+                (" + ", "<!-- IF a -->"),
+                ("__escape(guard(context && context['b'])) + ", "{b}"),
+                (" + ", "<!-- IF a -->"),
+                ("\"\\n\" :", ""),
+                (" :", "<!-- IF a -->"),
+                ("__escape(guard(context && context['c']));", "{c}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn minified() {
+        let template = "a\n{b}<!-- IF c -->d<!-- ENDIF c -->";
+        let output = ::compile_with(
+            template,
+            &::Options {
+                source_map: true,
+                minify: true,
+                ..::Options::default()
+            },
+        );
+        let map = json::parse(&output.source_map.unwrap()).unwrap();
+        let code = &output.code;
+
+        let mapped: Vec<(&str, Position)> = decode(map["mappings"].as_str().unwrap())
+            .into_iter()
+            .filter_map(|((line, column), orig)| {
+                assert_eq!(line, 0);
+                orig.map(|orig| (&code[column..column + 6], orig))
+            })
+            .collect();
+
+        assert_eq!(
+            mapped,
+            vec![
+                ("\"a\\n\"+", (0, 0)),
+                ("__esca", (1, 0)),
+                ("(guard", (1, 3)),
+                ("\"d\":\"\"", (1, 16)),
+                (":\"\")}c", (1, 3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn source_map_test() {
+        let map = json::parse(&source_map("a.tpl", "x", &[])).unwrap();
+
+        assert_eq!(map["version"], 3);
+        assert_eq!(map["sources"][0], "a.tpl");
+        assert_eq!(map["sourcesContent"][0], "x");
+        assert_eq!(map["mappings"], "");
+    }
+}
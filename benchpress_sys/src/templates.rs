@@ -26,8 +26,9 @@ pub fn length_i(i: u16) -> String {
 
 // static convenient keyword combinations
 lazy_static! {
-    pub static ref FIRST: String = format!("{} === 0", INDEX);
-    pub static ref LAST: String = format!("{} === {} - 1", INDEX, LENGTH);
+    // parenthesized so they can be concatenated or negated
+    pub static ref FIRST: String = format!("({} === 0)", INDEX);
+    pub static ref LAST: String = format!("({} === {} - 1)", INDEX, LENGTH);
     pub static ref RUNTIME_PARAMS: String =
         format!("{}, {}, {}, {}, {}", HELPERS, CONTEXT, GUARD, ITER, HELPER);
 }
@@ -38,8 +39,16 @@ pub fn indent(source: &str, amount: usize) -> String {
     source.lines().collect::<Vec<&str>>().join(&joiner)
 }
 
+/// keyword declaring a variable
+fn declare(target: Target) -> &'static str {
+    match target {
+        Target::Es5 => "var",
+        Target::Es2015 => "const",
+    }
+}
+
 /// block method template
-pub fn block(name: &str, body: &str) -> String {
+pub fn block(target: Target, name: &str, body: &str) -> String {
     let mut first = true;
     let safe_name: String = name
         .chars()
@@ -58,17 +67,19 @@ pub fn block(name: &str, body: &str) -> String {
     format!(
         "
 '{}': function {}({}) {{
-  var {} = {}.{};
-  var {} = {};
+  {} {} = {}.{};
+  {} {} = {};
   return {};
 }}
 ",
         escape_path(name),
         safe_name,
         RUNTIME_PARAMS.to_string(),
+        declare(target),
         ESCAPE,
         HELPERS,
         ESCAPE,
+        declare(target),
         VALUE,
         CONTEXT,
         indent(body, 2)
@@ -87,83 +98,192 @@ pub fn block_call(name: &str) -> String {
     )
 }
 
-/// module wrapper template
-pub fn wrapper(body: &str, blocks: &[String]) -> String {
-    let blocks_str = indent(&blocks.join(",\n"), 4);
-
+/// compiled function template
+fn compiled(target: Target, body: &str) -> String {
     format!(
-        "
-(function (factory) {{
-  if (typeof module === 'object' && module.exports) {{
-    module.exports = factory();
-  }} else if (typeof define === 'function' && define.amd) {{
-    define(factory);
-  }}
-}})(function () {{
-  function compiled({}) {{
-    var {} = {}.{};
-    var {} = {};
-    return {};
-  }}
-
-  {} = {{
-    {}
-  }};
-
-  return compiled;
-}})
-",
-        RUNTIME_PARAMS.to_string(),
+        "function compiled({}) {{
+  {} {} = {}.{};
+  {} {} = {};
+  return {};
+}}",
+        *RUNTIME_PARAMS,
+        declare(target),
         ESCAPE,
         HELPERS,
         ESCAPE,
+        declare(target),
         VALUE,
         CONTEXT,
-        indent(body, 6),
-        BLOCKS,
-        blocks_str
+        indent(body, 4)
     )
 }
 
-/// if-else template
-/// `neg` switches body and alt
-pub fn if_else(neg: bool, subject: &str, body: &str, alt: &str) -> String {
-    // switch body/alt position if neg
-    let (first, second) = if neg { (alt, body) } else { (body, alt) };
+/// compiled function with its blocks attached,
+/// indented by a given number of spaces
+fn compiled_with_blocks(target: Target, body: &str, blocks: &[String], amount: usize) -> String {
+    let pad = " ".repeat(amount);
 
     format!(
-        "({} ?
-  {} :
-  {})",
-        subject,
-        indent(first, 4),
-        indent(second, 4)
+        "{}{}
+
+{}{} = {{
+{}  {}
+{}}};",
+        pad,
+        indent(&compiled(target, body), amount),
+        pad,
+        BLOCKS,
+        pad,
+        indent(&blocks.join(",\n"), amount + 2),
+        pad
     )
-    .trim()
-    .to_string()
+}
+
+// factory wrapper supporting both CommonJS and AMD
+static UMD: &str = "(function (factory) {
+  if (typeof module === 'object' && module.exports) {
+    module.exports = factory();
+  } else if (typeof define === 'function' && define.amd) {
+    define(factory);
+  }
+})";
+// the same wrapper as a single expression, for minified output
+static UMD_MINIFIED: &str = "(function (f) {
+  typeof module === 'object' && module.exports ? module.exports = f() :
+    typeof define === 'function' && define.amd && define(f);
+})";
+
+/// module wrapper template
+/// the name is used by formats which register the template globally
+pub fn wrapper(options: &Options, body: &str, blocks: &[String]) -> String {
+    let target = options.target;
+
+    match options.format {
+        OutputFormat::Umd => format!(
+            "
+{}(function () {{
+{}
+
+  return compiled;
+}})
+",
+            if options.minify { UMD_MINIFIED } else { UMD },
+            compiled_with_blocks(target, body, blocks, 2)
+        ),
+        OutputFormat::Esm => format!(
+            "
+export default {}
+
+export {} blocks = {} = {{
+  {}
+}};
+",
+            compiled(target, body),
+            declare(target),
+            BLOCKS,
+            indent(&blocks.join(",\n"), 2)
+        ),
+        OutputFormat::CommonJs => format!(
+            "
+{}
+
+module.exports = compiled;
+",
+            compiled_with_blocks(target, body, blocks, 0)
+        ),
+        OutputFormat::Amd => format!(
+            "
+define(function () {{
+{}
+
+  return compiled;
+}});
+",
+            compiled_with_blocks(target, body, blocks, 2)
+        ),
+        OutputFormat::Iife => format!(
+            "
+(function () {{
+{}
+
+  {} templates = window.templates = window.templates || {{}};
+  templates['{}'] = compiled;
+}})();
+",
+            compiled_with_blocks(target, body, blocks, 2),
+            declare(target),
+            escape_path(options.template_name())
+        ),
+        OutputFormat::Function => format!(
+            "
+(function () {{
+{}
+
+  return compiled;
+}})()
+",
+            compiled_with_blocks(target, body, blocks, 2)
+        ),
+    }
+}
+
+/// if-else template
+/// a flat chain of ternaries, testing each subject in order
+pub fn if_else(branches: &[(String, String)], alt: &str) -> String {
+    let mut output = String::from("(");
+    for (subject, body) in branches {
+        output.push_str(&format!(
+            "{} ?
+  {} :
+  ",
+            subject,
+            indent(body, 4)
+        ));
+    }
+    output.push_str(&indent(alt, 4));
+    output.push(')');
+
+    output.trim().to_string()
 }
 
 /// iter template
-pub fn iter(suffix: u16, subject: &str, body: &str, alt: &str) -> String {
+pub fn iter(target: Target, suffix: u16, subject: &str, body: &str, alt: &str) -> String {
     let key = key_i(suffix);
 
-    format!(
-        "iter({}, function each({}, {}, {}, {}) {{
+    match target {
+        Target::Es5 => format!(
+            "iter({}, function each({}, {}, {}, {}) {{
   var {} = {};
   return {};
 }}, function alt() {{
   return {};
 }})",
-        subject,
-        key,
-        INDEX,
-        LENGTH,
-        VALUE,
-        KEY,
-        key,
-        indent(body, 4),
-        indent(alt, 4)
-    )
+            subject,
+            key,
+            INDEX,
+            LENGTH,
+            VALUE,
+            KEY,
+            key,
+            indent(body, 4),
+            indent(alt, 4)
+        ),
+        Target::Es2015 => format!(
+            "iter({}, ({}, {}, {}, {}) => {{
+  const {} = {};
+  return {};
+}}, () => {})",
+            subject,
+            key,
+            INDEX,
+            LENGTH,
+            VALUE,
+            KEY,
+            key,
+            indent(body, 4),
+            indent(alt, 2)
+        ),
+    }
 }
 
 /// create a string concatenation in JS
@@ -171,6 +291,42 @@ pub fn concat(input: &[String]) -> String {
     input.join(" + \n")
 }
 
+/// create a template literal in JS
+/// from text escaped with `escape_template` and substitutions
+pub fn template_literal(input: &[String]) -> String {
+    format!("`{}`", input.concat())
+}
+
+/// create a template literal substitution
+pub fn substitution(code: &str) -> String {
+    format!("${{{}}}", code)
+}
+
+/// escape text for the source of a template literal
+pub fn escape_template(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+
+    for ch in input.chars() {
+        match ch {
+            '\\' => output.push_str("\\\\"),
+            '`' => output.push_str("\\`"),
+            // could start a substitution along with the text after it
+            '$' => output.push_str("\\$"),
+            // line breaks would change with indentation,
+            // and carriage returns are normalized
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\u{2028}' | '\u{2029}' | '\u{E000}' | '\u{E001}' => {
+                output.push_str(&format!("\\u{:x}", ch as u32))
+            }
+            _ => output.push(ch),
+        }
+    }
+
+    output
+}
+
+use options::{Options, OutputFormat, Target};
 use parser::Expression;
 
 /// escape path
@@ -186,32 +342,98 @@ pub fn escape_path(input: &str) -> String {
         .collect()
 }
 
+/// escape line breaks in the source of a string literal
+/// since they aren't allowed within JS string literals
+pub fn escape_line_breaks(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut escaped = false;
+    let mut chars = input.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            // a line continuation adds nothing to the string
+            '\n' | '\r' | '\u{2028}' | '\u{2029}' if escaped => {
+                output.pop();
+                if ch == '\r' && chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+            }
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\u{2028}' => output.push_str("\\u2028"),
+            '\u{2029}' => output.push_str("\\u2029"),
+            _ => output.push(ch),
+        }
+
+        escaped = ch == '\\' && !escaped;
+    }
+
+    output
+}
+
+/// escape the private use characters the generator uses as markers
+/// in the source of a string literal
+pub fn escape_markers(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut escaped = false;
+
+    for ch in input.chars() {
+        match ch {
+            '\u{E000}' | '\u{E001}' => {
+                // an escaped marker is the marker itself
+                if escaped {
+                    output.pop();
+                }
+                output.push_str(&format!("\\u{:x}", ch as u32));
+            }
+            _ => output.push(ch),
+        }
+
+        escaped = ch == '\\' && !escaped;
+    }
+
+    output
+}
+
+/// split an index like `[1]` from the end of a path part,
+/// returning the part without it and the suffix of the key to index with
+pub fn split_index(part: &str) -> (&str, Option<char>) {
+    if part.ends_with(']') && part.len() > 3 {
+        let n: usize = part.len() - 2;
+        let index: Option<char> = part.chars().nth(n).filter(|ch| ch.is_numeric());
+
+        match part.get(..part.len() - 3) {
+            Some(fixed) => (fixed, index),
+            None => (part, None),
+        }
+    } else {
+        (part, None)
+    }
+}
+
+/// whether a property can be accessed with a dot
+pub fn is_identifier(input: &str) -> bool {
+    let mut chars = input.chars();
+
+    chars
+        .next()
+        .is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_' || ch == '$')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '$')
+}
+
 /// create guarded chained property access
-pub fn guard(input: Vec<String>) -> String {
+pub fn guard(target: Target, input: Vec<String>) -> String {
     let mut exp = CONTEXT.to_string();
     let mut last = exp.clone();
 
     for part in input {
         // handle indices like item[1]
-        let (part_fixed, index) = if part.ends_with(']') && part.len() > 3 {
-            let n: usize = part.len() - 2;
-            let index: Option<char> = match part.chars().nth(n) {
-                Some(ch) => {
-                    if ch.is_numeric() {
-                        Some(ch)
-                    } else {
-                        None
-                    }
-                }
-                None => None,
-            };
+        let (part_fixed, index) = split_index(&part);
 
-            (part[..part.len() - 3].to_string(), index)
-        } else {
-            (part, None)
+        last = match target {
+            Target::Es2015 if is_identifier(part_fixed) => format!("{}.{}", last, part_fixed),
+            _ => format!("{}['{}']", last, escape_path(part_fixed)),
         };
-
-        last = format!("{}['{}']", last, escape_path(&part_fixed));
         exp.push_str(" && ");
         exp.push_str(&last);
 
@@ -226,9 +448,11 @@ pub fn guard(input: Vec<String>) -> String {
 }
 
 /// create JS code for a given expression
-pub fn expression(input: Expression) -> String {
+pub fn expression(target: Target, input: Expression) -> String {
     match input {
-        Expression::StringLiteral { value } => format!("\"{}\"", value),
+        Expression::StringLiteral { value } => {
+            format!("\"{}\"", escape_markers(&escape_line_breaks(&value)))
+        }
         Expression::PathExpression { path } => {
             if let Some(part) = path.get(0).cloned() {
                 match part.as_str() {
@@ -238,31 +462,57 @@ pub fn expression(input: Expression) -> String {
                     "@value" => format!("guard({})", VALUE),
                     "@first" => FIRST.to_string(),
                     "@last" => LAST.to_string(),
-                    _ => guard(path),
+                    _ => guard(target, path),
                 }
             } else {
-                guard(path)
+                guard(target, path)
             }
         }
         Expression::HelperExpression { helper_name, args } => {
             let args_str = args
                 .into_iter()
-                .map(expression)
+                .map(|arg| expression(target, arg))
                 .collect::<Vec<String>>()
                 .join(", ");
 
             format!(
                 "{}({}, {}, '{}', [{}])",
-                HELPER, CONTEXT, HELPERS, helper_name, args_str
+                HELPER,
+                CONTEXT,
+                HELPERS,
+                escape_path(&helper_name),
+                args_str
             )
         }
-        Expression::NegativeExpression { expr } => format!("!{}", expression(*expr)),
+        Expression::NegativeExpression { expr } => format!("!{}", expression(target, *expr)),
+        // parenthesized so they can be concatenated or negated
+        Expression::ComparisonExpression {
+            operator,
+            left,
+            right,
+        } => format!(
+            "({} {} {})",
+            expression(target, *left),
+            operator.js(),
+            expression(target, *right)
+        ),
+        Expression::LogicalExpression {
+            operator,
+            left,
+            right,
+        } => format!(
+            "({} {} {})",
+            expression(target, *left),
+            operator.js(),
+            expression(target, *right)
+        ),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use parser::{Comparison, Logical};
 
     #[test]
     fn key_length_i() {
@@ -278,7 +528,7 @@ mod tests {
     #[test]
     fn block_test() {
         assert_eq!(
-            block("metaTags", "'every' +\n' meta tag'"),
+            block(Target::Es5, "metaTags", "'every' +\n' meta tag'"),
             "'metaTags': function metaTags(helpers, context, guard, iter, helper) {
   var __escape = helpers.__escape;
   var value = context;
@@ -288,7 +538,7 @@ mod tests {
         );
 
         assert_eq!(
-            block("meta.tags", "'every meta tag'"),
+            block(Target::Es5, "meta.tags", "'every meta tag'"),
             "'meta.tags': function metatags(helpers, context, guard, iter, helper) {
   var __escape = helpers.__escape;
   var value = context;
@@ -300,7 +550,7 @@ mod tests {
     #[test]
     fn wrapper_test() {
         assert_eq!(
-            wrapper("'stuff'", &[]),
+            wrapper(&Options::default(), "'stuff'", &[]),
             "
 (function (factory) {
   if (typeof module === 'object' && module.exports) {
@@ -325,7 +575,11 @@ mod tests {
         );
 
         assert_eq!(
-            wrapper("'stuff'", &["one".to_string(), "two\nthree".to_string(),]),
+            wrapper(
+                &Options::default(),
+                "'stuff'",
+                &["one".to_string(), "two\nthree".to_string(),]
+            ),
             "
 (function (factory) {
   if (typeof module === 'object' && module.exports) {
@@ -352,10 +606,119 @@ mod tests {
         );
     }
 
+    #[test]
+    fn esm_wrapper_test() {
+        assert_eq!(
+            wrapper(
+                &Options {
+                    format: OutputFormat::Esm,
+                    ..Options::default()
+                },
+                "'stuff'",
+                &["one".to_string(), "two\nthree".to_string()]
+            ),
+            "
+export default function compiled(helpers, context, guard, iter, helper) {
+  var __escape = helpers.__escape;
+  var value = context;
+  return 'stuff';
+}
+
+export var blocks = compiled.blocks = {
+  one,
+  two
+  three
+};
+"
+        );
+    }
+
+    #[test]
+    fn formats_test() {
+        let blocks = ["one".to_string()];
+        let module = |format| {
+            let options = Options {
+                format,
+                name: Some("a'b".to_string()),
+                ..Options::default()
+            };
+            wrapper(&options, "'stuff'", &blocks)
+        };
+        let compiled = "function compiled(helpers, context, guard, iter, helper) {
+    var __escape = helpers.__escape;
+    var value = context;
+    return 'stuff';
+  }
+
+  compiled.blocks = {
+    one
+  };";
+
+        assert_eq!(
+            module(OutputFormat::CommonJs),
+            "
+function compiled(helpers, context, guard, iter, helper) {
+  var __escape = helpers.__escape;
+  var value = context;
+  return 'stuff';
+}
+
+compiled.blocks = {
+  one
+};
+
+module.exports = compiled;
+"
+        );
+        assert_eq!(
+            module(OutputFormat::Amd),
+            format!(
+                "
+define(function () {{
+  {}
+
+  return compiled;
+}});
+",
+                compiled
+            )
+        );
+        assert_eq!(
+            module(OutputFormat::Iife),
+            format!(
+                "
+(function () {{
+  {}
+
+  var templates = window.templates = window.templates || {{}};
+  templates['a\\'b'] = compiled;
+}})();
+",
+                compiled
+            )
+        );
+        assert_eq!(
+            module(OutputFormat::Function),
+            format!(
+                "
+(function () {{
+  {}
+
+  return compiled;
+}})()
+",
+                compiled
+            )
+        );
+    }
+
     #[test]
     fn if_else_test() {
         assert_eq!(
-            if_else(false, "thing", "'body' +\n' content'", "'alt content'"),
+            if_else(
+                &[("thing".to_string(), "'body' +\n' content'".to_string())],
+                "'alt content'"
+            ),
             "(thing ?
   'body' +
     ' content' :
@@ -363,17 +726,31 @@ mod tests {
         );
 
         assert_eq!(
-            if_else(true, "false_thing", "'body content'", "'alt content'"),
-            "(false_thing ?
-  'alt content' :
-  'body content')"
+            if_else(
+                &[
+                    ("first".to_string(), "'first content'".to_string()),
+                    ("second".to_string(), "'second content'".to_string())
+                ],
+                "'alt content'"
+            ),
+            "(first ?
+  'first content' :
+  second ?
+  'second content' :
+  'alt content')"
         );
     }
 
     #[test]
     fn iter_test() {
         assert_eq!(
-            iter(9, "stuff", "'for ' + \n'each one'", "'if ' + \n'none'"),
+            iter(
+                Target::Es5,
+                9,
+                "stuff",
+                "'for ' + \n'each one'",
+                "'if ' + \n'none'"
+            ),
             "iter(stuff, function each(key9, index, length, value) {
   var key = key9;
   return 'for ' + 
@@ -383,6 +760,14 @@ mod tests {
     'none';
 })"
             .to_string()
+        );
+
+        assert_eq!(
+            iter(Target::Es2015, 9, "stuff", "`for ${value}`", "''"),
+            "iter(stuff, (key9, index, length, value) => {
+  const key = key9;
+  return `for ${value}`;
+}, () => '')"
         )
     }
 
@@ -390,6 +775,7 @@ mod tests {
     fn guard_test() {
         assert_eq!(
             guard(
+                Target::Es5,
                 ["thing", "stuff"]
                     .iter()
                     .map(|&x| x.to_string())
@@ -398,54 +784,150 @@ mod tests {
             "guard(context && context['thing'] && context['thing']['stuff'])"
         );
 
-        assert_eq!(guard(
-            ["items[1]", "prop"].iter().map(|&x| x.to_string()).collect::<Vec<String>>()
+        assert_eq!(guard(Target::Es5, ["items[1]", "prop"].iter().map(|&x| x.to_string()).collect::<Vec<String>>()
         ), "guard(context && context['items'] && context['items'][key1] && context['items'][key1]['prop'])".to_string());
 
         assert_eq!(
-            guard(vec!["foo\\bar".to_string()]),
+            guard(Target::Es5, vec!["foo\\bar".to_string()]),
             "guard(context && context['foo\\\\bar'])".to_string()
         )
     }
 
+    #[test]
+    fn dotted_guard_test() {
+        assert_eq!(
+            guard(
+                Target::Es2015,
+                ["items[1]", "prop-name", "_a$"]
+                    .iter()
+                    .map(|&x| x.to_string())
+                    .collect::<Vec<String>>()
+            ),
+            "guard(context && context.items && context.items[key1] && context.items[key1]['prop-name'] && context.items[key1]['prop-name']._a$)"
+        );
+
+        assert_eq!(
+            guard(Target::Es2015, vec!["1a".to_string(), "it's".to_string()]),
+            "guard(context && context['1a'] && context['1a']['it\\'s'])"
+        );
+    }
+
+    #[test]
+    fn escape_template_test() {
+        assert_eq!(
+            escape_template("a`b\\c${d}$\n\r\u{E000}"),
+            "a\\`b\\\\c\\${d}\\$\\n\\r\\ue000"
+        );
+        assert_eq!(
+            template_literal(&[escape_template("a "), substitution("b")]),
+            "`a ${b}`"
+        );
+    }
+
+    #[test]
+    fn escape_line_breaks_test() {
+        assert_eq!(escape_line_breaks("a\nb\r\nc"), "a\\nb\\r\\nc");
+        assert_eq!(escape_line_breaks("a\\\r\nb\\\\\nc"), "ab\\\\\\nc");
+    }
+
+    #[test]
+    fn escape_markers_test() {
+        assert_eq!(escape_markers("a\u{E000}b\\\u{E001}"), "a\\ue000b\\ue001");
+        assert_eq!(escape_markers("\\\\\u{E000}"), "\\\\\\ue000");
+    }
+
+    #[test]
+    fn first_last_negated() {
+        let negated = |name: &str| {
+            expression(
+                Target::Es5,
+                Expression::NegativeExpression {
+                    expr: Box::new(Expression::PathExpression {
+                        path: vec![name.to_string()],
+                    }),
+                },
+            )
+        };
+
+        assert_eq!(negated("@first"), "!(index === 0)");
+        assert_eq!(negated("@last"), "!(index === length - 1)");
+    }
+
     #[test]
     fn expression_test() {
         assert_eq!(
-            expression(Expression::StringLiteral {
-                value: "stuff\\n \\\"about\\\" things".to_string()
-            }),
+            expression(
+                Target::Es5,
+                Expression::StringLiteral {
+                    value: "stuff\\n \\\"about\\\" things".to_string()
+                }
+            ),
             "\"stuff\\n \\\"about\\\" things\"".to_string()
         );
 
         assert_eq!(
-            expression(Expression::PathExpression {
-                path: vec!["thing".to_string()],
-            }),
+            expression(
+                Target::Es5,
+                Expression::PathExpression {
+                    path: vec!["thing".to_string()],
+                }
+            ),
             "guard(context && context['thing'])".to_string()
         );
 
         assert_eq!(
-            expression(Expression::PathExpression {
-                path: vec!["@root".to_string()],
-            }),
+            expression(
+                Target::Es5,
+                Expression::PathExpression {
+                    path: vec!["@root".to_string()],
+                }
+            ),
             "context".to_string()
         );
 
         assert_eq!(
-            expression(Expression::PathExpression {
-                path: vec!["@first".to_string()],
-            }),
-            "index === 0".to_string()
+            expression(
+                Target::Es5,
+                Expression::PathExpression {
+                    path: vec!["@first".to_string()],
+                }
+            ),
+            "(index === 0)".to_string()
         );
 
         assert_eq!(
-            expression(Expression::PathExpression {
-                path: vec!["@last".to_string()],
-            }),
-            "index === length - 1".to_string()
+            expression(
+                Target::Es5,
+                Expression::LogicalExpression {
+                    operator: Logical::Or,
+                    left: Box::new(Expression::ComparisonExpression {
+                        operator: Comparison::LessEqual,
+                        left: Box::new(Expression::PathExpression {
+                            path: vec!["a".to_string()],
+                        }),
+                        right: Box::new(Expression::StringLiteral {
+                            value: "b".to_string()
+                        }),
+                    }),
+                    right: Box::new(Expression::PathExpression {
+                        path: vec!["@first".to_string()],
+                    }),
+                }
+            ),
+            "((guard(context && context['a']) <= \"b\") || (index === 0))".to_string()
+        );
+
+        assert_eq!(
+            expression(
+                Target::Es5,
+                Expression::PathExpression {
+                    path: vec!["@last".to_string()],
+                }
+            ),
+            "(index === length - 1)".to_string()
         );
 
-        assert_eq!(expression(Expression::HelperExpression {
+        assert_eq!(expression(Target::Es5, Expression::HelperExpression {
             helper_name: "localeToHTML".to_string(),
             args: vec![
                 Expression::PathExpression { path: vec!["userLang".to_string()] },
@@ -2,6 +2,9 @@
 pub enum Token {
     // stuff that's just text in the template
     Text(String),
+    // a note left in the template, which produces no output
+    // the ` note ` in `{{{! note }}}` or `<!-- # note -->`
+    Comment(String),
 
     // the property name to the value
     // the `person.name` in `{{person.name}}`
@@ -14,16 +17,31 @@ pub enum Token {
     BlockOpen,  // {{{, <!--
     BlockClose, // }}}, -->
 
-    If,   // if, IF
-    Else, // else, ELSE
-    Iter, // each, BEGIN
-    End,  // end, END, ENDIF
+    If,     // if, IF
+    Else,   // else, ELSE
+    ElseIf, // else if, ELSE IF
+    Iter,   // each, BEGIN
+    End,    // end, END, ENDIF
+
+    Import,  // import
+    Extends, // extends
+    Block,   // block
+    Super,   // super
 
     Bang,       // !
     LeftParen,  // (
     RightParen, // )
     Comma,      // ,
 
+    Equal,        // ==
+    NotEqual,     // !=
+    Less,         // <
+    LessEqual,    // <=
+    Greater,      // >
+    GreaterEqual, // >=
+    And,          // &&
+    Or,           // ||
+
     RawOpen,      // {{
     RawClose,     // }}
     EscapedOpen,  // {